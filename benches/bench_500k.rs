@@ -60,7 +60,7 @@ fn batch_edit(c: &mut Criterion) {
 }
 
 fn write_to_fzf(c: &mut Criterion) {
-    let mut app = App::new(AppArgs {
+    let app = App::new(AppArgs {
         todo_path: PathBuf::from("../fuckc3-10000-todo"),
         ..Default::default()
     });
@@ -69,7 +69,7 @@ fn write_to_fzf(c: &mut Criterion) {
     c.bench_function("write 10k todos to fzf", |b| {
         b.iter(|| {
             let selected = Arc::clone(&selected);
-            fzf_search::write_todos(black_box(&mut app), &mut file, selected)
+            fzf_search::write_todos(black_box(&app), &mut file, selected)
         })
     });
 }
@@ -1,36 +1,100 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 // imports {{{
 use crate::Args;
-use c3::todo_app::{App, Restriction, Todo, TodoList};
-use c3::{DisplayArgs, DoOnSelected, TodoDisplay};
-use clap::Parser;
+use c3::todo_app::{convert::Format, filter, restriction, App, Restriction, Todo, TodoList, TreePath};
+use c3::fileio::ExternalChanges;
+use c3::{date, DisplayArgs, DoOnSelected, TodoDisplay};
+use clap::{Parser, Subcommand};
 use clap::{Command, CommandFactory};
 use clap_complete::Shell;
 use clap_complete::{generate, Generator};
-use std::io;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::process;
-use std::rc::Rc;
+use std::time::Duration;
 // }}}
 
+/// Todos are targeted by their id, or by a tree path of the indices `list` shows (`2/0/3`) or
+/// the starts of the messages (`Work/Report`)
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    /// Add a todo, printing its id
+    Add {
+        message: String,
+        /// Todo to add the new one to the sub list of
+        #[arg(long, value_name = "TARGET")]
+        to: Option<String>,
+        /// Add it to the top of the list
+        #[arg(short = 'A', long)]
+        prepend: bool,
+        #[arg(short, long)]
+        priority: Option<u8>,
+    },
+    /// Mark a todo done
+    Done { target: String },
+    /// Mark a todo undone
+    Undone { target: String },
+    /// Remove a todo, with its sub todos and note
+    Rm { target: String },
+    /// Change the message of a todo
+    Edit { target: String, message: String },
+    /// Move a todo to the sub list of another one, or to the root list with /
+    Move { target: String, destination: String },
+    /// Set the priority of a todo
+    Prio { target: String, priority: u8 },
+    /// Schedule a todo every given days (7), weeks (2w) or by a rule (weekdays, month(1))
+    Schedule { target: String, schedule: String },
+    /// Make a todo a reminder for a date
+    Remind { target: String, date: String },
+    /// Set the note of a todo, read from stdin if not given
+    Note { target: String, note: Option<String> },
+    /// List todos of the root list, or of the sub list of a todo, with their tree paths
+    List {
+        target: Option<String>,
+        /// Show ids instead of tree paths
+        #[arg(long)]
+        ids: bool,
+    },
+    /// Print the whole tree, or the tree under a todo
+    Tree {
+        target: Option<String>,
+        /// Minimal tree with no tree graphics
+        #[arg(short = 'M', long)]
+        minimal: bool,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
     /// Search and select todo. Used for batch change operations
     #[arg(short = 'S', long)]
     search_and_select: Vec<String>,
 
+    /// Filter todos with a query like `prio<=2 and not done and sched:daily`. Used like -S, and
+    /// together with it
+    #[arg(long, value_name = "QUERY")]
+    filter: Option<String>,
+
+    /// Tree path (like 2/0 or Work/Report) or id of the todo whose sub list -a, -A, -S and
+    /// --list work in, instead of the root list
+    #[arg(long, value_name = "PATH")]
+    at: Option<String>,
+
     #[arg(long)]
     do_on_selected: Option<DoOnSelected>,
 
     #[arg(short = 'b', long, default_value_t = false)]
     batch_edit: bool,
 
-    /// A todo message to append
+    /// A todo message to append. Same as the add command
     #[arg(short = 'a', long)]
     append_todo: Vec<String>,
 
-    /// A todo message to prepend
+    /// A todo message to prepend. Same as add --prepend
     #[arg(short = 'A', long)]
     prepend_todo: Vec<String>,
 
@@ -42,6 +106,17 @@ pub struct CliArgs {
     #[arg(short = 'o', long)]
     output_file: Option<PathBuf>,
 
+    /// Print the todos in another format (non interactive)
+    #[arg(long, value_name = "FORMAT")]
+    export: Option<Format>,
+
+    /// Add the todos of a file in another format, told by its extension or --import-format
+    #[arg(long, value_name = "FILE")]
+    import: Option<PathBuf>,
+
+    #[arg(long, value_name = "FORMAT")]
+    import_format: Option<Format>,
+
     #[arg(short = 'p', long, default_value_t = false)]
     print_path: bool,
 
@@ -53,10 +128,39 @@ pub struct CliArgs {
     #[arg(short = 'l', long)]
     list: bool,
 
+    /// List +project and @context tags of the tree with how many todos use them (non interactive)
+    #[arg(long)]
+    tags: bool,
+
+    /// Report lines that aren't valid todos in the todo file and its notes, exiting with 1 if
+    /// any (non interactive)
+    #[arg(long)]
+    check: bool,
+
+    /// List the last N operations written to the todos (20 if not given, 0 for all), with
+    /// their numbers (non interactive)
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "20"
+    )]
+    history: Option<usize>,
+
+    /// Revert the operation with this number from --history (non interactive)
+    #[arg(long, value_name = "NUMBER")]
+    revert: Option<usize>,
+
     /// Write contents of todo file in the stdout (non interactive)
     #[arg(short = 's', long)]
     stdout: bool,
 
+    /// Wait up to this many seconds for another running c3 to release the todos, instead of
+    /// failing to write
+    #[arg(long, value_name = "SECONDS")]
+    wait_lock: Option<u64>,
+
     /// Generate completion for a certain shell
     #[arg(short = 'c', long)]
     completion: Option<Shell>,
@@ -65,26 +169,126 @@ pub struct CliArgs {
 pub struct NotCli;
 #[inline]
 pub fn run(app: &mut App, args: CliArgs) -> Result<(), NotCli> {
-    if !args.search_and_select.is_empty() {
+    if args.check {
+        let diagnostics = app.diagnostics();
+        for (path, line) in &diagnostics {
+            println!("{}:{}:{}", path.display(), line.number, line.error);
+        }
+        if !diagnostics.is_empty() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(count) = args.history {
+        match app.journal() {
+            Ok(operations) => {
+                let skipped = match count {
+                    0 => 0,
+                    count => operations.len().saturating_sub(count),
+                };
+                for operation in operations.into_iter().skip(skipped) {
+                    println!("{operation}");
+                }
+            }
+            Err(error) => {
+                eprintln!("Failed to read the journal: {error}");
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(number) = args.revert {
+        let result = match args.wait_lock {
+            Some(seconds) => app.lock(Some(Duration::from_secs(seconds))),
+            None => Ok(()),
+        }
+        .and_then(|_| app.revert(number));
+        if let Err(error) = result {
+            eprintln!("Failed to revert operation {number}: {error}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(command) = args.command {
+        run_command(app, command);
+        write(app, args.wait_lock);
+        return Ok(());
+    }
+    let adding =
+        !args.append_todo.is_empty() || !args.prepend_todo.is_empty() || args.import.is_some();
+    if let Some(target) = args.at.as_deref() {
+        let path = list_path(app, Some(target));
+        // Adding to a todo gives it a sub list, the others need one to be there
+        if (!adding && app.list_of_path(&path).is_none()) || !app.select_list(&path) {
+            fail(&format!("\"{target}\" doesn't have sub todos"));
+        }
+    }
+    if let Some(path) = args.import.as_deref() {
+        let format = args
+            .import_format
+            .or_else(|| Format::of_path(path))
+            .unwrap_or_else(|| fail(&format!("Can't tell the format of \"{}\", give it with --import-format", path.display())));
+        if let Err(error) = app.import(path, format) {
+            fail(&format!("Failed to import \"{}\": {error}", path.display()));
+        }
+        write(app, args.wait_lock);
+        return Ok(());
+    }
+    if let Some(format) = args.export {
+        match app.export(format) {
+            Ok(output) => println!("{output}"),
+            Err(error) => fail(&format!("Failed to export: {error}")),
+        }
+        return Ok(());
+    }
+    if adding {
+        for message in args.append_todo {
+            app.append(message);
+        }
+        for message in args.prepend_todo {
+            app.prepend(message);
+        }
+        write(app, args.wait_lock);
+        return Ok(());
+    }
+    if !args.search_and_select.is_empty() || args.filter.is_some() {
+        let searched = !args.search_and_select.is_empty();
         for query in args.search_and_select {
-            app.set_restriction(Rc::new(move |todo| todo.matches(query.as_str())))
+            app.set_restriction(restriction::matches(query))
+        }
+        if let Some(query) = args.filter {
+            let filter = filter::parse(&query).unwrap_or_else(|error| fail(&format!("Invalid filter: {error}")));
+            if searched {
+                app.set_restriction_with_last(filter, None);
+            } else {
+                app.set_restriction(filter);
+            }
         }
         if app.is_todos_empty() {
             process::exit(1);
         }
         let restriction = app.get_restriction().clone();
         if let Some(do_on_selected) = args.do_on_selected {
+            app.current_list_mut().changed = true;
             match do_on_selected {
-                DoOnSelected::Delete => app
-                    .current_list_mut()
-                    .todos
-                    .retain(|todo| !restriction(todo)),
+                DoOnSelected::Delete => {
+                    // Removed like in the tui, so their notes and sub lists get removed too
+                    let ids: Vec<String> = app
+                        .current_list()
+                        .todos
+                        .iter()
+                        .filter(|todo| restriction(todo))
+                        .map(|todo| todo.id().to_string())
+                        .collect();
+                    app.remove_by_ids(&ids);
+                }
                 DoOnSelected::Done => {
                     for todo in app.current_list_mut().todos_mut(&restriction) {
                         todo.set_done(true);
                     }
                 }
             }
+            write(app, args.wait_lock);
         } else {
             print_todos(app);
             return Ok(());
@@ -92,9 +296,7 @@ pub fn run(app: &mut App, args: CliArgs) -> Result<(), NotCli> {
     }
     if args.batch_edit {
         app.batch_editor_messages();
-    }
-    if app.is_changed() {
-        app.write().expect("Failed to write file.");
+        write(app, args.wait_lock);
     }
     if args.print_path {
         println!("{}", app.args.todo_path.to_str().unwrap());
@@ -113,12 +315,18 @@ pub fn run(app: &mut App, args: CliArgs) -> Result<(), NotCli> {
         app.todo_list.write_to_stdout().expect("Failed to write the main todolist on stdout");
         return Ok(());
     }
+    if args.tags {
+        for (tag, count) in app.tag_counts() {
+            println!("{tag} {count}");
+        }
+        return Ok(());
+    }
     if args.minimal_tree || args.list {
         if app.args.no_tree {
             print_todos(app);
         } else {
             let mut print_todo = PrintTodoTree::new(args.minimal_tree);
-            print_todo.print_list(&app.todo_list, &app.args.display_args, app.get_restriction())
+            print_todo.print_list(app.current_list(), &app.args.display_args, app.get_restriction())
         }
         return Ok(());
     }
@@ -129,6 +337,208 @@ pub fn run(app: &mut App, args: CliArgs) -> Result<(), NotCli> {
     Err(NotCli)
 }
 
+fn write(app: &mut App, wait_lock: Option<u64>) {
+    if !app.is_changed() {
+        return;
+    }
+    let mut result = match wait_lock {
+        Some(seconds) => app.lock(Some(Duration::from_secs(seconds))),
+        None => Ok(()),
+    }
+    .and_then(|_| app.write());
+    // Another c3 or calcurse wrote to the tree while this one was running
+    if result.as_ref().is_err_and(|error| ExternalChanges::of(error).is_some()) {
+        result = app.merge_external_changes().and_then(|_| app.write());
+    }
+    if let Err(error) = result {
+        eprintln!("Failed to write file: {error}");
+        process::exit(1);
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
+
+fn target_path(app: &App, target: &str) -> Vec<usize> {
+    app.path_of_target(target)
+        .unwrap_or_else(|error| fail(&format!("Can't find \"{target}\": {error}")))
+}
+
+/// Tree path of a list: the root list for / (or no target), or the sub list of a todo
+fn list_path(app: &App, target: Option<&str>) -> Vec<usize> {
+    app.path_of_list(target.unwrap_or_default())
+        .unwrap_or_else(|error| fail(&format!("Can't find \"{}\": {error}", target.unwrap_or_default())))
+}
+
+fn select(app: &mut App, target: &str) {
+    let path = target_path(app, target);
+    app.select_path(&path);
+}
+
+fn run_command(app: &mut App, command: CliCommand) {
+    match command {
+        CliCommand::Add {
+            message,
+            to,
+            prepend,
+            priority,
+        } => {
+            let path = list_path(app, to.as_deref());
+            if !app.select_list(&path) {
+                fail("Todos can't have sub lists in no tree mode, or if they have a note");
+            }
+            if prepend {
+                app.prepend(message);
+            } else {
+                app.append(message);
+            }
+            if let Some(priority) = priority {
+                app.set_current_priority(priority);
+            }
+            if let Some(todo) = app.todo() {
+                println!("{}", todo.id());
+            }
+        }
+        CliCommand::Done { target } => {
+            select(app, &target);
+            if app.todo().is_some_and(|todo| !todo.done()) {
+                app.toggle_current_done();
+            }
+        }
+        CliCommand::Undone { target } => {
+            select(app, &target);
+            if app.todo().is_some_and(Todo::done) {
+                app.toggle_current_done();
+            }
+        }
+        CliCommand::Rm { target } => {
+            select(app, &target);
+            app.remove_todo();
+        }
+        CliCommand::Edit { target, message } => {
+            if message.is_empty() {
+                fail("Todo message can't be empty");
+            }
+            select(app, &target);
+            app.save_undo_point();
+            if let Some(todo) = app.todo_mut() {
+                todo.message = message;
+            }
+            app.reorder_current();
+        }
+        CliCommand::Move {
+            target,
+            destination,
+        } => {
+            let path = target_path(app, &target);
+            let destination_path = list_path(app, Some(&destination));
+            if destination_path.starts_with(&path) {
+                fail("A todo can't be moved into itself");
+            }
+            let destination_id = app
+                .todo_at_path(&destination_path)
+                .map(|todo| todo.id().to_string());
+            app.select_path(&path);
+            app.remove_todo();
+            let Some(todo) = app.removed_todos.pop() else {
+                return;
+            };
+            // Removing the todo might have changed the indices of the destination
+            let destination_path = destination_id
+                .and_then(|id| app.tree_path_of_id(&id))
+                .unwrap_or_default();
+            if !app.select_list(&destination_path) {
+                fail("Todos can't have sub lists in no tree mode, or if they have a note");
+            }
+            app.paste_todo(todo);
+        }
+        CliCommand::Prio { target, priority } => {
+            select(app, &target);
+            app.set_current_priority(priority);
+        }
+        CliCommand::Schedule { target, schedule } => {
+            select(app, &target);
+            if !app.schedule_current(&schedule) {
+                fail(&format!("\"{schedule}\" is not a day count, week count or schedule rule"));
+            }
+        }
+        CliCommand::Remind { target, date } => {
+            let date = date::parse_user_input(&date)
+                .unwrap_or_else(|_| fail(&format!("\"{date}\" is not a date like 2024-05-03")));
+            select(app, &target);
+            app.remind_current(date);
+        }
+        CliCommand::Note { target, note } => {
+            let note = note.unwrap_or_else(|| {
+                let mut note = String::new();
+                if let Err(error) = io::stdin().read_to_string(&mut note) {
+                    fail(&format!("Failed to read the note: {error}"));
+                }
+                note
+            });
+            select(app, &target);
+            let has_todos = app
+                .todo()
+                .and_then(|todo| todo.dependency.as_ref())
+                .and_then(|dep| dep.todo_list())
+                .is_some_and(|list| !list.todos.is_empty());
+            if has_todos {
+                fail("The todo has sub todos, it can't have a note too");
+            }
+            app.save_undo_point();
+            let result = app.todo_mut().map(|todo| {
+                // So the file of the old note or empty list gets removed
+                todo.remove_dependency();
+                todo.set_note(note)
+            });
+            if let Some(Err(error)) = result {
+                fail(&format!("Failed to set the note: {error}"));
+            }
+        }
+        CliCommand::List { target, ids } => {
+            let path = list_path(app, target.as_deref());
+            let Some(list) = app.list_of_path(&path) else {
+                return;
+            };
+            let restriction = app.get_restriction();
+            for (index, todo) in list.todos(restriction).enumerate() {
+                let display = todo.display_with_args(&app.args.display_args);
+                if ids {
+                    println!("{} {display}", todo.id());
+                } else {
+                    let mut todo_path = path.clone();
+                    todo_path.push(list.true_position_in_list(index, restriction));
+                    println!("{} {display}", TreePath::from(&todo_path[..]));
+                }
+            }
+            // The ids are meant to be used by the next commands, they have to be in the file
+            if ids && app.select_list(&path) {
+                app.current_list_mut().keep_ids();
+            }
+        }
+        CliCommand::Tree { target, minimal } => {
+            let mut print_todo = PrintTodoTree::new(minimal);
+            let restriction = app.get_restriction();
+            match target.as_deref() {
+                None | Some("/") => print_todo.print_list(&app.todo_list, &app.args.display_args, restriction),
+                Some(target) => {
+                    let path = target_path(app, target);
+                    if let Some(todo) = app.todo_at_path(&path) {
+                        print_todo.print_todo(todo, &app.args.display_args);
+                        if let Some(list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
+                            print_todo.tree_child().print_list(list, &app.args.display_args, restriction);
+                        } else if let Some(note) = todo.dependency.as_ref().and_then(|dep| dep.note()) {
+                            print_todo.print_note(note);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
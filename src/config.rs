@@ -0,0 +1,460 @@
+//! The config file, a TOML file at `$XDG_CONFIG_HOME/c3/config.toml` (or
+//! `~/.config/c3/config.toml`) that gives the defaults of the command line options, like:
+//!
+//! ```toml
+//! [display]
+//! done-string = "✓ "
+//!
+//! [tui]
+//! no-glow = true
+//! ```
+//!
+//! Options given on the command line override it, flags with a value like `--no-glow=false`.
+use clap::{value_parser, ArgAction, ArgMatches, Command};
+use home::home_dir;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+use crate::fileio::invalid_data;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Flag,
+    Text,
+    Number,
+    /// Text that can start with `~/`
+    Path,
+}
+
+/// The settings of each section, named like their options with `-` written as `_`
+const SECTIONS: [(&str, &[(&str, Kind)]); 4] = [
+    (
+        "app",
+        &[
+            ("no_tree", Kind::Flag),
+            ("todo_path", Kind::Path),
+            ("sort_method", Kind::Text),
+        ],
+    ),
+    (
+        "display",
+        &[
+            ("show_done", Kind::Flag),
+            ("done_string", Kind::Text),
+            ("undone_string", Kind::Text),
+        ],
+    ),
+    (
+        "tui",
+        &[
+            ("minimal_render", Kind::Flag),
+            ("highlight_string", Kind::Text),
+            ("enable_module", Kind::Flag),
+            ("outline", Kind::Flag),
+            ("no_glow", Kind::Flag),
+            ("note_viewer", Kind::Text),
+            ("theme", Kind::Text),
+        ],
+    ),
+    (
+        "cli",
+        &[("minimal_tree", Kind::Flag), ("wait_lock", Kind::Number)],
+    ),
+];
+
+#[inline(always)]
+fn key_of(id: &str) -> String {
+    id.replace('_', "-")
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// The default values of options, by their ids
+    defaults: Vec<(&'static str, String)>,
+    /// Key bindings of the `[keys]` section, by the names of their actions
+    keys: Vec<(String, Vec<String>)>,
+    /// Styles of the `[theme]` section, by their names
+    theme: Vec<(String, String)>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/c3/config.toml`, or `~/.config/c3/config.toml`
+    pub fn default_path() -> PathBuf {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .unwrap_or_else(|| home_dir().unwrap_or_default().join(".config"));
+        config_dir.join("c3").join("config.toml")
+    }
+
+    /// The path given with `--config` in the arguments, read before they're parsed as the
+    /// config changes how they're parsed
+    pub fn path_in_args<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+        let mut args = args.into_iter();
+        let mut path = None;
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--") => break,
+                Some("--config") => path = args.next().map(PathBuf::from),
+                Some(arg) => {
+                    if let Some(value) = arg.strip_prefix("--config=") {
+                        path = Some(PathBuf::from(value));
+                    }
+                }
+                None => {}
+            }
+        }
+        path
+    }
+
+    /// Reads the config at the path, or the one at the default path if there's any
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let (path, content) = match path {
+            Some(path) => (path.to_path_buf(), fs::read_to_string(path)),
+            None => {
+                let path = Self::default_path();
+                let content = fs::read_to_string(&path);
+                (path, content)
+            }
+        };
+        match content {
+            Ok(content) => Self::parse(&content)
+                .map_err(|error| invalid_data(format!("{}: {error}", path.display()))),
+            Err(error)
+                if error.kind() == io::ErrorKind::NotFound && path == Self::default_path() =>
+            {
+                Ok(Self::default())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn parse(input: &str) -> io::Result<Self> {
+        let table: Table = input
+            .parse()
+            .map_err(|error: toml::de::Error| invalid_data(error.message().to_string()))?;
+        let mut defaults = vec![];
+        let mut keys = vec![];
+        let mut theme = vec![];
+        for (section_name, section) in &table {
+            if section_name == "keys" {
+                keys = read_keys(section)?;
+                continue;
+            }
+            if section_name == "theme" {
+                theme = read_theme(section)?;
+                continue;
+            }
+            let settings = SECTIONS
+                .iter()
+                .find(|(name, _)| name == section_name)
+                .map(|(_, settings)| settings)
+                .ok_or_else(|| invalid_data(format!("unknown section [{section_name}]")))?;
+            let Value::Table(section) = section else {
+                return Err(invalid_data(format!("{section_name} should be a section")));
+            };
+            for (key, value) in section {
+                let &(id, kind) = settings
+                    .iter()
+                    .find(|(id, _)| key_of(id) == *key)
+                    .ok_or_else(|| invalid_data(format!("unknown setting {section_name}.{key}")))?;
+                let default = match (kind, value) {
+                    (Kind::Flag, Value::Boolean(value)) => value.to_string(),
+                    (Kind::Number, Value::Integer(value)) if *value >= 0 => value.to_string(),
+                    (Kind::Text, Value::String(value)) => value.clone(),
+                    (Kind::Path, Value::String(value)) => match value.strip_prefix("~/") {
+                        Some(rest) => home_dir()
+                            .unwrap_or_default()
+                            .join(rest)
+                            .to_string_lossy()
+                            .into_owned(),
+                        None => value.clone(),
+                    },
+                    _ => {
+                        let expected = match kind {
+                            Kind::Flag => "true or false",
+                            Kind::Number => "a number",
+                            Kind::Text | Kind::Path => "a string",
+                        };
+                        return Err(invalid_data(format!(
+                            "{section_name}.{key} should be {expected}"
+                        )));
+                    }
+                };
+                defaults.push((id, default));
+            }
+        }
+        Ok(Self {
+            defaults,
+            keys,
+            theme,
+        })
+    }
+
+    /// Key bindings of the `[keys]` section, checked by the TUI that knows the actions
+    #[inline]
+    pub fn keys(&self) -> &[(String, Vec<String>)] {
+        &self.keys
+    }
+
+    /// Styles of the `[theme]` section, checked by the TUI that knows the styles
+    #[inline]
+    pub fn theme(&self) -> &[(String, String)] {
+        &self.theme
+    }
+
+    /// The command with the defaults of its options set to the ones of the config. Flags take
+    /// an optional value, so one turned on in the config is turned off with `--flag=false`
+    pub fn apply(&self, mut command: Command) -> Command {
+        let flags = SECTIONS
+            .iter()
+            .flat_map(|(_, settings)| settings.iter())
+            .filter(|(_, kind)| *kind == Kind::Flag);
+        for &(id, _) in flags {
+            if command.get_arguments().any(|arg| arg.get_id() == id) {
+                command = command.mut_arg(id, |arg| {
+                    arg.action(ArgAction::Set)
+                        .value_parser(value_parser!(bool))
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("true")
+                        .default_value("false")
+                });
+            }
+        }
+        for (id, default) in &self.defaults {
+            if command.get_arguments().any(|arg| arg.get_id() == id) {
+                command = command.mut_arg(*id, |arg| arg.default_value(default.clone()));
+            }
+        }
+        command
+    }
+}
+
+/// The bindings of the `[keys]` section, where an action has a key or an array of them
+fn read_keys(section: &Value) -> io::Result<Vec<(String, Vec<String>)>> {
+    let Value::Table(section) = section else {
+        return Err(invalid_data(String::from("keys should be a section")));
+    };
+    let mut keys = vec![];
+    for (action, value) in section {
+        let bindings = match value {
+            Value::String(binding) => Some(vec![binding.clone()]),
+            Value::Array(bindings) => bindings
+                .iter()
+                .map(|binding| binding.as_str().map(str::to_string))
+                .collect(),
+            _ => None,
+        };
+        let bindings = bindings.ok_or_else(|| {
+            invalid_data(format!("keys.{action} should be a key or an array of keys"))
+        })?;
+        keys.push((action.clone(), bindings));
+    }
+    Ok(keys)
+}
+
+/// The styles of the `[theme]` section, which are all strings
+fn read_theme(section: &Value) -> io::Result<Vec<(String, String)>> {
+    let Value::Table(section) = section else {
+        return Err(invalid_data(String::from("theme should be a section")));
+    };
+    section
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(style) => Ok((name.clone(), style.clone())),
+            _ => Err(invalid_data(format!("theme.{name} should be a string"))),
+        })
+        .collect()
+}
+
+/// The config the parsed options make, with the command line and the config file merged
+pub fn effective(matches: &ArgMatches) -> String {
+    let mut sections = vec![];
+    for (section_name, settings) in SECTIONS {
+        let mut lines = vec![format!("[{section_name}]")];
+        for &(id, kind) in settings {
+            let Some(raw) = matches
+                .try_get_raw(id)
+                .ok()
+                .flatten()
+                .and_then(|mut values| values.next())
+            else {
+                continue;
+            };
+            let raw = raw.to_string_lossy();
+            let value = match kind {
+                Kind::Flag => Value::Boolean(raw == "true"),
+                Kind::Number => match raw.parse() {
+                    Ok(number) => Value::Integer(number),
+                    Err(_) => continue,
+                },
+                Kind::Text | Kind::Path => Value::String(raw.into_owned()),
+            };
+            lines.push(format!("{} = {value}", key_of(id)));
+        }
+        if lines.len() > 1 {
+            sections.push(lines.join("\n") + "\n");
+        }
+    }
+    sections.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("c3")
+            .arg(
+                Arg::new("done_string")
+                    .long("done-string")
+                    .default_value("[x] "),
+            )
+            .arg(
+                Arg::new("undone_string")
+                    .long("undone-string")
+                    .default_value("[ ] "),
+            )
+            .arg(
+                Arg::new("no_glow")
+                    .short('G')
+                    .long("no-glow")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("outline")
+                    .short('o')
+                    .long("outline")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("wait_lock")
+                    .long("wait-lock")
+                    .value_parser(value_parser!(u64)),
+            )
+    }
+
+    #[test]
+    fn test_config() -> io::Result<()> {
+        let config = Config::parse(
+            "\
+# Comments are fine
+[display]
+done-string = \"+ \"
+undone-string = \"- \"
+
+[tui]
+no-glow = true
+
+[cli]
+wait-lock = 5
+
+[keys]
+go_top = \"gg\"
+delete = [\"dd\", \"<Del>\"]
+
+[theme]
+done = \"dim\"
+",
+        )?;
+        assert_eq!(
+            config.keys(),
+            [
+                (String::from("delete"), vec![String::from("dd"), String::from("<Del>")]),
+                (String::from("go_top"), vec![String::from("gg")]),
+            ]
+        );
+        assert_eq!(
+            config.theme(),
+            [(String::from("done"), String::from("dim"))]
+        );
+        let matches = config
+            .apply(command())
+            .get_matches_from(["c3", "--done-string", "done "]);
+        assert_eq!(
+            matches.get_one::<String>("done_string").map(String::as_str),
+            Some("done ")
+        );
+        assert_eq!(
+            matches
+                .get_one::<String>("undone_string")
+                .map(String::as_str),
+            Some("- ")
+        );
+        assert!(matches.get_flag("no_glow"));
+        assert_eq!(matches.get_one::<u64>("wait_lock"), Some(&5));
+        assert_eq!(
+            effective(&matches),
+            "\
+[display]
+done-string = \"done \"
+undone-string = \"- \"
+
+[tui]
+outline = false
+no-glow = true
+
+[cli]
+wait-lock = 5
+"
+        );
+
+        let matches = Config::default().apply(command()).get_matches_from(["c3"]);
+        assert!(!matches.get_flag("no_glow"));
+        assert_eq!(matches.get_one::<u64>("wait_lock"), None);
+
+        // A flag the config turns on can be turned off
+        let flag = |args: &[&str]| {
+            let matches = config.apply(command()).get_matches_from(args);
+            (matches.get_flag("no_glow"), matches.get_flag("outline"))
+        };
+        assert_eq!(flag(&["c3", "--no-glow=false"]), (false, false));
+        assert_eq!(flag(&["c3", "-G=false", "--outline"]), (false, true));
+        assert_eq!(flag(&["c3", "-G", "-o"]), (true, true));
+        assert_eq!(flag(&["c3", "-oG"]), (true, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let error = |input: &str| Config::parse(input).unwrap_err().to_string();
+        assert_eq!(error("[colors]\nred = 1"), "unknown section [colors]");
+        assert_eq!(error("[tui]\nglow = true"), "unknown setting tui.glow");
+        assert_eq!(
+            error("[tui]\nno-glow = \"yes\""),
+            "tui.no-glow should be true or false"
+        );
+        assert_eq!(
+            error("[cli]\nwait-lock = -1"),
+            "cli.wait-lock should be a number"
+        );
+        assert_eq!(error("tui = 1"), "tui should be a section");
+        assert_eq!(
+            error("[keys]\nquit = 1"),
+            "keys.quit should be a key or an array of keys"
+        );
+        assert_eq!(error("[theme]\ndone = 1"), "theme.done should be a string");
+        assert!(Config::parse("[tui").is_err());
+    }
+
+    #[test]
+    fn test_path_in_args() {
+        let args = |args: &[&str]| Config::path_in_args(args.iter().map(OsString::from));
+        assert_eq!(
+            args(&["c3", "--config", "a.toml"]),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            args(&["c3", "--config=b.toml", "-l"]),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(args(&["c3", "--", "--config", "a.toml"]), None);
+        assert_eq!(args(&["c3", "-l"]), None);
+    }
+}
@@ -1,10 +1,14 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 // imports {{{
 use home::home_dir;
-use std::fs::{remove_dir, remove_file, File};
-use std::io::{self, prelude::*, Write};
+use sha1::{Digest, Sha1};
+use std::cell::Cell;
+use std::fs::{self, remove_dir, remove_file, File};
+use std::io::{self, prelude::*, BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error, fmt, process, thread};
 
 use std::env;
 use std::process::Command;
@@ -72,3 +76,723 @@ pub fn file_content(path: &Path) -> io::Result<String> {
     file.read_to_string(&mut content)?;
     Ok(content)
 }
+
+/// An error of data that was read, like a file in the wrong format
+#[inline(always)]
+pub fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A hidden file next to the path, like the temporary file it's staged in
+#[inline(always)]
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.c3-{}-{count}.{extension}", process::id()))
+}
+
+/// The file a symlink points to, so that writing the path replaces the target instead of the
+/// link. Other paths are as they are
+#[inline(always)]
+fn resolve_symlink(path: &Path) -> PathBuf {
+    let is_symlink = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink());
+    if !is_symlink {
+        return path.to_path_buf();
+    }
+    fs::canonicalize(path)
+        .or_else(|_| {
+            // A link to a file that isn't there yet
+            let directory = path.parent().unwrap_or(Path::new(""));
+            fs::read_link(path).map(|target| directory.join(target))
+        })
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[inline(always)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    // Directories can't be opened as files on windows, renames are already durable there
+    if cfg!(unix) {
+        let directory = if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            directory
+        };
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A set of files that are written together. Each file is written and synced to a temporary
+/// file next to it, and the real files are only replaced (renamed over) in `commit`, after
+/// all of them are staged. If staging fails nothing is touched, and temporary files are cleaned
+/// up when the transaction is dropped. A symlink is written through to its target, and a
+/// replaced file keeps its permissions.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    staged: Vec<(PathBuf, PathBuf)>,
+    removed: Vec<PathBuf>,
+}
+
+/// A file the commit replaced or removed, with a copy of what it was until the commit is done
+#[derive(Debug)]
+struct Replaced {
+    path: PathBuf,
+    // None for a file that wasn't there before
+    backup: Option<PathBuf>,
+}
+
+impl Replaced {
+    /// Puts the file back like it was before the commit
+    #[inline]
+    fn restore(&self) -> io::Result<()> {
+        match &self.backup {
+            Some(backup) => fs::rename(backup, &self.path),
+            None => remove_file(&self.path),
+        }
+    }
+}
+
+/// Keeps the file at the path as a backup without moving it, so it's never missing. The backup
+/// is a hard link if the file system has them
+#[inline(always)]
+fn back_up(path: &Path) -> io::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let backup = sibling_path(path, "bak");
+    if fs::hard_link(path, &backup).is_err() {
+        fs::copy(path, &backup)?;
+    }
+    Ok(Some(backup))
+}
+
+/// Commit failed, and some of the files that were already replaced couldn't be put back
+#[derive(Debug)]
+pub struct PartialWrite {
+    pub committed: Vec<PathBuf>,
+    pub failed: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "writing \"{}\" failed: {}", self.failed.display(), self.error)?;
+        if !self.committed.is_empty() {
+            write!(f, ". Files already written:")?;
+            for path in &self.committed {
+                write!(f, " \"{}\"", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for PartialWrite {}
+
+impl From<PartialWrite> for io::Error {
+    fn from(partial: PartialWrite) -> io::Error {
+        io::Error::new(partial.error.kind(), partial)
+    }
+}
+
+impl Transaction {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn write<F>(&mut self, path: &Path, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    {
+        let path = resolve_symlink(path);
+        let temp = sibling_path(&path, "tmp");
+        let result = File::create(&temp).and_then(|file| {
+            if let Ok(metadata) = fs::metadata(&path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.into_inner().map_err(|error| error.into_error())?.sync_all()
+        });
+        match result {
+            Ok(()) => {
+                self.staged.push((temp, path));
+                Ok(())
+            }
+            Err(error) => {
+                let _ = remove_file(temp);
+                Err(error)
+            }
+        }
+    }
+
+    /// Removes the file on commit, after every staged file is in place. Files that are also
+    /// staged to be written are kept.
+    #[inline]
+    pub fn remove(&mut self, path: &Path) {
+        self.removed.push(path.to_path_buf());
+    }
+
+    /// Paths of the files the transaction writes or removes
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.staged
+            .iter()
+            .map(|(_, path)| path.as_path())
+            .chain(self.removed.iter().map(PathBuf::as_path))
+    }
+
+    /// Replaces the real files with the staged ones in the order they were staged, so files
+    /// should be staged before the ones that point to them. The replaced files are kept until
+    /// all of them are in place, if one can't be replaced the ones before it are put back.
+    /// Returns paths of the written files
+    pub fn commit(mut self) -> Result<Vec<PathBuf>, PartialWrite> {
+        let mut replaced: Vec<Replaced> = vec![];
+        if let Err((failed, error)) = self.replace(&mut replaced) {
+            let mut committed: Vec<PathBuf> = vec![];
+            for file in replaced.into_iter().rev() {
+                if file.restore().is_err() {
+                    committed.push(file.path);
+                }
+            }
+            committed.reverse();
+            return Err(PartialWrite {
+                committed,
+                failed,
+                error,
+            });
+        }
+        let committed: Vec<PathBuf> = replaced
+            .into_iter()
+            .map(|file| {
+                if let Some(backup) = file.backup {
+                    let _ = remove_file(backup);
+                }
+                file.path
+            })
+            .collect();
+        let mut directories: Vec<&Path> = committed.iter().flat_map(|path| path.parent()).collect();
+        directories.sort();
+        directories.dedup();
+        for directory in directories {
+            if let Err(error) = sync_directory(directory) {
+                return Err(PartialWrite {
+                    failed: directory.to_path_buf(),
+                    committed,
+                    error,
+                });
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Renames the staged files over the real ones and removes the removed ones, keeping
+    /// backups of both in `replaced`. Stops at the first file that fails
+    fn replace(&mut self, replaced: &mut Vec<Replaced>) -> Result<(), (PathBuf, io::Error)> {
+        let staged = std::mem::take(&mut self.staged);
+        let mut staged = staged.into_iter();
+        while let Some((temp, path)) = staged.next() {
+            let result = back_up(&path).and_then(|backup| {
+                fs::rename(&temp, &path).inspect_err(|_| {
+                    if let Some(backup) = &backup {
+                        let _ = remove_file(backup);
+                    }
+                })?;
+                Ok(backup)
+            });
+            match result {
+                Ok(backup) => replaced.push(Replaced { path, backup }),
+                Err(error) => {
+                    let _ = remove_file(temp);
+                    for (temp, _) in staged {
+                        let _ = remove_file(temp);
+                    }
+                    return Err((path, error));
+                }
+            }
+        }
+        for path in std::mem::take(&mut self.removed) {
+            if path.exists() && !replaced.iter().any(|file| file.path == path) {
+                let backup = sibling_path(&path, "bak");
+                fs::rename(&path, &backup).map_err(|error| (path.clone(), error))?;
+                replaced.push(Replaced {
+                    path,
+                    backup: Some(backup),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        for (temp, _) in &self.staged {
+            let _ = remove_file(temp);
+        }
+    }
+}
+
+/// What a file looked like when c3 last read or wrote it, to tell if something else changed
+/// it since
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    // Set once it's old enough to be trusted, even if that's after the read
+    modified: Cell<Option<SystemTime>>,
+    len: u64,
+    hash: String,
+}
+
+// Modification times this close to the read might be shared with a write right after it
+const RACY_MODIFIED_SECS: u64 = 2;
+
+#[inline(always)]
+fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(content))
+}
+
+/// The modification time, if it's old enough that a write can't share it anymore
+#[inline(always)]
+fn trusted_modified(metadata: &fs::Metadata) -> Option<SystemTime> {
+    metadata.modified().ok().filter(|modified| {
+        modified
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed.as_secs() >= RACY_MODIFIED_SECS)
+    })
+}
+
+impl Fingerprint {
+    /// Reads the file, returning its content along with its fingerprint
+    pub fn read(path: &Path) -> io::Result<(Self, Vec<u8>)> {
+        // Metadata is taken first, so a write between the two shows up as a changed hash later
+        let modified = trusted_modified(&fs::metadata(path)?);
+        let content = fs::read(path)?;
+        let fingerprint = Self {
+            modified: Cell::new(modified),
+            len: content.len() as u64,
+            hash: content_hash(&content),
+        };
+        Ok((fingerprint, content))
+    }
+
+    /// Whether the file still has the content it had. The content is only hashed again if
+    /// the modification time changed, or was too recent to be trusted when read. A matching
+    /// hash trusts the modification time from then on, once it's old enough.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        if metadata.len() != self.len {
+            return false;
+        }
+        let modified = self.modified.get();
+        if modified.is_some() && metadata.modified().ok() == modified {
+            return true;
+        }
+        let matches = fs::read(path).is_ok_and(|content| content_hash(&content) == self.hash);
+        if matches {
+            self.modified.set(trusted_modified(&metadata));
+        }
+        matches
+    }
+}
+
+/// Whether the file changed since the fingerprint was taken. A file without a fingerprint
+/// wasn't there when c3 read it, so it's modified if it exists now.
+#[inline]
+pub fn is_modified(path: &Path, fingerprint: Option<&Fingerprint>) -> bool {
+    match fingerprint {
+        Some(fingerprint) => !fingerprint.matches(path),
+        None => path.exists(),
+    }
+}
+
+/// Files with unsaved changes in c3 that something else also changed after they were read
+#[derive(Debug)]
+pub struct ExternalChanges(pub Vec<PathBuf>);
+
+impl ExternalChanges {
+    /// The `ExternalChanges` an io error was made from, if any
+    #[inline]
+    pub fn of(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ExternalChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "changed outside c3 since they were read:")?;
+        for path in &self.0 {
+            write!(f, " \"{}\"", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ExternalChanges {}
+
+impl From<ExternalChanges> for io::Error {
+    fn from(changes: ExternalChanges) -> io::Error {
+        io::Error::other(changes)
+    }
+}
+
+const LOCK_NAME: &str = ".c3.lock";
+const LOCK_RETRY_MS: u64 = 100;
+// A lock file without a pid in it older than this was left by a c3 that died creating it
+const UNFINISHED_LOCK_SECS: u64 = 2;
+// Where a process can't be looked up, a lock not refreshed for this long is taken to be stale
+#[cfg(not(unix))]
+const UNREFRESHED_LOCK_SECS: u64 = 60;
+
+/// The tree is locked by another running c3
+#[derive(Debug)]
+pub struct Locked {
+    pub pid: Option<u32>,
+}
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "todos are locked by another c3 (pid {pid})"),
+            None => write!(f, "todos are locked by another c3"),
+        }
+    }
+}
+
+impl error::Error for Locked {}
+
+impl From<Locked> for io::Error {
+    fn from(locked: Locked) -> io::Error {
+        io::Error::new(io::ErrorKind::WouldBlock, locked)
+    }
+}
+
+#[cfg(unix)]
+#[inline(always)]
+fn is_holder_alive(pid: u32, _lock_age: Option<Duration>) -> bool {
+    use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+    // Zero and negative pids address process groups, they're never in a valid lock file
+    match i32::try_from(pid) {
+        Ok(pid) if pid > 0 => !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH)),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+#[inline(always)]
+fn is_holder_alive(_pid: u32, lock_age: Option<Duration>) -> bool {
+    lock_age.is_some_and(|age| age.as_secs() < UNREFRESHED_LOCK_SECS)
+}
+
+#[inline(always)]
+fn parse_pid(content: &str) -> Option<u32> {
+    content.trim().parse().ok()
+}
+
+/// Advisory lock of a notes directory, held by the c3 that is allowed to write the tree. It's
+/// a file with the pid of its holder, removed when the lock is dropped. Locks of processes
+/// that are not running anymore are taken over, or where processes can't be looked up, locks
+/// that weren't refreshed for a while.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    pub fn acquire(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(LOCK_NAME);
+        loop {
+            match File::options().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", process::id())?;
+                    file.sync_all()?;
+                    return Ok(Self { path });
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    let content = fs::read_to_string(&path).unwrap_or_default();
+                    let pid = parse_pid(&content);
+                    let lock_age = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    let is_stale = match pid {
+                        Some(pid) => !is_holder_alive(pid, lock_age),
+                        None => lock_age.is_some_and(|age| age.as_secs() >= UNFINISHED_LOCK_SECS),
+                    };
+                    if !is_stale {
+                        return Err(Locked { pid }.into());
+                    }
+                    Self::take_over(&path, &content)?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Moves the stale lock with the content out of the way, so it can be created anew. The
+    /// move is atomic, so of several c3 taking the lock over only one moves it. If what was
+    /// moved isn't the stale lock anymore, another c3 took it over first and it's put back.
+    fn take_over(path: &Path, stale_content: &str) -> io::Result<()> {
+        let moved = path.with_file_name(format!("{LOCK_NAME}.{}", process::id()));
+        match fs::rename(path, &moved) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        }
+        let content = fs::read_to_string(&moved).unwrap_or_default();
+        if content != stale_content {
+            // Fails only if yet another c3 created the lock in the meantime
+            let _ = fs::hard_link(&moved, path);
+            remove_file(&moved)?;
+            return Err(Locked {
+                pid: parse_pid(&content),
+            }
+            .into());
+        }
+        remove_file(&moved)
+    }
+
+    /// Tells others this lock is still held where they can't see if its process is running
+    #[inline]
+    pub fn refresh(&self) -> io::Result<()> {
+        File::options()
+            .write(true)
+            .open(&self.path)?
+            .set_modified(SystemTime::now())
+    }
+
+    /// Like `acquire`, but retries until the lock is released or the timeout is reached
+    pub fn acquire_waiting(dir: &Path, timeout: Duration) -> io::Result<Self> {
+        let start = Instant::now();
+        loop {
+            match Self::acquire(dir) {
+                Err(error) if Locked::of(&error).is_some() && start.elapsed() < timeout => {
+                    thread::sleep(Duration::from_millis(LOCK_RETRY_MS));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Locked {
+    /// The `Locked` an io error was made from, if any
+    #[inline]
+    pub fn of(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// Writes a single file through a `Transaction`
+#[inline]
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut transaction = Transaction::new();
+    transaction.write(path, write)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_rollback() -> io::Result<()> {
+        let dir = PathBuf::from("test-transaction-rollback");
+        fs::create_dir_all(&dir)?;
+        let first = dir.join("first");
+        fs::write(&first, "old")?;
+        let mut transaction = Transaction::new();
+        transaction.write(&first, |writer| write!(writer, "new"))?;
+        let failed = transaction.write(&dir.join("second"), |_| Err(io::Error::other("full")));
+        drop(transaction);
+        let content = fs::read_to_string(&first)?;
+        let file_count = fs::read_dir(&dir)?.count();
+        fs::remove_dir_all(dir)?;
+
+        assert!(failed.is_err());
+        assert_eq!(content, "old");
+        assert_eq!(file_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint() -> io::Result<()> {
+        let dir = PathBuf::from("test-fingerprint");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("file");
+        fs::write(&path, "content")?;
+        let (fingerprint, content) = Fingerprint::read(&path)?;
+        let unchanged = !is_modified(&path, Some(&fingerprint));
+        fs::write(&path, "content")?;
+        let same_content = !is_modified(&path, Some(&fingerprint));
+        fs::write(&path, "changed")?;
+        let changed = is_modified(&path, Some(&fingerprint));
+        let created = is_modified(&path, None);
+        fs::remove_dir_all(dir)?;
+
+        assert_eq!(content, b"content");
+        assert!(unchanged);
+        assert!(same_content);
+        assert!(changed);
+        assert!(created);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint_trusted_later() -> io::Result<()> {
+        let dir = PathBuf::from("test-fingerprint-trusted-later");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("file");
+        fs::write(&path, "content")?;
+        let (fingerprint, _) = Fingerprint::read(&path)?;
+        let racy = fingerprint.modified.get();
+        // Like the file was written long enough ago
+        let old = SystemTime::now() - Duration::from_secs(RACY_MODIFIED_SECS + 1);
+        File::options().write(true).open(&path)?.set_modified(old)?;
+        let unchanged = fingerprint.matches(&path);
+        fs::remove_dir_all(dir)?;
+
+        assert_eq!(racy, None);
+        assert!(unchanged);
+        assert_eq!(fingerprint.modified.get(), Some(old));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> io::Result<()> {
+        let dir = PathBuf::from("test-lock");
+        fs::create_dir_all(&dir)?;
+        let lock = Lock::acquire(&dir)?;
+        let second = Lock::acquire(&dir);
+        let waited = Lock::acquire_waiting(&dir, Duration::from_millis(200));
+        drop(lock);
+        let after_release = Lock::acquire(&dir).map(drop);
+        fs::write(dir.join(LOCK_NAME), i32::MAX.to_string())?;
+        let stale = Lock::acquire(&dir).map(drop);
+        let file_count = fs::read_dir(&dir)?.count();
+        fs::remove_dir_all(dir)?;
+
+        let pid = second.err().and_then(|error| Locked::of(&error).and_then(|locked| locked.pid));
+        assert_eq!(pid, Some(process::id()));
+        assert!(waited.is_err());
+        assert!(after_release.is_ok());
+        assert!(stale.is_ok());
+        assert_eq!(file_count, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_taken_over_first() -> io::Result<()> {
+        let dir = PathBuf::from("test-lock-taken-over");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(LOCK_NAME);
+        fs::write(&path, i32::MAX.to_string())?;
+        // Another c3 replaced the stale lock after this one read it
+        let lock = Lock::acquire(&dir)?;
+        let taken_over = Lock::take_over(&path, &i32::MAX.to_string());
+        let content = fs::read_to_string(&path)?;
+        let file_count = fs::read_dir(&dir)?.count();
+        drop(lock);
+        fs::remove_dir_all(dir)?;
+
+        let pid = taken_over
+            .err()
+            .and_then(|error| Locked::of(&error).and_then(|locked| locked.pid));
+        assert_eq!(pid, Some(process::id()));
+        assert_eq!(content, process::id().to_string());
+        assert_eq!(file_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_commit() -> io::Result<()> {
+        let dir = PathBuf::from("test-transaction-commit");
+        fs::create_dir_all(&dir)?;
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::write(&second, "removed")?;
+        let mut transaction = Transaction::new();
+        transaction.write(&first, |writer| write!(writer, "new"))?;
+        transaction.remove(&second);
+        let committed = transaction.commit()?;
+        let content = fs::read_to_string(&first)?;
+        let file_count = fs::read_dir(&dir)?.count();
+        fs::remove_dir_all(dir)?;
+
+        assert_eq!(committed, vec![first, second]);
+        assert_eq!(content, "new");
+        assert_eq!(file_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_commit_failed() -> io::Result<()> {
+        let dir = PathBuf::from("test-transaction-commit-failed");
+        let (first, second, third) = (dir.join("first"), dir.join("second"), dir.join("third"));
+        // A directory can't be replaced by a file
+        fs::create_dir_all(third.join("directory"))?;
+        fs::write(&first, "old")?;
+        fs::write(dir.join("removed"), "removed")?;
+        let mut transaction = Transaction::new();
+        transaction.write(&first, |writer| write!(writer, "new"))?;
+        transaction.write(&second, |writer| write!(writer, "new"))?;
+        transaction.write(&third, |writer| write!(writer, "new"))?;
+        transaction.remove(&dir.join("removed"));
+        let result = transaction.commit();
+        let content = fs::read_to_string(&first)?;
+        let mut names: Vec<String> = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<_>>()?;
+        names.sort();
+        fs::remove_dir_all(dir)?;
+
+        let partial = result.unwrap_err();
+        assert_eq!(partial.failed, third);
+        assert!(partial.committed.is_empty());
+        assert_eq!(content, "old");
+        assert_eq!(names, vec!["first", "removed", "third"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_symlink() -> io::Result<()> {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = PathBuf::from("test-write-symlink");
+        fs::create_dir_all(&dir)?;
+        let (target, link) = (dir.join("target"), dir.join("link"));
+        fs::write(&target, "old")?;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600))?;
+        symlink("target", &link)?;
+        write_atomically(&link, |writer| write!(writer, "new"))?;
+        let is_symlink = fs::symlink_metadata(&link)?.is_symlink();
+        let content = fs::read_to_string(&target)?;
+        let mode = fs::metadata(&target)?.permissions().mode() & 0o777;
+        fs::remove_dir_all(dir)?;
+
+        assert!(is_symlink);
+        assert_eq!(content, "new");
+        assert_eq!(mode, 0o600);
+        Ok(())
+    }
+}
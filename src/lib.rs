@@ -6,6 +6,7 @@ use std::fmt;
 use std::path::PathBuf;
 use todo_app::SortMethod;
 
+pub mod config;
 pub mod date;
 pub mod fileio;
 pub mod todo_app;
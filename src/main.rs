@@ -1,12 +1,15 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 // imports {{{
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::env;
 use std::io;
+use std::path::PathBuf;
+use std::process;
 pub(crate) mod cli_app;
 pub(crate) mod tui_app;
-use c3::{todo_app::App, AppArgs};
+use c3::{config::{self, Config}, todo_app::App, AppArgs};
 use cli_app::CliArgs;
-use tui_app::TuiArgs;
+use tui_app::{keymap::Keymap, theme::{self, Theme}, TuiArgs};
 // }}}
 
 /// A tree-like todo application that makes you smile
@@ -21,14 +24,47 @@ pub struct Args {
 
     #[clap(flatten)]
     pub tui_args: TuiArgs,
+
+    /// Config file to read the defaults of options from, instead of the one in the config
+    /// directory
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Print the config the config file and the options make, and exit
+    #[arg(long)]
+    pub print_config: bool,
 }
 
 fn main() -> io::Result<()> {
-    let args = Args::parse();
+    let config_path = Config::path_in_args(env::args_os());
+    let config = Config::load(config_path.as_deref()).unwrap_or_else(|error| {
+        eprintln!("Failed to read the config: {error}");
+        process::exit(1);
+    });
+    let keymap = Keymap::new(config.keys()).unwrap_or_else(|error| {
+        eprintln!("Failed to read the config: {error}");
+        process::exit(1);
+    });
+    let matches = config.apply(Args::command()).get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    let theme = Theme::new(args.tui_args.theme, config.theme(), theme::no_color())
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read the config: {error}");
+            process::exit(1);
+        });
+    if args.print_config {
+        print!(
+            "{}\n{}\n{}",
+            config::effective(&matches),
+            keymap.config(),
+            theme.config()
+        );
+        return Ok(());
+    }
     let mut app = App::new(args.app_args);
 
     if cli_app::run(&mut app, args.cli_args).is_err() {
-        let result = tui_app::run(&mut app, args.tui_args);
+        let result = tui_app::run(&mut app, args.tui_args, keymap, theme);
         tui_app::shutdown()?;
         result
     } else {
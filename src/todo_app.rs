@@ -1,19 +1,35 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 // imports {{{
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::create_dir_all;
 use std::path::Path;
 use std::str::{FromStr, Lines};
+use std::time::Duration;
 use std::{io, path::PathBuf};
 use clap::ValueEnum;
+pub use todo::recurrence::Recurrence;
 pub use todo::schedule::Schedule;
 mod todo;
 mod todo_list;
+pub mod convert;
+pub mod filter;
 pub mod fzf_search;
-use crate::{fileio, AppArgs};
-use std::rc::Rc;
-pub use todo::Todo;
-pub use self::todo_list::TodoList;
+pub mod restriction;
+pub mod tree_path;
+mod bulk;
+mod journal;
+mod undo;
+use crate::fileio::{self, ExternalChanges, Lock, Transaction};
+use crate::{date, AppArgs};
+use bulk::ScheduleInput;
+use journal::Journal;
+use undo::UndoHistory;
+pub use journal::Operation;
+pub use todo::{ParseError, Todo};
+pub use self::todo_list::{MalformedLine, TodoList};
+pub use restriction::Restriction;
+pub use tree_path::{TreePath, TreePathError};
 // }}}
 
 #[derive(ValueEnum, Clone, Debug, PartialEq, Default)]
@@ -25,6 +41,9 @@ pub enum SortMethod {
     AbandonedFirst,
     #[value(alias = "nta")]
     NormalThenAbandoned,
+    /// Undone todos with the closest due date first, todos without a due date after them
+    #[value(alias = "u", alias = "due")]
+    Urgency,
 }
 
 impl SortMethod {
@@ -49,12 +68,24 @@ impl SortMethod {
                 } else {
                     order
                 }
-            }
+            },
+            Self::Urgency => |a: &Todo, b: &Todo| {
+                let order = a.done().cmp(&b.done()).then_with(|| match (a.due, b.due) {
+                    (Some(a_due), Some(b_due)) => a_due.cmp(&b_due),
+                    (Some(_), None) => cmp::Ordering::Less,
+                    (None, Some(_)) => cmp::Ordering::Greater,
+                    (None, None) => cmp::Ordering::Equal,
+                });
+                if order.is_eq() {
+                    a.cmp(b)
+                } else {
+                    order
+                }
+            },
         }
     }
 }
 
-pub type Restriction = Rc<dyn Fn(&Todo) -> bool>;
 pub struct App {
     notes_dir: PathBuf,
     pub todo_list: TodoList,
@@ -63,6 +94,9 @@ pub struct App {
     pub args: AppArgs,
     pub removed_todos: Vec<Todo>,
     restriction: Restriction,
+    lock: Option<Lock>,
+    read_only: bool,
+    undo_history: UndoHistory,
 }
 
 #[derive(Debug)]
@@ -121,6 +155,9 @@ impl App {
             tree_path: vec![],
             args,
             restriction: Self::no_restriction(),
+            lock: None,
+            read_only: false,
+            undo_history: UndoHistory::default(),
         };
         app.update_show_done_restriction();
         app
@@ -136,6 +173,8 @@ impl App {
         if !args.no_tree {
             let _ = todo_list.read_dependencies(notes_dir);
         }
+        todo_list.ensure_unique_ids(&mut HashSet::new());
+        let _ = todo_list.read_histories(notes_dir);
         todo_list
     }
 
@@ -151,33 +190,88 @@ impl App {
         &self.restriction
     }
 
+    /// Takes the lock of the notes directory until `unlock` or the end of the app. Without it,
+    /// the lock is only taken while writing
+    pub fn lock(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        create_dir_all(&self.notes_dir)?;
+        self.lock = None;
+        self.lock = Some(match timeout {
+            Some(timeout) => Lock::acquire_waiting(&self.notes_dir, timeout)?,
+            None => Lock::acquire(&self.notes_dir)?,
+        });
+        Ok(())
+    }
+
+    #[inline]
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    /// Keeps the lock from looking stale where its process can't be looked up
+    #[inline]
+    pub fn refresh_lock(&self) {
+        if let Some(lock) = &self.lock {
+            let _ = lock.refresh();
+        }
+    }
+
+    #[inline]
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    #[inline]
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     #[inline]
     pub fn open_path(&mut self, path: PathBuf) {
+        // The lock belongs to the notes directory of the old path
+        let was_locked = self.is_locked();
+        self.unlock();
         self.notes_dir = fileio::append_notes_to_path_parent(&path);
         self.todo_list = Self::read_a_todo_list(&path, &self.notes_dir, &self.args);
         self.tree_path = vec![];
         self.args.todo_path = path;
+        self.clear_undo_history();
+        if was_locked {
+            self.read_only = self.lock(None).is_err();
+        }
     }
 
     #[inline]
-    pub fn output_list_to_path(&self, path: &Path) -> io::Result<()> {
+    pub fn output_list_to_path(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
         let list = self.current_list();
         let dependency_path = fileio::append_notes_to_path_parent(path);
         create_dir_all(&dependency_path)?;
-        list.force_write(path)?;
-
-        list.force_write_dependencies(&dependency_path)?;
-        Ok(())
-    }
-
-    #[inline]
-    pub fn append_list(&mut self, todo_list: TodoList) {
+        let mut transaction = Transaction::new();
+        list.force_stage(path, &mut transaction)?;
+        list.force_stage_dependencies(&dependency_path, &mut transaction)?;
+        Ok(transaction.commit()?)
+    }
+
+    /// Appends the todos to the current list in a single undo step. Ids the tree already has
+    /// are renewed
+    pub fn append_list(&mut self, mut todo_list: TodoList) {
+        let mut ids = HashSet::new();
+        self.todo_list.ensure_unique_ids(&mut ids);
+        todo_list.ensure_unique_ids(&mut ids);
+        self.begin_undo_step();
+        for todo in &todo_list.todos {
+            self.record_added(todo.id().to_string());
+        }
         self.current_list_mut().append_list(todo_list)
     }
 
     pub fn set_restriction_with_last(&mut self, restriction: Restriction, last_restriction: Option<Restriction>) {
         let last_restriction = last_restriction.unwrap_or(self.restriction.clone());
-        self.set_restriction(Rc::new(move |todo| restriction(todo) && last_restriction(todo)))
+        self.set_restriction(restriction::and(restriction, last_restriction))
     }
 
     pub fn batch_editor_messages(&mut self) {
@@ -193,7 +287,7 @@ impl App {
         let new_messages =
             fileio::open_temp_editor(Some(&content), fileio::temp_path("messages")).unwrap();
         let new_messages = new_messages.lines();
-        self.batch_edit_current_list(new_messages);
+        self.rewrite_current_list(|app| app.batch_edit_current_list(new_messages));
         self.fix_index();
     }
 
@@ -260,6 +354,7 @@ impl App {
 
     #[inline(always)]
     pub fn increase_day_by(&mut self, days: i64) {
+        self.save_undo_point();
         if let Some(Some(schedule)) = self.todo_mut().map(|todo| todo.schedule.as_mut()) {
             schedule.add_days_to_date(-days);
             self.reorder_current();
@@ -268,14 +363,20 @@ impl App {
 
     #[inline]
     pub fn prepend(&mut self, message: String) {
-        self.current_list_mut().prepend(Todo::new(message, 1));
+        let todo = Todo::new(message, 1);
+        self.begin_undo_step();
+        self.record_added(todo.id().to_string());
+        self.current_list_mut().prepend(todo);
         self.index = 0;
     }
 
     #[inline]
     pub fn append(&mut self, message: String) {
+        let todo = Todo::new(message, 0);
+        self.begin_undo_step();
+        self.record_added(todo.id().to_string());
         let todo_list = self.current_list_mut();
-        todo_list.push(Todo::new(message, 0));
+        todo_list.push(todo);
         self.index = todo_list.reorder_last();
     }
 
@@ -293,7 +394,7 @@ impl App {
         if self.args.display_args.show_done {
             self.unset_restriction()
         } else {
-            self.set_restriction(Rc::new(|todo| !todo.done()))
+            self.set_restriction(restriction::undone())
         }
     }
 
@@ -317,11 +418,18 @@ impl App {
 
     #[inline]
     pub fn toggle_current_done(&mut self) {
+        self.begin_undo_step();
+        self.toggle_current_done_recorded();
+    }
+
+    // Parents that get done along with the todo are a part of the same undo step
+    fn toggle_current_done_recorded(&mut self) {
+        self.record_current();
         if let Some(todo) = self.todo_mut() {
             todo.toggle_done();
             self.reorder_current();
             while self.is_undone_empty() && self.traverse_up() {
-                self.toggle_current_done()
+                self.toggle_current_done_recorded()
             }
         }
     }
@@ -329,11 +437,20 @@ impl App {
     #[inline]
     pub fn read(&mut self) {
         self.todo_list = Self::read_a_todo_list(&self.args.todo_path, &self.notes_dir, &self.args);
+        self.clear_undo_history();
         let len = self.max_tree_length();
         self.tree_path.truncate(len);
         self.fix_index();
     }
 
+    /// Every line c3 couldn't parse in the todo file and the notes directory
+    pub fn diagnostics(&self) -> Vec<(PathBuf, &MalformedLine)> {
+        let mut output = vec![];
+        self.todo_list
+            .collect_malformed_lines(&self.args.todo_path, &self.notes_dir, &mut output);
+        output
+    }
+
     #[inline]
     fn fix_index(&mut self) {
         let size = self.current_list().len(&self.restriction);
@@ -393,7 +510,7 @@ impl App {
     pub fn traverse_down(&mut self) {
         if self.is_tree() {
             match self.todo() {
-                Some(todo) if todo.dependency.as_ref().map_or(false, |dep| dep.is_list()) => {
+                Some(todo) if todo.dependency.as_ref().is_some_and(|dep| dep.is_list()) => {
                     let index = self.index;
                     let restriction = self.restriction.clone();
                     let true_index = self
@@ -452,28 +569,219 @@ impl App {
 
     #[inline]
     pub fn current_list_mut(&mut self) -> &mut TodoList {
-        let is_root = self.is_root();
-        if is_root {
-            return &mut self.todo_list;
+        Self::list_at_path_mut(&mut self.todo_list, &self.tree_path)
+    }
+
+    #[inline]
+    pub fn current_list(&self) -> &TodoList {
+        Self::list_at_path(&self.todo_list, &self.tree_path)
+    }
+
+    #[inline]
+    fn stage_removed_todo_dependency_files(&self, dependency_path: &Path, transaction: &mut Transaction) {
+        for todo in &self.removed_todos {
+            todo.stage_dependency_removal(dependency_path, transaction);
         }
-        let mut list = &mut self.todo_list;
+    }
 
-        for &index in &self.tree_path {
-            if list.todos[index].dependency.is_some() {
-                list = &mut list.todos[index].dependency.as_mut().unwrap().todo_list
+    #[inline(always)]
+    fn collect_external_changes(&self) -> Vec<(PathBuf, bool)> {
+        let mut output = vec![];
+        self.todo_list.collect_external_changes(
+            &self.args.todo_path,
+            &self.notes_dir,
+            self.is_tree(),
+            &mut output,
+        );
+        output
+    }
+
+    /// Files of the tree that something else changed since c3 read or wrote them
+    #[inline]
+    pub fn external_changes(&self) -> Vec<PathBuf> {
+        self.collect_external_changes()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Merges the changes made outside c3 into the tree, keeping the changes made in c3 where
+    /// both changed the same todo. Nothing is written.
+    pub fn merge_external_changes(&mut self) -> io::Result<()> {
+        let tree = self.is_tree();
+        self.todo_list
+            .merge_external_changes(&self.args.todo_path, &self.notes_dir, tree)?;
+        self.todo_list.ensure_unique_ids(&mut HashSet::new());
+        self.clear_undo_history();
+        let len = self.max_tree_length();
+        self.tree_path.truncate(len);
+        self.fix_index();
+        Ok(())
+    }
+
+    /// Like `overwrite`, but fails with `ExternalChanges` instead of writing over lists that
+    /// were changed both in c3 and outside of it
+    #[inline]
+    pub fn write(&mut self) -> io::Result<Vec<PathBuf>> {
+        let _lock = self.write_lock()?;
+        let conflicts: Vec<PathBuf> = self
+            .collect_external_changes()
+            .into_iter()
+            .filter_map(|(path, changed)| changed.then_some(path))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(ExternalChanges(conflicts).into());
+        }
+        self.write_files()
+    }
+
+    /// The lock to hold while writing, if the app doesn't already hold one for itself
+    #[inline(always)]
+    fn write_lock(&self) -> io::Result<Option<Lock>> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "c3 is read only, another c3 has the lock of the todos",
+            ));
+        }
+        if self.lock.is_some() {
+            return Ok(None);
+        }
+        create_dir_all(&self.notes_dir)?;
+        Lock::acquire(&self.notes_dir).map(Some)
+    }
+
+    /// Writes the changed files of the tree, even over changes made outside c3. Returns paths
+    /// of the files that got written or removed
+    #[inline]
+    pub fn overwrite(&mut self) -> io::Result<Vec<PathBuf>> {
+        let _lock = self.write_lock()?;
+        self.write_files()
+    }
+
+    /// Operations in the journal of the tree, oldest first
+    #[inline]
+    pub fn journal(&self) -> io::Result<Vec<Operation>> {
+        Journal::new(&self.args.todo_path).operations()
+    }
+
+    /// Reverts the operation with the given number from the journal in the files, then reads
+    /// them again. Returns paths of the files that got written.
+    pub fn revert(&mut self, number: usize) -> io::Result<Vec<PathBuf>> {
+        let _lock = self.write_lock()?;
+        let committed = Journal::new(&self.args.todo_path).revert(number)?;
+        self.read();
+        Ok(committed)
+    }
+
+    /// Writes every changed file of the tree through a `Transaction`. Each file is replaced
+    /// atomically, the files of sub lists, notes and histories before the lists pointing to
+    /// them and the todo file last, so a crash never leaves a list pointing to a missing file.
+    /// The files aren't replaced all at once though: if one fails, the ones already replaced
+    /// are put back, and the error is a `PartialWrite` listing those that couldn't be.
+    fn write_files(&mut self) -> io::Result<Vec<PathBuf>> {
+        // Ids given on read are only worth a write along with a change
+        if !self.is_changed() && self.removed_todos.is_empty() {
+            return Ok(vec![]);
+        }
+        let note_dir = fileio::append_notes_to_path_parent(&self.args.todo_path);
+
+        create_dir_all(&note_dir)?;
+        let mut transaction = Transaction::new();
+        self.stage_removed_todo_dependency_files(&note_dir, &mut transaction);
+        self.todo_list.stage_removed_dependencies(&note_dir, &mut transaction);
+        self.todo_list.stage_histories(&note_dir, &mut transaction)?;
+        if self.is_tree() {
+            self.todo_list.stage_dependencies(&note_dir, &mut transaction)?;
+        }
+        self.todo_list.stage(&self.args.todo_path, &mut transaction)?;
+        let committed = Journal::new(&self.args.todo_path).commit(transaction)?;
+        self.removed_todos.clear();
+        self.todo_list.mark_written(&self.args.todo_path, &note_dir);
+        self.todo_list.mark_histories_written();
+        Ok(committed)
+    }
+
+    #[inline]
+    pub fn todo_by_id(&self, id: &str) -> Option<&Todo> {
+        let path = self.todo_list.path_of_id(id)?;
+        let (&index, tree_path) = path.split_last()?;
+        Self::list_at_path(&self.todo_list, tree_path).todos.get(index)
+    }
+
+    #[inline]
+    pub fn todo_by_id_mut(&mut self, id: &str) -> Option<&mut Todo> {
+        let path = self.todo_list.path_of_id(id)?;
+        let (&index, tree_path) = path.split_last()?;
+        let list = Self::list_at_path_mut(&mut self.todo_list, tree_path);
+        list.changed = true;
+        list.todos.get_mut(index)
+    }
+
+    /// Moves the cursor to the todo with the given id. Returns false if there's no such todo, or
+    /// it's not visible with the current restriction
+    pub fn select_id(&mut self, id: &str) -> bool {
+        if let Some(mut path) = self.todo_list.path_of_id(id) {
+            path.pop();
+            self.tree_path = path;
+            self.update_show_done_restriction();
+            let restriction = self.restriction.clone();
+            if let Some(index) = self.current_list().restricted_position_of_id(id, &restriction) {
+                self.index = index;
+                return true;
             }
+            self.fix_index();
         }
-        list
+        false
     }
 
     #[inline]
-    pub fn current_list(&self) -> &TodoList {
+    pub fn tree_path_of_id(&self, id: &str) -> Option<Vec<usize>> {
+        self.todo_list.path_of_id(id)
+    }
+
+    /// The todo at a tree path of true indices, like the ones `tree_path` holds
+    pub fn todo_at_path(&self, path: &[usize]) -> Option<&Todo> {
+        let (&index, parents) = path.split_last()?;
         let mut list = &self.todo_list;
-        if self.is_root() {
-            return list;
+        for &parent in parents {
+            list = list.todos.get(parent)?.dependency.as_ref()?.todo_list()?;
         }
-        for &index in &self.tree_path {
-            if let Some(todo_list) = &list.todos[index]
+        list.todos.get(index)
+    }
+
+    /// Moves the cursor to the todo at the tree path, showing done todos too so that any todo
+    /// can be selected. Returns false if there's no such todo
+    pub fn select_path(&mut self, path: &[usize]) -> bool {
+        match (self.todo_at_path(path), path.split_last()) {
+            (Some(_), Some((&index, parents))) => {
+                self.tree_path = parents.to_vec();
+                self.unset_restriction();
+                self.index = index;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes the sub list of the todo at the tree path the current list, adding the list if
+    /// the todo doesn't have one. An empty path is the root list.
+    pub fn select_list(&mut self, path: &[usize]) -> bool {
+        if path.is_empty() {
+            self.go_root();
+            return true;
+        }
+        if !self.select_path(path) {
+            return false;
+        }
+        self.add_dependency_traverse_down();
+        self.tree_path.len() == path.len()
+    }
+
+    #[inline(always)]
+    fn list_at_path<'a>(mut list: &'a TodoList, tree_path: &[usize]) -> &'a TodoList {
+        for &index in tree_path {
+            if let Some(todo_list) = list.todos[index]
                 .dependency
                 .as_ref()
                 .and_then(|dep| dep.todo_list())
@@ -486,27 +794,44 @@ impl App {
         list
     }
 
-    #[inline]
-    pub fn handle_removed_todo_dependency_files(&mut self, dependency_path: &Path) {
-        for todo in &mut self.removed_todos {
-            let _ = todo.delete_dependency_file(dependency_path);
+    #[inline(always)]
+    fn list_at_path_mut<'a>(mut list: &'a mut TodoList, tree_path: &[usize]) -> &'a mut TodoList {
+        for &index in tree_path {
+            if list.todos[index].dependency.is_some() {
+                list = &mut list.todos[index].dependency.as_mut().unwrap().todo_list
+            }
         }
-        self.removed_todos = vec![];
+        list
     }
 
+    /// Pushes a todo to the current list, giving it (and its sub-todos) new ids if they're
+    /// already used in the tree, like when pasting a yanked todo.
     #[inline]
-    pub fn write(&mut self) -> io::Result<()> {
-        let note_dir = fileio::append_notes_to_path_parent(&self.args.todo_path);
+    pub fn paste_todo(&mut self, todo: Todo) {
+        self.paste_todos(vec![todo]);
+    }
 
-        create_dir_all(&note_dir)?;
-        let todo_path = self.args.todo_path.clone();
-        self.handle_removed_todo_dependency_files(&note_dir);
-        self.todo_list.write(&todo_path)?;
-        self.todo_list.delete_removed_dependent_files(&note_dir)?;
-        if self.is_tree() {
-            self.todo_list.write_dependencies(&note_dir)?;
+    /// Every tag used in the tree by todos matching the restriction, with how many todos use it.
+    /// Most used tags come first
+    pub fn tag_counts(&self) -> Vec<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut stack: Vec<&TodoList> = vec![&self.todo_list];
+        while let Some(list) = stack.pop() {
+            for todo in list.todos(&self.restriction) {
+                for tag in todo.tags() {
+                    *counts.entry(tag).or_default() += 1;
+                }
+                if let Some(todo_list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
+                    stack.push(todo_list);
+                }
+            }
         }
-        Ok(())
+        let mut counts: Vec<(String, usize)> = counts
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
     }
 
     #[inline]
@@ -521,14 +846,39 @@ impl App {
 
     #[inline]
     pub fn toggle_current_daily(&mut self) {
+        self.save_undo_point();
         if let Some(todo) = self.todo_mut() {
             todo.toggle_daily();
             self.reorder_current();
         }
     }
 
+    /// Schedules the current todo every given days (`7`), weeks (`2w`) or by a rule like
+    /// `weekdays`. Returns false if the input is none of them
+    pub fn schedule_current(&mut self, input: &str) -> bool {
+        let Some(schedule) = ScheduleInput::parse(input) else {
+            return false;
+        };
+        self.save_undo_point();
+        if let Some(todo) = self.todo_mut() {
+            schedule.apply(todo);
+        }
+        self.reorder_current();
+        true
+    }
+
+    #[inline]
+    pub fn remind_current(&mut self, date: date::Type) {
+        self.save_undo_point();
+        if let Some(todo) = self.todo_mut() {
+            todo.schedule = Some(Schedule::new_reminder(date));
+            self.reorder_current();
+        }
+    }
+
     #[inline]
     pub fn toggle_current_weekly(&mut self) {
+        self.save_undo_point();
         if let Some(todo) = self.todo_mut() {
             todo.toggle_weekly();
             self.reorder_current();
@@ -537,19 +887,17 @@ impl App {
 
     #[inline]
     pub fn is_undone_empty(&self) -> bool {
-        let restriction: Restriction = Rc::new(move |todo| !todo.done());
-        self.current_list().is_empty(&restriction)
+        self.current_list().is_empty(&restriction::undone())
     }
 
     #[inline]
     pub fn is_done_empty(&self) -> bool {
-        let restriction: Restriction = Rc::new(move |todo| todo.done());
-        self.current_list().is_empty(&restriction)
+        self.current_list().is_empty(&restriction::done())
     }
 
     #[inline(always)]
     pub fn no_restriction() -> Restriction {
-        Rc::new(|_| true)
+        restriction::all()
     }
 
     #[inline(always)]
@@ -565,6 +913,7 @@ impl App {
 
     #[inline]
     pub fn set_current_priority(&mut self, priority: u8) {
+        self.save_undo_point();
         if let Some(todo) = self.todo_mut() {
             todo.set_priority(priority);
             self.reorder_current();
@@ -588,6 +937,7 @@ impl App {
     pub fn remove_todo(&mut self) {
         let restriction = self.restriction.clone();
         if !self.is_todos_empty() {
+            self.save_undo_point();
             let index = self.index;
             let todo = self.current_list_mut().remove(index, &restriction);
             self.removed_todos.push(todo);
@@ -608,6 +958,7 @@ impl App {
 
     #[inline]
     pub fn remove_current_dependent(&mut self) {
+        self.save_undo_point();
         if let Some(todo) = self.todo_mut() {
             todo.remove_dependency();
         }
@@ -615,6 +966,7 @@ impl App {
 
     #[inline]
     pub fn add_dependency(&mut self) {
+        self.save_undo_point();
         if let Some(todo) = self.todo_mut() {
             todo.add_todo_dependency();
         }
@@ -624,6 +976,7 @@ impl App {
     pub fn edit_or_add_note(&mut self) {
         if self.is_tree() {
             let list_changed = self.current_list().changed;
+            self.save_undo_point();
             if let Some(todo) = self.todo_mut() {
                 if !todo.edit_note().unwrap_or_default() {
                     self.current_list_mut().changed = list_changed;
@@ -634,6 +987,7 @@ impl App {
 
     #[inline]
     pub fn move_current_down(&mut self) {
+        self.save_undo_point();
         let index = self.index;
         let restriction = self.restriction.clone();
         let next_priority = self
@@ -661,6 +1015,7 @@ impl App {
         if self.index == 0 {
             return;
         }
+        self.save_undo_point();
         let index = self.index;
         let restriction = self.restriction.clone();
         let prev_priority = self
@@ -690,6 +1045,7 @@ impl App {
             // change anything, we won't borrow mutable and set the todo_list.changed=true
             if let Some(todo) = self.todo() {
                 if todo.dependency.is_none() {
+                    self.save_undo_point();
                     self.todo_mut().unwrap().add_todo_dependency();
                 }
             }
@@ -749,6 +1105,7 @@ pub mod test_helpers {
 #[cfg(test)]
 mod tests {
     use std::fs::{self, remove_dir_all};
+    use std::rc::Rc;
     use super::test_helpers::*;
     use crate::date;
     use super::*;
@@ -757,16 +1114,16 @@ mod tests {
     fn test_is_changed() -> io::Result<()> {
         let dir = dir("test-is-changed")?;
         let mut app = write_test_todos(&dir)?;
-        assert_eq!(app.is_changed(), false);
+        assert!(!app.is_changed());
         app.todo_mut();
-        assert_eq!(app.is_changed(), true);
+        assert!(app.is_changed());
         app.write()?;
-        assert_eq!(app.is_changed(), false);
+        assert!(!app.is_changed());
         let list = app.current_list_mut();
         list.sort();
-        assert_eq!(app.is_changed(), true);
+        assert!(app.is_changed());
         app.read();
-        assert_eq!(app.is_changed(), false);
+        assert!(!app.is_changed());
         remove_dir_all(dir)?;
         Ok(())
     }
@@ -833,7 +1190,7 @@ mod tests {
             .map(|res| res.map(|e| e.file_name().to_str().unwrap().to_string()))
             .collect::<Result<Vec<_>, io::Error>>()?;
 
-        let expected_names = vec![
+        let expected_names = [
             "33a25a20dcf8d607bcac45120f26ab158d5dbdd2",
             "560b05afe5e03eae9f8ad475b0b8b73ea6911272.todo",
             "63c5498f09d086fca6d870345350bfb210945790.todo",
@@ -849,6 +1206,143 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_write_committed() -> io::Result<()> {
+        let dir = dir("test-write-committed")?;
+        let mut app = write_test_todos(&dir)?;
+        let unchanged = app.write()?;
+        app.set_current_priority(3);
+        let changed = app.write()?;
+        // The new sub list is in place before the todo file points to it
+        app.go_root();
+        app.append(String::from("With a sub list"));
+        app.add_dependency_traverse_down();
+        app.append(String::from("Under it"));
+        let nested = app.write()?;
+
+        remove_dir_all(dir.clone())?;
+        assert!(unchanged.is_empty());
+        assert_eq!(changed, vec![dir.join("todo")]);
+        assert_eq!(nested.len(), 2);
+        assert!(nested[0].starts_with(dir.join("notes")));
+        assert_eq!(nested[1], dir.join("todo"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_changes() -> io::Result<()> {
+        let dir = dir("test-external-changes")?;
+        let mut app = write_test_todos(&dir)?;
+        let todo_path = dir.join("todo");
+        let ids: Vec<String> = app.todo_list.iter().map(|todo| todo.id().to_string()).collect();
+        let removed_outside = format!("[0] Goodbye [id:{}]\n", ids[1]);
+        let content = fs::read_to_string(&todo_path)?.replace(&removed_outside, "");
+        fs::write(&todo_path, content + "[1] From outside\n")?;
+        let changes = app.external_changes();
+        app.append(String::from("From c3"));
+        let write_result = app.write();
+        app.merge_external_changes()?;
+        app.write()?;
+        let mut messages: Vec<String> = App::new(AppArgs {
+            todo_path: todo_path.clone(),
+            ..Default::default()
+        })
+            .todo_list
+            .iter()
+            .map(|todo| todo.message.clone())
+            .collect();
+        messages.sort();
+
+        remove_dir_all(dir)?;
+        assert_eq!(changes, vec![todo_path]);
+        assert!(write_result.is_err_and(|error| ExternalChanges::of(&error).is_some()));
+        assert_eq!(messages, vec!["From c3", "From outside", "Hello", "Hello there"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_external_edits() -> io::Result<()> {
+        let dir = dir("test-merge-external-edits")?;
+        let mut app = write_test_todos(&dir)?;
+        let todo_path = dir.join("todo");
+        let ids: Vec<String> = app.todo_list.iter().map(|todo| todo.id().to_string()).collect();
+        // Outside c3 the first todo is marked done, and the second one gets priority 2
+        let line = |prefix: &str, message: &str, id: &str| format!("{prefix} {message} [id:{id}]");
+        let content = fs::read_to_string(&todo_path)?
+            .replace(&line("[0]", "Hello", &ids[0]), &line("[-0]", "Hello", &ids[0]))
+            .replace(&line("[0]", "Goodbye", &ids[1]), &line("[2]", "Goodbye", &ids[1]));
+        fs::write(&todo_path, content)?;
+        // In c3 only the second one is changed
+        app.todo_by_id_mut(&ids[1]).unwrap().set_priority(3);
+        app.merge_external_changes()?;
+        app.write()?;
+        app.read();
+
+        remove_dir_all(dir)?;
+        assert!(app.todo_by_id(&ids[0]).unwrap().done());
+        assert_eq!(app.todo_by_id(&ids[1]).unwrap().priority(), 3);
+        assert!(app.todo_by_id(&ids[2]).unwrap().dependency.is_some());
+        assert_eq!(app.todo_list.todos.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_lines_without_ids() -> io::Result<()> {
+        let dir = dir("test-merge-lines-without-ids")?;
+        fs::create_dir_all(dir.join("notes"))?;
+        let todo_path = dir.join("todo");
+        fs::write(&todo_path, "[0] Keep\n[0] Edit\n[0] Remove\n")?;
+        let args = || AppArgs {
+            todo_path: todo_path.clone(),
+            ..Default::default()
+        };
+        let mut app = App::new(args());
+        // Outside c3 one line is changed and one is removed, both still without ids
+        fs::write(&todo_path, "[0] Keep\n[1] Edit\n")?;
+        app.append(String::from("From c3"));
+        app.merge_external_changes()?;
+        app.write()?;
+        let mut lines: Vec<(u8, String)> = App::new(args())
+            .todo_list
+            .iter()
+            .map(|todo| (todo.priority(), todo.message.clone()))
+            .collect();
+        lines.sort();
+
+        remove_dir_all(dir)?;
+        let expected = [(0, "From c3"), (0, "Keep"), (1, "Edit")];
+        assert_eq!(lines, expected.map(|(priority, message)| (priority, String::from(message))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> io::Result<()> {
+        let dir = dir("test-lock-app")?;
+        let mut app = write_test_todos(&dir)?;
+        let mut other = App::new(AppArgs {
+            todo_path: dir.join("todo"),
+            ..Default::default()
+        });
+        app.lock(None)?;
+        other.append(String::from("From other"));
+        let locked_write = other.write();
+        other.set_read_only(true);
+        let read_only_write = other.write();
+        app.append(String::from("From locked"));
+        app.write()?;
+        app.unlock();
+        other.set_read_only(false);
+        other.merge_external_changes()?;
+        other.write()?;
+        let notes_count = fs::read_dir(dir.join("notes"))?.count();
+
+        remove_dir_all(dir)?;
+        assert!(locked_write.is_err_and(|error| fileio::Locked::of(&error).is_some()));
+        assert!(read_only_write.is_err());
+        assert_eq!(notes_count, 4);
+        Ok(())
+    }
+
     #[test]
     fn test_delete_todo() -> io::Result<()> {
         let dir = dir("test-delete-todo")?;
@@ -877,8 +1371,12 @@ mod tests {
             .unwrap()
             .map(|dir| dir.map(|entry| entry.path()))
             .collect();
-        let string = fs::read_to_string(&dir.join("todo"))?;
-        let expected_string = String::from("[0] Hello\n[0] Goodbye\n[0] Hello there\n");
+        let string = fs::read_to_string(dir.join("todo"))?;
+        let ids: Vec<&str> = app.todo_list.iter().map(Todo::id).collect();
+        let expected_string = format!(
+            "[0] Hello [id:{}]\n[0] Goodbye [id:{}]\n[0] Hello there [id:{}]\n",
+            ids[0], ids[1], ids[2]
+        );
         remove_dir_all(dir)?;
         assert!(names?.is_empty());
         assert_eq!(string, expected_string);
@@ -901,13 +1399,156 @@ mod tests {
             .collect();
         let expected = vec![PathBuf::from("test-remove-current-dependency-partial/notes/63c5498f09d086fca6d870345350bfb210945790.todo")];
         assert_eq!(names.unwrap(), expected);
-        let string = fs::read_to_string(&dir.join("todo"))?;
-        let expected_string = String::from("[0] Hello\n[0] Goodbye\n[0]>63c5498f09d086fca6d870345350bfb210945790.todo Hello there\n");
+        let string = fs::read_to_string(dir.join("todo"))?;
+        let ids: Vec<&str> = app.todo_list.iter().map(Todo::id).collect();
+        let expected_string = format!(
+            "[0] Hello [id:{}]\n[0] Goodbye [id:{}]\n[0]>63c5498f09d086fca6d870345350bfb210945790.todo Hello there [id:{}]\n",
+            ids[0], ids[1], ids[2]
+        );
         remove_dir_all(dir)?;
         assert_eq!(string, expected_string);
         Ok(())
     }
 
+    #[test]
+    fn test_sort_urgency() {
+        let mut todo_list = TodoList::new();
+        todo_list.set_todo_cmp(SortMethod::Urgency.cmp_function());
+        let mut overdue = Todo::new("overdue".to_string(), 5);
+        overdue.set_due(Some(date::add_days(date::current(), -1)));
+        let mut soon = Todo::new("soon".to_string(), 9);
+        soon.set_due(Some(date::add_days(date::current(), 2)));
+        let mut done = Todo::new("done".to_string(), 1);
+        done.set_due(Some(date::add_days(date::current(), -5)));
+        done.set_done(true);
+        todo_list.push(Todo::new("no due".to_string(), 1));
+        todo_list.push(done);
+        todo_list.push(soon);
+        todo_list.push(overdue);
+        todo_list.sort();
+        assert_eq!(todo_list.messages(&App::no_restriction()), vec!["overdue", "soon", "no due", "done"]);
+    }
+
+    #[test]
+    fn test_tags() -> io::Result<()> {
+        let dir = dir("test-tags")?;
+        let mut app = write_test_todos(&dir)?;
+        remove_dir_all(dir)?;
+        app.append(String::from("Mow the lawn @home +garden"));
+        app.append(String::from("Plant trees +garden"));
+        app.add_dependency_traverse_down();
+        app.append(String::from("Buy seeds @shop +garden"));
+        app.go_root();
+        assert_eq!(
+            app.tag_counts(),
+            vec![
+                (String::from("+garden"), 3),
+                (String::from("@home"), 1),
+                (String::from("@shop"), 1),
+            ]
+        );
+        app.set_restriction(restriction::project("garden"));
+        assert_eq!(app.current_list().len(app.get_restriction()), 2);
+        app.set_restriction(restriction::context("home"));
+        assert_eq!(app.current_list().len(app.get_restriction()), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> io::Result<()> {
+        let dir = dir("test-history")?;
+        let mut app = write_test_todos(&dir)?;
+        app.toggle_current_daily();
+        app.toggle_current_done();
+        let id = app.todo_list.todos[2].id().to_string();
+        app.write()?;
+        let history_path = dir.join("notes").join(format!("{id}.history"));
+        let history = fs::read_to_string(&history_path)?;
+        app.read();
+        let todo = app.todo_by_id(&id).unwrap();
+        assert_eq!(todo.history(), &[date::current()]);
+        assert_eq!(todo.current_streak(), 1);
+        assert_eq!(todo.to_string(), "0- Hello there (Daily, streak 1)");
+        app.toggle_show_done();
+        assert!(app.select_id(&id));
+        app.remove_todo();
+        app.write()?;
+        let exists = history_path.exists();
+        remove_dir_all(dir)?;
+        assert_eq!(history, format!("{}\n", date::format(Some(date::current()))));
+        assert!(!exists);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ids() -> io::Result<()> {
+        let dir = dir("test-ids")?;
+        let mut app = write_test_todos(&dir)?;
+        let id = app.todo_list.todos[2]
+            .dependency
+            .as_ref()
+            .unwrap()
+            .todo_list
+            .todos[0]
+            .id()
+            .to_string();
+        app.read();
+        assert_eq!(app.tree_path_of_id(&id), Some(vec![2, 0]));
+        assert_eq!(app.todo_by_id(&id).unwrap().message, "Is there anybody outthere?");
+        app.todo_by_id_mut(&id).unwrap().message = String::from("Is there anybody out there?");
+        assert!(app.is_changed());
+        assert!(app.select_id(&id));
+        assert_eq!(app.tree_path, vec![2]);
+        assert_eq!(app.todo().unwrap().message, "Is there anybody out there?");
+        app.write()?;
+        app.read();
+        assert_eq!(app.tree_path_of_id(&id), Some(vec![2, 0]));
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_ids() -> io::Result<()> {
+        let dir = dir("test-duplicate-ids")?;
+        let todo_path = dir.join("todo");
+        let content = "[1] first [id:aaaa]\n[1] second [id:aaaa]\n[1] third\n";
+        fs::write(&todo_path, content)?;
+        let mut app = App::new(AppArgs {
+            todo_path: todo_path.clone(),
+            ..Default::default()
+        });
+        let ids: Vec<String> = app.todo_list.iter().map(|todo| todo.id().to_string()).collect();
+        assert_eq!(ids[0], "aaaa");
+        assert!(!ids[1].is_empty() && ids[1] != "aaaa");
+        assert!(!ids[2].is_empty() && ids[2] != ids[1]);
+
+        // Only reading the tree doesn't write the new ids, the next change does
+        assert!(!app.is_changed());
+        assert!(app.write()?.is_empty());
+        assert_eq!(fs::read_to_string(&todo_path)?, content);
+        app.append(String::from("fourth"));
+        app.write()?;
+        app.read();
+        remove_dir_all(dir)?;
+        for id in &ids {
+            assert!(app.todo_by_id(id).is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_paste_todo() -> io::Result<()> {
+        let dir = dir("test-paste-todo")?;
+        let mut app = write_test_todos(&dir)?;
+        remove_dir_all(dir)?;
+        let todo = app.todo().unwrap().clone();
+        app.paste_todo(todo.clone());
+        assert_eq!(app.current_list().len(app.get_restriction()), 4);
+        assert_ne!(app.todo().unwrap().id(), todo.id());
+        assert_eq!(app.todo_by_id(todo.id()), Some(&todo));
+        Ok(())
+    }
+
     #[test]
     fn test_sort_method() -> io::Result<()> {
         let todo_path = dir("test-sort-method")?.join("todo");
@@ -929,8 +1570,7 @@ mod tests {
         if let Some(schedule) = app
             .todo_mut()
             .as_mut()
-            .map(|todo| todo.schedule.as_mut())
-            .flatten()
+            .and_then(|todo| todo.schedule.as_mut())
         {
             schedule.set_current_date();
             schedule.add_days_to_date(-2);
@@ -941,4 +1581,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_targets() -> io::Result<()> {
+        let dir = dir("test-targets")?;
+        let mut app = write_test_todos(&dir)?;
+        let parent = app
+            .todo_list
+            .todos
+            .iter()
+            .position(|todo| todo.message == "Hello there")
+            .unwrap();
+        let nested = app.todo_list.todos[parent].dependency.as_ref().unwrap().todo_list.todos[0].id().to_string();
+        assert_eq!(app.path_of_target(&nested), Ok(vec![parent, 0]));
+        assert_eq!(app.path_of_target(&format!("{parent}/0/")), Ok(vec![parent, 0]));
+        assert!(app.path_of_target(&format!("{parent}/1")).is_err());
+
+        app.current_list_mut().todos[parent].set_done(true);
+        assert!(app.select_path(&[parent]));
+        assert_eq!(app.todo().map(Todo::done), Some(true));
+        assert!(app.select_list(&[parent]));
+        assert_eq!(app.current_list().todos[0].id(), nested);
+        // The deepest todo has a note, it can't have a list too
+        assert!(!app.select_list(&[parent, 0, 0, 0]));
+        assert!(app.select_list(&[]));
+        assert!(app.is_root());
+        remove_dir_all(dir)?;
+        Ok(())
+    }
 }
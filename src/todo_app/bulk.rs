@@ -0,0 +1,302 @@
+use super::{App, Todo};
+
+/// A schedule typed in, like `3`, `2w` or a rule like `weekdays`
+pub(super) enum ScheduleInput {
+    Days(i64),
+    Rule(super::Recurrence),
+}
+
+impl ScheduleInput {
+    pub(super) fn parse(input: &str) -> Option<Self> {
+        let days = match input.strip_suffix('w') {
+            Some(weeks) => weeks.parse::<i64>().map(|weeks| weeks * 7),
+            None => input.parse::<i64>(),
+        };
+        match days {
+            Ok(days) if days <= 0 => None,
+            Ok(days) => Some(Self::Days(days)),
+            Err(_) => input.parse().ok().map(Self::Rule),
+        }
+    }
+
+    pub(super) fn apply(&self, todo: &mut Todo) {
+        match self {
+            Self::Days(days) => todo.enable_day(*days),
+            Self::Rule(rule) => todo.enable_rule(rule.clone()),
+        }
+    }
+}
+
+/// Operations on several todos at once, picked by their ids wherever they are in the tree. Each
+/// one is a single undo step, and leaves the cursor where it was.
+impl App {
+    /// Puts the cursor on the todo with the id, with done todos shown too. Returns false if
+    /// there's no such todo
+    fn point_at_id(&mut self, id: &str) -> bool {
+        match self.todo_list.path_of_id(id) {
+            Some(mut path) => {
+                self.index = path.pop().unwrap_or_default();
+                self.tree_path = path;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs the operation with the cursor on every todo with one of the ids, then puts the
+    /// cursor and the restriction back
+    fn for_each_id(&mut self, ids: &[String], mut operation: impl FnMut(&mut Self)) {
+        let cursor_id = self.todo().map(|todo| todo.id().to_string());
+        let parent_id = self.current_parent_id();
+        let index = self.index;
+        let restriction = self.restriction.clone();
+        self.begin_undo_step();
+        for id in ids {
+            self.restriction = Self::no_restriction();
+            if self.point_at_id(id) {
+                operation(self);
+            }
+        }
+        self.restriction = restriction;
+        self.restore_cursor(cursor_id, parent_id, index);
+    }
+
+    /// Puts the cursor back on the todo it was on, or where it was in its list if the todo is
+    /// gone or hidden now
+    fn restore_cursor(
+        &mut self,
+        cursor_id: Option<String>,
+        parent_id: Option<String>,
+        index: usize,
+    ) {
+        let restriction = self.restriction.clone();
+        let restored = cursor_id.and_then(|id| {
+            let mut tree_path = self.todo_list.path_of_id(&id)?;
+            tree_path.pop();
+            let index = Self::list_at_path(&self.todo_list, &tree_path)
+                .restricted_position_of_id(&id, &restriction)?;
+            Some((tree_path, index))
+        });
+        if let Some((tree_path, index)) = restored {
+            self.tree_path = tree_path;
+            self.index = index;
+            return;
+        }
+        self.tree_path = parent_id
+            .and_then(|id| self.todo_list.path_of_id(&id))
+            .unwrap_or_default();
+        self.index = index;
+        self.fix_index();
+    }
+
+    /// Changes the todo under the cursor through `edit`, recording it and keeping its list
+    /// sorted
+    fn edit_current(&mut self, edit: &impl Fn(&mut Todo)) {
+        self.record_current();
+        if let Some(todo) = self.todo_mut() {
+            edit(todo);
+            self.reorder_current();
+        }
+    }
+
+    pub fn set_done_by_ids(&mut self, ids: &[String], done: bool) {
+        self.for_each_id(ids, |app| app.edit_current(&|todo| todo.set_done(done)));
+    }
+
+    pub fn set_priority_by_ids(&mut self, ids: &[String], priority: u8) {
+        self.for_each_id(ids, |app| {
+            app.edit_current(&|todo| todo.set_priority(priority))
+        });
+    }
+
+    /// Sets the schedule of the todos like `schedule_current`. Returns false if the input isn't
+    /// a schedule
+    pub fn schedule_by_ids(&mut self, ids: &[String], input: &str) -> bool {
+        let Some(schedule) = ScheduleInput::parse(input) else {
+            return false;
+        };
+        self.for_each_id(ids, |app| app.edit_current(&|todo| schedule.apply(todo)));
+        true
+    }
+
+    /// Removes the todos, with their sub todos, into `removed_todos` like `remove_todo`
+    pub fn remove_by_ids(&mut self, ids: &[String]) {
+        self.for_each_id(ids, |app| {
+            app.record_current();
+            let index = app.index;
+            let restriction = app.restriction.clone();
+            let todo = app.current_list_mut().remove(index, &restriction);
+            app.removed_todos.push(todo);
+        });
+    }
+
+    /// Moves the todos, with their sub todos, to the current list. Todos the current list is
+    /// under stay where they are
+    pub fn move_ids_here(&mut self, ids: &[String]) {
+        let target = self.current_parent_id();
+        let target_path = self.tree_path.clone();
+        let ids: Vec<String> = ids
+            .iter()
+            .filter(|id| {
+                self.todo_list.path_of_id(id).is_some_and(|path| {
+                    !target_path.starts_with(&path) && path[..path.len() - 1] != target_path
+                })
+            })
+            .cloned()
+            .collect();
+        self.for_each_id(&ids, |app| {
+            app.record_current();
+            let index = app.index;
+            let restriction = app.restriction.clone();
+            let todo = app.current_list_mut().remove(index, &restriction);
+            // The target's path changes as todos before it are moved out of its way
+            app.tree_path = target
+                .as_ref()
+                .and_then(|id| app.todo_list.path_of_id(id))
+                .unwrap_or_default();
+            app.record_added(todo.id().to_string());
+            let list = app.current_list_mut();
+            list.push(todo);
+            list.reorder_last();
+        });
+    }
+
+    /// Pastes the todos to the current list like `paste_todo`, in one undo step
+    pub fn paste_todos(&mut self, todos: Vec<Todo>) {
+        self.begin_undo_step();
+        for mut todo in todos {
+            if self.todo_list.path_of_id(todo.id()).is_some() {
+                todo.renew();
+            }
+            // A todo cut from this tree is pasted back, its files shouldn't be removed anymore
+            self.removed_todos
+                .retain(|removed| removed.id() != todo.id());
+            self.record_added(todo.id().to_string());
+            let list = self.current_list_mut();
+            list.push(todo);
+            self.index = list.reorder_last();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::{test_helpers::*, TodoList};
+    use crate::AppArgs;
+    use std::fs::remove_dir_all;
+    use std::io;
+
+    fn id_of(todo_list: &TodoList, message: &str) -> Option<String> {
+        todo_list.todos.iter().find_map(|todo| {
+            if todo.message == message {
+                return Some(todo.id().to_string());
+            }
+            id_of(todo.dependency.as_ref()?.todo_list()?, message)
+        })
+    }
+
+    fn ids(app: &App, messages: &[&str]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| id_of(&app.todo_list, message).unwrap())
+            .collect()
+    }
+
+    fn messages(todo_list: &TodoList) -> Vec<&str> {
+        todo_list
+            .todos
+            .iter()
+            .map(|todo| todo.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_bulk() -> io::Result<()> {
+        let dir = dir("test-bulk")?;
+        let mut app = write_test_todos(&dir)?;
+        remove_dir_all(dir)?;
+        app.clear_undo_history();
+        app.index = 0;
+        let cursor = app.todo().unwrap().id().to_string();
+        let nested = ["Goodbye", "Just nod if you can here me"];
+
+        app.set_done_by_ids(&ids(&app, &nested), true);
+        for id in ids(&app, &nested) {
+            assert!(app.todo_by_id(&id).unwrap().done());
+        }
+        assert_eq!(app.todo().unwrap().id(), cursor);
+        assert!(app.undo());
+        for id in ids(&app, &nested) {
+            assert!(!app.todo_by_id(&id).unwrap().done());
+        }
+
+        app.set_priority_by_ids(&ids(&app, &nested), 7);
+        for id in ids(&app, &nested) {
+            assert_eq!(app.todo_by_id(&id).unwrap().priority(), 7);
+        }
+        assert!(app.schedule_by_ids(&ids(&app, &nested), "2w"));
+        assert!(!app.schedule_by_ids(&ids(&app, &nested), "sometimes"));
+        let id = id_of(&app.todo_list, "Goodbye").unwrap();
+        let schedule = app.todo_by_id(&id).unwrap().schedule.as_ref();
+        assert_eq!(schedule.map(|schedule| schedule.days()), Some(14));
+
+        // A todo is moved with its sub todos, but not into itself
+        app.select_id(&id_of(&app.todo_list, "Is there anybody outthere?").unwrap());
+        app.traverse_down();
+        app.move_ids_here(&ids(&app, &["Goodbye", "Hello there"]));
+        let mut moved = messages(app.current_list());
+        moved.sort();
+        assert_eq!(moved, vec!["Goodbye", "Just nod if you can here me"]);
+        assert_eq!(messages(&app.todo_list), vec!["Hello", "Hello there"]);
+        let id = id_of(&app.todo_list, "Is there anyone home").unwrap();
+        assert_eq!(app.tree_path_of_id(&id).map(|path| path.len()), Some(4));
+        assert!(app.undo());
+        assert_eq!(
+            messages(app.current_list()),
+            vec!["Just nod if you can here me"]
+        );
+        assert_eq!(messages(&app.todo_list).len(), 3);
+
+        app.go_root();
+        let removed = app.removed_todos.len();
+        app.remove_by_ids(&ids(&app, &["Hello", "Just nod if you can here me"]));
+        assert_eq!(app.removed_todos.len(), removed + 2);
+        assert_eq!(id_of(&app.todo_list, "Is there anyone home"), None);
+        assert_eq!(messages(&app.todo_list).len(), 2);
+
+        let todos: Vec<Todo> = app.removed_todos.drain(removed..).collect();
+        app.paste_todos(todos.clone());
+        app.paste_todos(todos);
+        assert_eq!(messages(&app.todo_list).len(), 6);
+        assert!(app.undo());
+        assert_eq!(messages(&app.todo_list).len(), 4);
+        Ok(())
+    }
+    #[test]
+    fn test_paste_copy() -> io::Result<()> {
+        let dir = dir("test-paste-copy")?;
+        let mut app = write_test_todos(&dir)?;
+        let id = id_of(&app.todo_list, "Hello there").unwrap();
+        let copy = app.todo_by_id(&id).unwrap().clone();
+        app.paste_todos(vec![copy]);
+        app.write()?;
+
+        // The copy has files of its own, removing the original leaves them
+        app.remove_by_ids(&[id]);
+        app.write()?;
+        let app = App::new(AppArgs {
+            todo_path: dir.join("todo"),
+            ..Default::default()
+        });
+        remove_dir_all(dir)?;
+        assert!(id_of(&app.todo_list, "Hello there").is_some());
+        let home = id_of(&app.todo_list, "Is there anyone home").unwrap();
+        let note = app
+            .todo_by_id(&home)
+            .and_then(|todo| todo.dependency.as_ref())
+            .and_then(|dep| dep.note());
+        assert_eq!(note, Some("Heaven from hell"));
+        Ok(())
+    }
+}
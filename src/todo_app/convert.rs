@@ -0,0 +1,213 @@
+//! Conversion of todo trees to and from the formats of other tools
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use super::todo_list::TodoCmp;
+use super::{App, Todo, TodoList};
+use crate::fileio::invalid_data;
+pub mod ical;
+pub mod json;
+pub mod markdown;
+pub mod org;
+pub mod taskwarrior;
+pub mod todo_txt;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The todo tree as JSON, with sub lists nested in their todos
+    Json,
+    /// todo.txt lines, with sub todos pointing to their todo with `parent:`
+    TodoTxt,
+    /// A Markdown checklist, with sub todos indented under their todo
+    Markdown,
+    /// An iCalendar file with a VTODO for every todo
+    Ical,
+    /// An Org mode outline, with sub todos as deeper headlines
+    Org,
+    /// The JSON of Taskwarrior's `task export`, with a todo for every project. Only imported
+    Taskwarrior,
+}
+
+impl Format {
+    /// The format of a file by its extension
+    pub fn of_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "txt" => Some(Self::TodoTxt),
+            "md" | "markdown" => Some(Self::Markdown),
+            "ics" | "ical" => Some(Self::Ical),
+            "org" => Some(Self::Org),
+            _ => None,
+        }
+    }
+
+    pub fn export(self, todo_list: &TodoList) -> io::Result<String> {
+        let output = match self {
+            Self::Json => json::export(todo_list),
+            Self::TodoTxt => todo_txt::export(todo_list),
+            Self::Markdown => markdown::export(todo_list),
+            Self::Ical => ical::export(todo_list),
+            Self::Org => org::export(todo_list),
+            Self::Taskwarrior => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Taskwarrior files can only be imported",
+                ))
+            }
+        };
+        Ok(output)
+    }
+
+    pub fn import(self, input: &str) -> io::Result<TodoList> {
+        match self {
+            Self::Json => json::import(input),
+            Self::TodoTxt => todo_txt::import(input),
+            Self::Markdown => markdown::import(input),
+            Self::Ical => ical::import(input),
+            Self::Org => org::import(input),
+            Self::Taskwarrior => taskwarrior::import(input),
+        }
+    }
+}
+
+/// Whether the key of another tool can be written as the id of a todo
+#[inline(always)]
+fn is_id(key: &str) -> bool {
+    Todo::is_valid_id(key)
+}
+
+impl App {
+    /// The current list and the lists under it, in the format
+    #[inline]
+    pub fn export(&self, format: Format) -> io::Result<String> {
+        format.export(self.current_list())
+    }
+
+    /// Appends the todos of a file in the format to the current list, with the lists under them.
+    /// Returns how many todos were added to the current list
+    pub fn import(&mut self, path: &Path, format: Format) -> io::Result<usize> {
+        let mut todo_list = format.import(&fs::read_to_string(path)?)?;
+        prepare_imported(&mut todo_list, self.args.sort_method.cmp_function());
+        let count = todo_list.todos.len();
+        self.append_list(todo_list);
+        self.current_list_mut().sort();
+        self.fix_index();
+        Ok(count)
+    }
+}
+
+/// Sorts the imported lists like read ones are, and makes all of them get written
+fn prepare_imported(todo_list: &mut TodoList, todo_cmp: TodoCmp) {
+    todo_list.set_todo_cmp(todo_cmp);
+    todo_list.sort();
+    todo_list.changed = true;
+    for todo in &mut todo_list.todos {
+        if let Some(dependency) = todo.dependency.as_mut() {
+            prepare_imported(&mut dependency.todo_list, todo_cmp);
+        }
+    }
+}
+
+/// A todo of a format that has all todos in one list, with the key of its parent
+pub(super) struct FlatTodo {
+    pub todo: Todo,
+    /// What the sub todos name the todo with
+    pub key: Option<String>,
+    pub parent: Option<String>,
+}
+
+/// Builds the tree of the todos, putting each todo in the sub list of its parent. Todos with a
+/// missing parent, or one that would make a loop, go to the top
+pub(super) fn nest(todos: Vec<FlatTodo>) -> TodoList {
+    let positions: HashMap<&str, usize> = todos
+        .iter()
+        .enumerate()
+        .filter_map(|(index, flat)| flat.key.as_deref().map(|key| (key, index)))
+        .collect();
+    let mut parents: Vec<Option<usize>> = vec![None; todos.len()];
+    let mut children: HashMap<Option<usize>, Vec<usize>> = HashMap::new();
+    for (index, flat) in todos.iter().enumerate() {
+        let parent = flat
+            .parent
+            .as_deref()
+            .and_then(|parent| positions.get(parent).copied())
+            .filter(|&parent| !is_under(parent, index, &parents));
+        parents[index] = parent;
+        children.entry(parent).or_default().push(index);
+    }
+    let mut todos: Vec<Option<Todo>> = todos.into_iter().map(|flat| Some(flat.todo)).collect();
+    build_list(None, &children, &mut todos)
+}
+
+/// Whether the todo at `index` is `ancestor` or under it
+fn is_under(index: usize, ancestor: usize, parents: &[Option<usize>]) -> bool {
+    let mut current = Some(index);
+    while let Some(index) = current {
+        if index == ancestor {
+            return true;
+        }
+        current = parents[index];
+    }
+    false
+}
+
+fn build_list(
+    index: Option<usize>,
+    children: &HashMap<Option<usize>, Vec<usize>>,
+    todos: &mut [Option<Todo>],
+) -> TodoList {
+    let mut todo_list = TodoList::new();
+    for &child in children.get(&index).into_iter().flatten() {
+        let Some(mut todo) = todos[child].take() else {
+            continue;
+        };
+        if children.contains_key(&Some(child)) {
+            // A todo can't have both, the sub todos are kept over the note
+            todo.remove_dependency();
+            todo.add_unique_todo_dependency();
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list = build_list(Some(child), children, todos);
+            }
+        }
+        todo_list.todos.push(todo);
+    }
+    todo_list
+}
+
+/// Builds the tree of an outline, where the todos under a todo come right after it with a
+/// bigger depth. Todos less deep than the ones before their list start over at the top
+pub(super) fn nest_outline(items: Vec<(usize, Todo)>) -> TodoList {
+    let mut items = items.into_iter().peekable();
+    let mut todo_list = TodoList::new();
+    while let Some(&(depth, _)) = items.peek() {
+        todo_list.todos.append(&mut outline_list(&mut items, depth).todos);
+    }
+    todo_list
+}
+
+fn outline_list<I>(items: &mut Peekable<I>, depth: usize) -> TodoList
+where
+    I: Iterator<Item = (usize, Todo)>,
+{
+    let mut todo_list = TodoList::new();
+    while let Some((_, mut todo)) = items.next_if(|(item_depth, _)| *item_depth == depth) {
+        let sub_depth = items
+            .peek()
+            .map(|(item_depth, _)| *item_depth)
+            .filter(|&sub_depth| sub_depth > depth);
+        if let Some(sub_depth) = sub_depth {
+            todo.remove_dependency();
+            todo.add_unique_todo_dependency();
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list = outline_list(items, sub_depth);
+            }
+        }
+        todo_list.todos.push(todo);
+    }
+    todo_list
+}
@@ -0,0 +1,443 @@
+//! iCalendar (`.ics`) files with a `VTODO` for every todo:
+//!
+//! - `SUMMARY` is the message, `DESCRIPTION` the note and `PRIORITY` the priority, which is 1
+//!   (highest) to 9 in both
+//! - `STATUS:COMPLETED` marks done todos
+//! - `DUE` is the due date, or the date of a reminder without one. Reminders also have an
+//!   `X-C3-REMINDER` date, so they're imported as reminders again
+//! - `RRULE` is a repeating schedule, with `DTSTART` the date it was last done in
+//! - sub todos point to the `UID` of their todo with `RELATED-TO`
+use chrono::{Utc, Weekday};
+use std::io;
+
+use super::{invalid_data, is_id, nest, FlatTodo};
+use crate::date;
+use crate::todo_app::{Recurrence, Schedule, Todo, TodoList};
+
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "MO"),
+    (Weekday::Tue, "TU"),
+    (Weekday::Wed, "WE"),
+    (Weekday::Thu, "TH"),
+    (Weekday::Fri, "FR"),
+    (Weekday::Sat, "SA"),
+    (Weekday::Sun, "SU"),
+];
+
+/// Lines longer than this many bytes are folded
+const LINE_LENGTH: usize = 75;
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    WEEKDAYS
+        .iter()
+        .find(|(day, _)| *day == weekday)
+        .map_or("MO", |(_, code)| code)
+}
+
+fn code_weekday(code: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(_, day_code)| day_code.eq_ignore_ascii_case(code))
+        .map(|(day, _)| *day)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[inline(always)]
+fn format_date(date: date::Type) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// The date of a `DATE` or `DATE-TIME` value
+fn parse_date(value: &str) -> io::Result<date::Type> {
+    value
+        .get(..8)
+        .and_then(|date| date::Type::parse_from_str(date, "%Y%m%d").ok())
+        .ok_or_else(|| invalid_data(format!("\"{value}\" isn't a date")))
+}
+
+fn rrule(schedule: &Schedule) -> Option<String> {
+    let rule = match schedule.rule() {
+        Some(Recurrence::Weekdays(days)) => {
+            let days: Vec<&str> = days.iter().copied().map(weekday_code).collect();
+            format!("FREQ=WEEKLY;BYDAY={}", days.join(","))
+        }
+        Some(Recurrence::MonthDays(days)) => {
+            let days: Vec<String> = days.iter().map(i8::to_string).collect();
+            format!("FREQ=MONTHLY;BYMONTHDAY={}", days.join(","))
+        }
+        Some(Recurrence::MonthWeekday(nth, weekday)) => {
+            format!("FREQ=MONTHLY;BYDAY={nth}{}", weekday_code(*weekday))
+        }
+        None => match schedule.days() {
+            0 => return None,
+            7 => String::from("FREQ=WEEKLY"),
+            days if days % 7 == 0 => format!("FREQ=WEEKLY;INTERVAL={}", days / 7),
+            1 => String::from("FREQ=DAILY"),
+            days => format!("FREQ=DAILY;INTERVAL={days}"),
+        },
+    };
+    Some(rule)
+}
+
+fn parse_rrule(value: &str) -> Option<Schedule> {
+    let mut freq = "";
+    let mut interval = 1;
+    let mut by_day: Vec<&str> = vec![];
+    let mut by_month_day: Vec<i8> = vec![];
+    for part in value.split(';') {
+        let (key, value) = part.split_once('=')?;
+        match key.to_uppercase().as_str() {
+            "FREQ" => freq = value,
+            "INTERVAL" => interval = value.parse().ok()?,
+            "BYDAY" => by_day = value.split(',').collect(),
+            "BYMONTHDAY" => {
+                by_month_day = value
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .ok()?
+            }
+            _ => {}
+        }
+    }
+    let rule = match (freq.to_uppercase().as_str(), &by_day[..]) {
+        ("DAILY", _) => return Some(Schedule::new(interval)),
+        ("WEEKLY", []) => return Some(Schedule::new(interval * 7)),
+        ("WEEKLY", days) if interval == 1 => {
+            let mut days = days
+                .iter()
+                .map(|day| code_weekday(day))
+                .collect::<Option<Vec<_>>>()?;
+            days.sort_by_key(Weekday::num_days_from_monday);
+            days.dedup();
+            Recurrence::Weekdays(days)
+        }
+        ("MONTHLY", []) if !by_month_day.is_empty() && interval == 1 => {
+            by_month_day.sort();
+            by_month_day.dedup();
+            Recurrence::MonthDays(by_month_day)
+        }
+        ("MONTHLY", [day]) if interval == 1 => {
+            let (nth, code) = day.split_at_checked(day.len().checked_sub(2)?)?;
+            Recurrence::MonthWeekday(nth.parse().ok()?, code_weekday(code)?)
+        }
+        ("MONTHLY", _) => return Some(Schedule::new(interval * 30)),
+        ("YEARLY", _) => return Some(Schedule::new(interval * 365)),
+        _ => return None,
+    };
+    Some(Schedule::new_rule(rule))
+}
+
+/// Writes the line folded to lines of at most `LINE_LENGTH` bytes
+fn push_line(lines: &mut Vec<String>, line: String) {
+    let mut rest = line.as_str();
+    let mut first = true;
+    while !rest.is_empty() {
+        // Folded lines start with a space, that counts in their length
+        let limit = if first { LINE_LENGTH } else { LINE_LENGTH - 1 };
+        let mut end = rest.len().min(limit);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let prefix = if first { "" } else { " " };
+        lines.push(format!("{prefix}{}", &rest[..end]));
+        rest = &rest[end..];
+        first = false;
+    }
+}
+
+fn write_list(todo_list: &TodoList, parent: Option<&str>, stamp: &str, lines: &mut Vec<String>) {
+    for todo in &todo_list.todos {
+        let uid = if todo.has_id() {
+            todo.id().to_string()
+        } else {
+            todo.hash()
+        };
+        push_line(lines, String::from("BEGIN:VTODO"));
+        push_line(lines, format!("UID:{uid}"));
+        push_line(lines, format!("DTSTAMP:{stamp}"));
+        push_line(lines, format!("SUMMARY:{}", escape(&todo.message)));
+        let status = if todo.done() {
+            "COMPLETED"
+        } else {
+            "NEEDS-ACTION"
+        };
+        push_line(lines, format!("STATUS:{status}"));
+        if todo.priority() != 0 {
+            push_line(lines, format!("PRIORITY:{}", todo.priority()));
+        }
+        let schedule = todo.schedule.as_ref();
+        let reminder = schedule
+            .filter(|schedule| schedule.is_reminder())
+            .and_then(Schedule::date);
+        if let Some(due) = todo.due.or(reminder) {
+            push_line(lines, format!("DUE;VALUE=DATE:{}", format_date(due)));
+        }
+        if let Some(reminder) = reminder {
+            push_line(
+                lines,
+                format!("X-C3-REMINDER;VALUE=DATE:{}", format_date(reminder)),
+            );
+        }
+        if let Some(rule) = schedule
+            .filter(|schedule| !schedule.is_reminder())
+            .and_then(rrule)
+        {
+            if let Some(date) = schedule.and_then(Schedule::date) {
+                push_line(lines, format!("DTSTART;VALUE=DATE:{}", format_date(date)));
+            }
+            push_line(lines, format!("RRULE:{rule}"));
+        }
+        let dependency = todo.dependency.as_ref();
+        if let Some(note) = dependency.and_then(|dep| dep.note()) {
+            push_line(lines, format!("DESCRIPTION:{}", escape(note)));
+        }
+        if let Some(parent) = parent {
+            push_line(lines, format!("RELATED-TO;RELTYPE=PARENT:{parent}"));
+        }
+        push_line(lines, String::from("END:VTODO"));
+        if let Some(sub_list) = dependency.and_then(|dep| dep.todo_list()) {
+            write_list(sub_list, Some(&uid), stamp, lines);
+        }
+    }
+}
+
+pub fn export(todo_list: &TodoList) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        format!("PRODID:-//c3//c3 {}//EN", env!("CARGO_PKG_VERSION")),
+    ];
+    write_list(todo_list, None, &stamp, &mut lines);
+    lines.push(String::from("END:VCALENDAR"));
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Properties of a `VTODO` c3 knows
+#[derive(Default)]
+struct VTodo {
+    uid: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    done: bool,
+    priority: u8,
+    due: Option<date::Type>,
+    start: Option<date::Type>,
+    rrule: Option<String>,
+    reminder: Option<date::Type>,
+    parent: Option<String>,
+}
+
+/// Name, parameters and value of a content line
+fn split_property(line: &str) -> Option<(String, &str, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(index, c)| {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return Some(index),
+            _ => {}
+        }
+        None
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let (name, params) = head.split_once(';').unwrap_or((head, ""));
+    Some((name.to_uppercase(), params, value))
+}
+
+impl VTodo {
+    fn set(&mut self, name: &str, params: &str, value: &str) -> io::Result<()> {
+        match name {
+            "UID" => self.uid = Some(value.to_string()),
+            "SUMMARY" => self.summary = Some(unescape(value)),
+            "DESCRIPTION" => self.description = Some(unescape(value)),
+            "STATUS" => self.done = value.eq_ignore_ascii_case("COMPLETED"),
+            "COMPLETED" => self.done = true,
+            "PRIORITY" => self.priority = value.trim().parse::<u8>().unwrap_or(0).min(9),
+            "DUE" => self.due = Some(parse_date(value)?),
+            "DTSTART" => self.start = Some(parse_date(value)?),
+            "RRULE" => self.rrule = Some(value.to_string()),
+            "X-C3-REMINDER" => self.reminder = Some(parse_date(value)?),
+            // Other relations, like siblings, aren't kept
+            "RELATED-TO"
+                if params.is_empty() || params.to_uppercase().contains("RELTYPE=PARENT") =>
+            {
+                self.parent = Some(value.to_string())
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn into_flat(self) -> io::Result<FlatTodo> {
+        let message = self
+            .summary
+            .filter(|summary| !summary.trim().is_empty())
+            .ok_or_else(|| invalid_data("a VTODO has no SUMMARY"))?;
+        let mut schedule = self.rrule.as_deref().and_then(parse_rrule);
+        if let Some(schedule) = schedule.as_mut() {
+            schedule.set_date(self.start);
+        }
+        let mut due = self.due;
+        if let Some(reminder) = self.reminder {
+            schedule = Some(Schedule::new_reminder(reminder));
+            due = due.filter(|&due| due != reminder);
+        }
+        let id = self
+            .uid
+            .clone()
+            .filter(|uid| is_id(uid))
+            .unwrap_or_default();
+        let mut todo =
+            Todo::from_parts(id, message, self.priority, self.done, schedule, due, vec![]);
+        if !todo.has_id() {
+            todo.renew_id();
+        }
+        if let Some(note) = self.description.filter(|note| !note.is_empty()) {
+            todo.set_note(note)?;
+        }
+        Ok(FlatTodo {
+            todo,
+            key: self.uid,
+            parent: self.parent,
+        })
+    }
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let unfolded = input
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    let mut todos = vec![];
+    let mut current: Option<VTodo> = None;
+    // Components inside the VTODO, like VALARM, whose properties aren't the todo's
+    let mut inner_depth = 0;
+    for line in unfolded.lines() {
+        let Some((name, params, value)) = split_property(line) else {
+            continue;
+        };
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                current = Some(VTodo::default())
+            }
+            ("BEGIN", Some(_)) => inner_depth += 1,
+            ("END", Some(_)) if inner_depth > 0 => inner_depth -= 1,
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => {
+                if let Some(vtodo) = current.take() {
+                    todos.push(vtodo.into_flat()?);
+                }
+            }
+            (name, Some(vtodo)) if inner_depth == 0 => vtodo.set(name, params, value)?,
+            _ => {}
+        }
+    }
+    Ok(nest(todos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() -> io::Result<()> {
+        let input = "\
+BEGIN:VCALENDAR\r
+BEGIN:VTODO\r
+UID:parent-1\r
+SUMMARY:Plan the trip\\, soon\r
+PRIORITY:2\r
+DUE:20240501T120000Z\r
+BEGIN:VALARM\r
+DESCRIPTION:Not the note\r
+END:VALARM\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:child\r
+SUMMARY:Book the \r
+ hotel\r
+STATUS:COMPLETED\r
+DESCRIPTION:Line one\\nLine two\r
+RELATED-TO:parent-1\r
+RRULE:FREQ=MONTHLY;BYDAY=-1FR\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+        let list = import(input)?;
+        let parent = &list.todos[0];
+        assert_eq!(parent.message, "Plan the trip, soon");
+        assert_eq!(parent.priority(), 2);
+        assert_eq!(parent.due, date::parse("2024-05-01").ok());
+        let child = &parent
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap()
+            .todos[0];
+        assert_eq!(child.message, "Book the hotel");
+        assert!(child.done());
+        assert_eq!(
+            child.dependency.as_ref().and_then(|dep| dep.note()),
+            Some("Line one\nLine two")
+        );
+        let rule = child.schedule.as_ref().and_then(Schedule::rule);
+        assert_eq!(rule, Some(&Recurrence::MonthWeekday(-1, Weekday::Fri)));
+        assert!(import("BEGIN:VTODO\nDUE:tomorrow\nEND:VTODO").is_err());
+        let list = import("BEGIN:VTODO\nSUMMARY:Two\\nlines\nEND:VTODO")?;
+        assert_eq!(list.todos[0].message, "Two lines");
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let mut todo_list = TodoList::new();
+        let mut weekly = Todo::new(String::from("Water the plants; all of them"), 3);
+        weekly.enable_rule("mon,thu".parse().unwrap());
+        weekly.set_done(true);
+        let mut reminder = Todo::new(String::from("Call mom"), 0);
+        reminder.schedule = Some(Schedule::new_reminder(date::current()));
+        reminder.set_note(String::from("About\nthe trip")).unwrap();
+        let mut every = Todo::new("A long message ".repeat(10), 1);
+        every.enable_day(14);
+        every.due = Some(date::current());
+        every.add_todo_dependency();
+        every.dependency.as_mut().unwrap().todo_list.todos = vec![reminder];
+        todo_list.todos = vec![weekly, every];
+
+        // The stamps of two exports can be a second apart
+        let export_unstamped = |todo_list: &TodoList| {
+            export(todo_list)
+                .lines()
+                .filter(|line| !line.starts_with("DTSTAMP:"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let exported = export_unstamped(&todo_list);
+        assert!(exported.lines().all(|line| line.len() <= LINE_LENGTH));
+        assert_eq!(export_unstamped(&import(&export(&todo_list))?), exported);
+        Ok(())
+    }
+}
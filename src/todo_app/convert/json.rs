@@ -0,0 +1,292 @@
+//! The todo tree as JSON. Every todo is an object with its sub todos nested in `todos`, keeping
+//! everything a todo file and the `notes` directory hold:
+//!
+//! ```json
+//! {
+//!   "todos": [
+//!     {
+//!       "id": "0123456789ab",
+//!       "message": "Water the plants",
+//!       "priority": 2,
+//!       "done": false,
+//!       "schedule": { "mode": "scheduled", "day": 3, "date": "2024-05-01", "rule": null },
+//!       "due": null,
+//!       "history": ["2024-04-28", "2024-05-01"],
+//!       "note": null,
+//!       "todos": null,
+//!       "extra": []
+//!     }
+//!   ]
+//! }
+//! ```
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use super::{invalid_data, is_id};
+use crate::date;
+use crate::todo_app::{Schedule, Todo, TodoList};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonList {
+    todos: Vec<JsonTodo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JsonScheduleMode {
+    Scheduled,
+    Reminder,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonSchedule {
+    mode: JsonScheduleMode,
+    /// Days between repeats, 0 for reminders
+    #[serde(default)]
+    day: i64,
+    /// Date it was last done in, or the date of a reminder
+    #[serde(default)]
+    date: Option<String>,
+    /// A calendar rule like `week(mon,thu)`, `weekdays` or `month(1)`
+    #[serde(default)]
+    rule: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonTodo {
+    #[serde(default)]
+    id: String,
+    message: String,
+    #[serde(default)]
+    priority: u8,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    schedule: Option<JsonSchedule>,
+    #[serde(default)]
+    due: Option<String>,
+    /// Dates a repeating todo was done in
+    #[serde(default)]
+    history: Vec<String>,
+    #[serde(default)]
+    note: Option<String>,
+    /// The sub list, if the todo has one, even an empty one
+    #[serde(default)]
+    todos: Option<Vec<JsonTodo>>,
+    /// Items of the todo line this version of c3 doesn't know, kept as they are
+    #[serde(default)]
+    extra: Vec<String>,
+}
+
+#[inline(always)]
+fn format_date(date: date::Type) -> String {
+    date::format(Some(date))
+}
+
+#[inline(always)]
+fn parse_date(input: &str) -> io::Result<date::Type> {
+    date::parse(input).map_err(|_| invalid_data(format!("\"{input}\" isn't a YYYY-MM-DD date")))
+}
+
+impl From<&Schedule> for JsonSchedule {
+    fn from(schedule: &Schedule) -> Self {
+        let (mode, day) = if schedule.is_reminder() {
+            (JsonScheduleMode::Reminder, 0)
+        } else {
+            (JsonScheduleMode::Scheduled, schedule.days())
+        };
+        Self {
+            mode,
+            day,
+            date: schedule.date().map(format_date),
+            rule: schedule.rule().map(String::from),
+        }
+    }
+}
+
+impl TryFrom<JsonSchedule> for Schedule {
+    type Error = io::Error;
+
+    fn try_from(json: JsonSchedule) -> io::Result<Self> {
+        let date = json.date.as_deref().map(parse_date).transpose()?;
+        let mut schedule = match json.mode {
+            JsonScheduleMode::Reminder => {
+                let date = date.ok_or_else(|| invalid_data("a reminder needs a date"))?;
+                return Ok(Schedule::new_reminder(date));
+            }
+            JsonScheduleMode::Scheduled => Schedule::new(json.day),
+        };
+        if let Some(rule) = json.rule {
+            let rule = rule
+                .parse()
+                .map_err(|_| invalid_data(format!("\"{rule}\" isn't a schedule rule")))?;
+            schedule.set_rule(rule);
+        }
+        schedule.set_date(date);
+        Ok(schedule)
+    }
+}
+
+impl From<&Todo> for JsonTodo {
+    fn from(todo: &Todo) -> Self {
+        let dependency = todo.dependency.as_ref();
+        Self {
+            id: todo.id().to_string(),
+            message: todo.message.clone(),
+            priority: todo.priority(),
+            done: todo.done(),
+            schedule: todo.schedule.as_ref().map(JsonSchedule::from),
+            due: todo.due.map(format_date),
+            history: todo.history().iter().copied().map(format_date).collect(),
+            note: dependency.and_then(|dep| dep.note()).map(String::from),
+            todos: dependency.and_then(|dep| dep.todo_list()).map(json_todos),
+            extra: todo.unknown_suffix_items().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<JsonTodo> for Todo {
+    type Error = io::Error;
+
+    fn try_from(json: JsonTodo) -> io::Result<Self> {
+        if json.message.trim().is_empty() {
+            return Err(invalid_data("a todo has an empty message"));
+        }
+        let schedule = json.schedule.map(Schedule::try_from).transpose()?;
+        let due = json.due.as_deref().map(parse_date).transpose()?;
+        let id = if is_id(&json.id) {
+            json.id
+        } else {
+            String::new()
+        };
+        let mut todo = Todo::from_parts(
+            id,
+            json.message,
+            json.priority,
+            json.done,
+            schedule,
+            due,
+            json.extra,
+        );
+        if !todo.has_id() {
+            todo.renew_id();
+        }
+        for date in &json.history {
+            todo.add_history(parse_date(date)?);
+        }
+        if let Some(note) = json.note {
+            todo.set_note(note)?;
+        } else if let Some(todos) = json.todos {
+            todo.add_unique_todo_dependency();
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list = todo_list(todos)?;
+            }
+        }
+        Ok(todo)
+    }
+}
+
+fn json_todos(todo_list: &TodoList) -> Vec<JsonTodo> {
+    todo_list.todos.iter().map(JsonTodo::from).collect()
+}
+
+fn todo_list(todos: Vec<JsonTodo>) -> io::Result<TodoList> {
+    let mut todo_list = TodoList::new();
+    todo_list.todos = todos
+        .into_iter()
+        .map(Todo::try_from)
+        .collect::<io::Result<_>>()?;
+    Ok(todo_list)
+}
+
+/// The list and the lists under it as pretty printed JSON
+pub fn export(todo_list: &TodoList) -> String {
+    let json = JsonList {
+        todos: json_todos(todo_list),
+    };
+    serde_json::to_string_pretty(&json).unwrap_or_default()
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let json: JsonList = serde_json::from_str(input).map_err(invalid_data)?;
+    todo_list(json.todos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::convert::Format;
+    use crate::todo_app::{test_helpers::*, App};
+    use crate::AppArgs;
+    use std::fs::{self, remove_dir_all};
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let dir = dir("test-json-round-trip")?;
+        let mut app = write_test_todos(&dir)?;
+        app.todo_list.todos[0].enable_rule("mon,thu".parse().unwrap());
+        app.todo_list.todos[0].set_done(true);
+        app.todo_list.todos[1].due = Some(date::current());
+        app.todo_list.sort();
+        let exported = export(&app.todo_list);
+        let imported = import(&exported)?;
+        // The files of the sub lists get new names
+        let line = |todo: &Todo| {
+            let name = todo.dependency.as_ref().map_or("", |dep| dep.name());
+            String::from(todo).replacen(name, "", 1)
+        };
+        let lines = |list: &TodoList| list.todos.iter().map(line).collect::<Vec<_>>();
+        assert_eq!(lines(&imported), lines(&app.todo_list));
+        assert_eq!(
+            imported.todos[0].history(),
+            app.todo_list.todos[0].history()
+        );
+        assert_eq!(export(&imported), exported);
+
+        // Imported in an empty tree, written and read back, it's still the same tree
+        let json_path = dir.join("todos.json");
+        fs::write(&json_path, &exported)?;
+        let tree_dir = dir.join("imported");
+        fs::create_dir_all(tree_dir.join("notes"))?;
+        let args = || AppArgs {
+            todo_path: tree_dir.join("todo"),
+            ..Default::default()
+        };
+        let mut tree = App::new(args());
+        assert_eq!(
+            tree.import(&json_path, Format::Json)?,
+            app.todo_list.todos.len()
+        );
+        tree.write()?;
+        assert_eq!(export(&App::new(args()).todo_list), exported);
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_errors() {
+        assert!(import("{\"todos\": [{\"message\": \"Hi\", \"due\": \"tomorrow\"}]}").is_err());
+        assert!(import("{\"todos\": [{\"message\": \"\"}]}").is_err());
+        assert!(import("[").is_err());
+        let list = import(r#"{"todos": [{"message": "Two\nlines", "extra": ["a\nb"]}]}"#).unwrap();
+        assert_eq!(String::from(&list.todos[0]).lines().count(), 1);
+        assert_eq!(list.todos[0].message, "Two lines");
+        let input = r#"{"todos": [{"message": "A", "id": "../x"}, {"message": "B", "id": "b c"}]}"#;
+        let list = import(input).unwrap();
+        for todo in &list.todos {
+            assert!(todo.has_id());
+            assert!(Todo::is_valid_id(todo.id()));
+        }
+        let input = r#"{"todos": [{"message": "A", "todos": []}, {"message": "A", "todos": []}]}"#;
+        let list = import(input).unwrap();
+        let name = |todo: &Todo| todo.dependency.as_ref().map(|dep| dep.name().to_string());
+        assert_ne!(name(&list.todos[0]), name(&list.todos[1]));
+        let list = import("{\"todos\": [{\"message\": \"Hi\", \"todos\": []}]}").unwrap();
+        assert!(list.todos[0]
+            .dependency
+            .as_ref()
+            .unwrap()
+            .todo_list()
+            .is_some());
+    }
+}
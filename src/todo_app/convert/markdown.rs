@@ -0,0 +1,205 @@
+//! Markdown checklists. Sub todos are indented under their todo, and notes are blockquotes
+//! indented like sub todos:
+//!
+//! ```markdown
+//! - [ ] Release
+//!   - [x] Write the changelog
+//!     > Keep it short
+//!   - [ ] Tag the commit
+//! ```
+//!
+//! Importing also takes plain `-`, `*` and `+` items as todos that aren't done, and skips the
+//! other lines, like headings and paragraphs. A todo can't have both a note and sub todos, so
+//! the note of a todo with sub todos is left out.
+use std::io;
+
+use super::nest_outline;
+use crate::todo_app::{Todo, TodoList};
+
+const INDENT: &str = "  ";
+
+fn write_list(todo_list: &TodoList, depth: usize, lines: &mut Vec<String>) {
+    let indent = INDENT.repeat(depth);
+    for todo in &todo_list.todos {
+        let check = if todo.done() { 'x' } else { ' ' };
+        lines.push(format!("{indent}- [{check}] {}", todo.message));
+        let Some(dependency) = todo.dependency.as_ref() else {
+            continue;
+        };
+        if let Some(note) = dependency.note() {
+            for line in note.lines() {
+                let line = format!("{indent}{INDENT}> {line}");
+                lines.push(line.trim_end().to_string());
+            }
+        } else if let Some(sub_list) = dependency.todo_list() {
+            write_list(sub_list, depth + 1, lines);
+        }
+    }
+}
+
+pub fn export(todo_list: &TodoList) -> String {
+    let mut lines = vec![];
+    write_list(todo_list, 0, &mut lines);
+    lines.join("\n")
+}
+
+/// Width of the indentation of the line, a tab being 4 spaces
+#[inline(always)]
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// The done state and message of a list item line
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let rest = line
+        .trim_start()
+        .strip_prefix(['-', '*', '+'])?
+        .strip_prefix([' ', '\t'])?
+        .trim_start();
+    let (done, message) = match rest.get(..3) {
+        Some("[ ]") => (false, &rest[3..]),
+        Some("[x]" | "[X]") => (true, &rest[3..]),
+        _ => (false, rest),
+    };
+    let message = message.trim();
+    (!message.is_empty()).then_some((done, message))
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let mut items: Vec<(usize, Todo, Vec<String>)> = vec![];
+    // Indentation of the items each depth is opened by
+    let mut indents: Vec<usize> = vec![];
+    for line in input.lines() {
+        if let Some(quote) = line.trim_start().strip_prefix('>') {
+            if let Some((_, _, note)) = items.last_mut() {
+                let quote = quote.strip_prefix(' ').unwrap_or(quote);
+                note.push(quote.trim_end().to_string());
+            }
+            continue;
+        }
+        let Some((done, message)) = list_item(line) else {
+            continue;
+        };
+        let indent = indent_width(line);
+        while indents.last().is_some_and(|&last| last > indent) {
+            indents.pop();
+        }
+        if indents.last() != Some(&indent) {
+            indents.push(indent);
+        }
+        let mut todo = Todo::new(message.to_string(), 0);
+        todo.set_done(done);
+        items.push((indents.len() - 1, todo, vec![]));
+    }
+    Ok(nest_outline(
+        items
+            .into_iter()
+            .map(|(depth, mut todo, note)| {
+                if !note.is_empty() {
+                    let _ = todo.set_note(note.join("\n"));
+                }
+                (depth, todo)
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::convert::Format;
+    use crate::todo_app::{test_helpers::*, App};
+    use crate::AppArgs;
+    use std::fs::{self, remove_dir_all};
+
+    #[test]
+    fn test_import() -> io::Result<()> {
+        let input = "\
+# Release plan
+
+Some words about it.
+
+- [ ] Release
+  - [x] Write the changelog
+    > Keep it short
+    >
+    > And sweet
+  - Tag the commit
+\t* [X] Push it
++ [ ] Celebrate
+- not a [ ] checkbox
+";
+        let list = import(input)?;
+        let release = &list.todos[0];
+        assert_eq!(release.message, "Release");
+        let sub_list = release
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap();
+        assert!(sub_list.todos[0].done());
+        let note = sub_list.todos[0]
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.note());
+        assert_eq!(note, Some("Keep it short\n\nAnd sweet"));
+        let tag = &sub_list.todos[1];
+        assert!(!tag.done());
+        let push = &tag
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap()
+            .todos[0];
+        assert!(push.done());
+        let messages: Vec<&str> = list
+            .todos
+            .iter()
+            .map(|todo| todo.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Release", "Celebrate", "not a [ ] checkbox"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let input = "\
+- [ ] Release
+  - [x] Write the changelog
+    > Keep it short
+    >
+    > And sweet
+  - [ ] Tag the commit
+    - [x] Push it
+- [ ] Celebrate";
+        assert_eq!(export(&import(input)?), input);
+        Ok(())
+    }
+    #[test]
+    fn test_same_sub_todos() -> io::Result<()> {
+        let input = "\
+- [ ] Feature A
+  - [ ] Tests
+    - [ ] unit A
+- [ ] Feature B
+  - [ ] Tests
+    - [ ] unit B";
+        let dir = dir("test-markdown-same-sub-todos")?;
+        let markdown_path = dir.join("todos.md");
+        fs::write(&markdown_path, input)?;
+        fs::create_dir_all(dir.join("notes"))?;
+        let args = || AppArgs {
+            todo_path: dir.join("todo"),
+            ..Default::default()
+        };
+        let mut app = App::new(args());
+        app.import(&markdown_path, Format::Markdown)?;
+        app.write()?;
+        assert_eq!(export(&App::new(args()).todo_list), input);
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+}
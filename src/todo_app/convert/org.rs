@@ -0,0 +1,506 @@
+//! [Org mode](https://orgmode.org) outlines, with a headline for every todo and sub todos as
+//! deeper headlines:
+//!
+//! ```org
+//! * TODO [#A] Release
+//! DEADLINE: <2024-05-10 Fri>
+//! ** DONE Write the changelog
+//! :PROPERTIES:
+//! :ID: changelog
+//! :END:
+//! Keep it short
+//! ** TODO Water the plants
+//! SCHEDULED: <2024-05-01 Wed +3d>
+//! ```
+//!
+//! - `TODO` and `DONE` are the done state, and `[#A]` to `[#I]` the priorities 1 to 9
+//! - `SCHEDULED:` is a reminder, or a repeating schedule when it has a repeater like `+3d`, with
+//!   the date it was last done in. `DEADLINE:` is the due date
+//! - the `ID` property is the id of the todo, and `C3_RULE` keeps the calendar rules of c3.
+//!   `C3_REPEAT` is the repeater of a schedule that has no date yet, which `SCHEDULED:` needs
+//! - the text under a headline is the note of the todo
+//!
+//! Importing takes headlines without a keyword as todos that aren't done, `CANCELED` ones as
+//! done, and the tags of a headline as `+tag`s of the message. A todo can't have both a note and
+//! sub todos, so the note of a todo with sub todos is left out.
+use std::io;
+
+use super::{invalid_data, is_id, nest_outline};
+use crate::date;
+use crate::todo_app::{Recurrence, Schedule, Todo, TodoList};
+
+const PRIORITY_LETTERS: &str = "ABCDEFGHI";
+
+#[inline(always)]
+fn timestamp(date: date::Type, repeater: Option<&str>) -> String {
+    let day = date.format("%Y-%m-%d %a");
+    match repeater {
+        Some(repeater) => format!("<{day} {repeater}>"),
+        None => format!("<{day}>"),
+    }
+}
+
+/// The repeater like `+3d` of a repeating schedule
+fn repeater(schedule: &Schedule) -> Option<String> {
+    if schedule.is_reminder() {
+        return None;
+    }
+    match schedule.days() {
+        0 => None,
+        days if days % 7 == 0 => Some(format!("+{}w", days / 7)),
+        days => Some(format!("+{days}d")),
+    }
+}
+
+/// The `SCHEDULED:` timestamp of a schedule, if it has a date
+fn scheduled(schedule: &Schedule) -> Option<String> {
+    let date = schedule.date()?;
+    if schedule.is_reminder() {
+        return Some(timestamp(date, None));
+    }
+    Some(timestamp(date, Some(&repeater(schedule)?)))
+}
+
+fn write_list(todo_list: &TodoList, depth: usize, lines: &mut Vec<String>) {
+    for todo in &todo_list.todos {
+        let mut headline = vec!["*".repeat(depth + 1)];
+        headline.push(String::from(if todo.done() { "DONE" } else { "TODO" }));
+        if let Some(letter) = PRIORITY_LETTERS
+            .chars()
+            .nth(usize::from(todo.priority()).wrapping_sub(1))
+        {
+            headline.push(format!("[#{letter}]"));
+        }
+        headline.push(todo.message.clone());
+        lines.push(headline.join(" "));
+
+        let mut planning = vec![];
+        if let Some(scheduled) = todo.schedule.as_ref().and_then(scheduled) {
+            planning.push(format!("SCHEDULED: {scheduled}"));
+        }
+        if let Some(due) = todo.due {
+            planning.push(format!("DEADLINE: {}", timestamp(due, None)));
+        }
+        if !planning.is_empty() {
+            lines.push(planning.join(" "));
+        }
+
+        let rule = todo.schedule.as_ref().and_then(Schedule::rule);
+        let undated_repeater = todo
+            .schedule
+            .as_ref()
+            .filter(|schedule| schedule.date().is_none() && schedule.rule().is_none())
+            .and_then(repeater);
+        if todo.has_id() || rule.is_some() || undated_repeater.is_some() {
+            lines.push(String::from(":PROPERTIES:"));
+            if todo.has_id() {
+                lines.push(format!(":ID: {}", todo.id()));
+            }
+            if let Some(rule) = rule {
+                lines.push(format!(":C3_RULE: {}", String::from(rule)));
+            }
+            if let Some(repeater) = undated_repeater {
+                lines.push(format!(":C3_REPEAT: {repeater}"));
+            }
+            lines.push(String::from(":END:"));
+        }
+
+        let Some(dependency) = todo.dependency.as_ref() else {
+            continue;
+        };
+        if let Some(note) = dependency.note() {
+            for line in note.lines() {
+                // A comma keeps lines of the note from being read as headlines
+                if line.starts_with('*') || line.starts_with(",*") {
+                    lines.push(format!(",{line}"));
+                } else {
+                    lines.push(line.to_string());
+                }
+            }
+        } else if let Some(sub_list) = dependency.todo_list() {
+            write_list(sub_list, depth + 1, lines);
+        }
+    }
+}
+
+pub fn export(todo_list: &TodoList) -> String {
+    let mut lines = vec![];
+    write_list(todo_list, 0, &mut lines);
+    lines.join("\n")
+}
+
+/// The depth of a headline line and the rest of it
+fn headline(line: &str) -> Option<(usize, &str)> {
+    let stars = line.len() - line.trim_start_matches('*').len();
+    let rest = line[stars..].strip_prefix([' ', '\t'])?;
+    Some((stars.checked_sub(1)?, rest))
+}
+
+/// The date and the repeater of the first timestamp of the text
+fn parse_timestamp(text: &str) -> io::Result<(date::Type, Option<&str>)> {
+    let inner = text
+        .trim_start()
+        .strip_prefix(['<', '['])
+        .and_then(|rest| rest.split_once(['>', ']']))
+        .map(|(inner, _)| inner)
+        .ok_or_else(|| invalid_data(format!("\"{text}\" isn't a timestamp")))?;
+    let mut parts = inner.split_whitespace();
+    let day = parts.next().unwrap_or_default();
+    let date =
+        date::parse(day).map_err(|_| invalid_data(format!("\"{day}\" isn't a YYYY-MM-DD date")))?;
+    let repeater = parts.find(|part| part.starts_with(['+', '.']));
+    Ok((date, repeater))
+}
+
+/// The schedule of a repeater like `+3d`, `++1w` or `.+1m`
+fn parse_repeater(repeater: &str) -> Option<Schedule> {
+    let value = repeater.trim_start_matches(['+', '.']);
+    let (count, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    let count: i64 = count.parse().ok()?;
+    let days = match unit {
+        "d" => count,
+        "w" => count * 7,
+        "m" => count * 30,
+        "y" => count * 365,
+        _ => return None,
+    };
+    Some(Schedule::new(days))
+}
+
+/// The message of a headline with its keyword and priority cookie taken out, and its tags as
+/// `+tag`s
+fn read_headline(text: &str) -> (bool, u8, String) {
+    let mut text = text.trim();
+    let mut done = false;
+    if let Some((keyword, rest)) = text.split_once(' ') {
+        match keyword {
+            "DONE" | "CANCELED" | "CANCELLED" => {
+                done = true;
+                text = rest.trim_start();
+            }
+            "TODO" | "NEXT" | "WAIT" | "WAITING" => text = rest.trim_start(),
+            _ => {}
+        }
+    }
+    let mut priority = 0;
+    if let Some(rest) = text.strip_prefix("[#") {
+        if let Some((cookie, rest)) = rest.split_once(']') {
+            let mut chars = cookie.chars();
+            priority = match (chars.next(), chars.next()) {
+                (Some(letter @ 'A'..='Z'), None) => (letter as u8 - b'A' + 1).min(9),
+                (Some(digit @ '0'..='9'), None) => digit as u8 - b'0',
+                _ => 0,
+            };
+            text = rest.trim_start();
+        }
+    }
+    let mut message = text.to_string();
+    if let Some((before, tags)) = text.rsplit_once([' ', '\t']) {
+        let tags = tags
+            .strip_prefix(':')
+            .and_then(|tags| tags.strip_suffix(':'));
+        if let Some(tags) = tags.filter(|tags| !tags.is_empty() && !tags.contains(' ')) {
+            message = before.trim_end().to_string();
+            for tag in tags.split(':').filter(|tag| !tag.is_empty()) {
+                message.push_str(&format!(" +{tag}"));
+            }
+        }
+    }
+    (done, priority, message)
+}
+
+/// A headline and the lines under it
+struct Entry<'a> {
+    depth: usize,
+    headline: &'a str,
+    lines: Vec<&'a str>,
+}
+
+impl Entry<'_> {
+    fn into_todo(self) -> io::Result<Todo> {
+        let (done, priority, message) = read_headline(self.headline);
+        if message.is_empty() {
+            return Err(invalid_data(format!(
+                "\"{}\" has no message",
+                self.headline
+            )));
+        }
+        let mut lines = self.lines.into_iter().peekable();
+        let mut scheduled = None;
+        let mut due = None;
+        if let Some(planning) = lines.next_if(|line| {
+            let line = line.trim_start();
+            ["SCHEDULED:", "DEADLINE:", "CLOSED:"]
+                .iter()
+                .any(|keyword| line.starts_with(keyword))
+        }) {
+            if let Some((_, rest)) = planning.split_once("SCHEDULED:") {
+                scheduled = Some(parse_timestamp(rest)?);
+            }
+            if let Some((_, rest)) = planning.split_once("DEADLINE:") {
+                due = Some(parse_timestamp(rest)?.0);
+            }
+        }
+
+        let mut id = String::new();
+        let mut rule = None;
+        let mut undated = None;
+        let mut body = vec![];
+        let mut in_drawer = false;
+        for line in lines {
+            let trimmed = line.trim();
+            if in_drawer {
+                if trimmed.eq_ignore_ascii_case(":END:") {
+                    in_drawer = false;
+                    continue;
+                }
+                let Some((key, value)) = trimmed
+                    .strip_prefix(':')
+                    .and_then(|property| property.split_once(':'))
+                else {
+                    continue;
+                };
+                let value = value.trim();
+                match key.to_uppercase().as_str() {
+                    "ID" if is_id(value) => id = value.to_string(),
+                    "C3_RULE" => {
+                        let parsed: Recurrence = value.parse().map_err(|_| {
+                            invalid_data(format!("\"{value}\" isn't a schedule rule"))
+                        })?;
+                        rule = Some(parsed);
+                    }
+                    "C3_REPEAT" => {
+                        let schedule = parse_repeater(value)
+                            .ok_or_else(|| invalid_data(format!("\"{value}\" isn't a repeater")))?;
+                        undated = Some(schedule);
+                    }
+                    _ => {}
+                }
+            } else if trimmed.len() > 2 && trimmed.starts_with(':') && trimmed.ends_with(':') {
+                // :PROPERTIES:, :LOGBOOK: and the other drawers
+                in_drawer = true;
+            } else {
+                body.push(line.trim_end());
+            }
+        }
+
+        let schedule = match (scheduled, rule) {
+            (Some((date, _)), Some(rule)) => {
+                let mut schedule = Schedule::new_rule(rule);
+                schedule.set_date(Some(date));
+                Some(schedule)
+            }
+            (None, Some(rule)) => Some(Schedule::new_rule(rule)),
+            (Some((date, Some(repeater))), None) => {
+                let mut schedule = parse_repeater(repeater)
+                    .ok_or_else(|| invalid_data(format!("\"{repeater}\" isn't a repeater")))?;
+                schedule.set_date(Some(date));
+                Some(schedule)
+            }
+            (Some((date, None)), None) => Some(Schedule::new_reminder(date)),
+            (None, None) => undated,
+        };
+        let mut todo = Todo::from_parts(id, message, priority, done, schedule, due, vec![]);
+        if !todo.has_id() {
+            todo.renew_id();
+        }
+        if let Some(note) = read_body(&body) {
+            todo.set_note(note)?;
+        }
+        Ok(todo)
+    }
+}
+
+/// The note of the lines under a headline, without their common indentation
+fn read_body(lines: &[&str]) -> Option<String> {
+    let start = lines.iter().position(|line| !line.is_empty())?;
+    let end = lines.iter().rposition(|line| !line.is_empty())?;
+    let lines = &lines[start..=end];
+    let indent = lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|line| {
+            let line = line.get(indent..).unwrap_or_default();
+            match line.strip_prefix(',') {
+                Some(rest) if rest.starts_with('*') || rest.starts_with(",*") => rest,
+                _ => line,
+            }
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let mut entries: Vec<(usize, Entry)> = vec![];
+    for (number, line) in input.lines().enumerate() {
+        if let Some((depth, headline)) = headline(line) {
+            entries.push((
+                number,
+                Entry {
+                    depth,
+                    headline,
+                    lines: vec![],
+                },
+            ));
+        } else if let Some((_, entry)) = entries.last_mut() {
+            entry.lines.push(line);
+        }
+    }
+    let mut items = vec![];
+    for (number, entry) in entries {
+        let depth = entry.depth;
+        let todo = entry
+            .into_todo()
+            .map_err(|error| invalid_data(format!("line {}: {error}", number + 1)))?;
+        items.push((depth, todo));
+    }
+    Ok(nest_outline(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() -> io::Result<()> {
+        let input = "\
+#+TITLE: Plans
+Words before the first headline.
+
+* TODO [#A] Release                                                 :work:
+  DEADLINE: <2024-05-10 Fri>
+** DONE Write the changelog
+   :PROPERTIES:
+   :ID: changelog
+   :END:
+   Keep it short
+
+   ,* And sweet
+*** Tag the commit
+* NEXT Water the plants
+  SCHEDULED: <2024-05-01 Wed .+3d>
+* WAIT [#2] Call mom
+  SCHEDULED: <2024-04-30 Tue 10:00>
+* CANCELED Plan the party
+  SCHEDULED: <2024-04-01 Mon +1w>
+  :PROPERTIES:
+  :C3_RULE: week(mon,thu)
+  :END:
+";
+        let list = import(input)?;
+        let messages: Vec<&str> = list
+            .todos
+            .iter()
+            .map(|todo| todo.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Release +work",
+                "Water the plants",
+                "Call mom",
+                "Plan the party"
+            ]
+        );
+        let release = &list.todos[0];
+        assert_eq!(release.priority(), 1);
+        assert_eq!(release.due, date::parse("2024-05-10").ok());
+        let changelog = &release
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap()
+            .todos[0];
+        assert!(changelog.done());
+        assert_eq!(changelog.id(), "changelog");
+        // The note of a todo with sub todos is left out
+        assert!(changelog
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .is_some());
+
+        let plants = list.todos[1].schedule.as_ref().unwrap();
+        assert_eq!(plants.days(), 3);
+        assert_eq!(plants.date(), date::parse("2024-05-01").ok());
+        assert_eq!(list.todos[2].priority(), 2);
+        assert!(list.todos[2]
+            .schedule
+            .as_ref()
+            .is_some_and(Schedule::is_reminder));
+        assert!(list.todos[3].done());
+        assert_eq!(
+            list.todos[3]
+                .schedule
+                .as_ref()
+                .and_then(Schedule::rule)
+                .map(String::from),
+            Some(String::from("week(mon,thu)"))
+        );
+
+        let note = import("* Todo\n  Keep it short\n\n  ,* And sweet\n")?.todos[0]
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.note())
+            .map(str::to_string);
+        assert_eq!(note.as_deref(), Some("Keep it short\n\n* And sweet"));
+
+        assert!(import("* TODO [#A]").is_err());
+        assert!(import("* Hi\nDEADLINE: <tomorrow>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let input = "\
+* TODO [#A] Release
+DEADLINE: <2024-05-10 Fri>
+:PROPERTIES:
+:ID: release
+:END:
+** DONE Write the changelog
+:PROPERTIES:
+:ID: changelog
+:END:
+Keep it short
+
+,* And sweet
+** TODO [#C] Tag the commit
+SCHEDULED: <2024-04-30 Tue>
+:PROPERTIES:
+:ID: tag
+:END:
+* TODO Water the plants
+SCHEDULED: <2024-05-01 Wed +3d>
+:PROPERTIES:
+:ID: plants
+:END:
+* DONE Plan the party
+SCHEDULED: <2024-04-01 Mon +2w> DEADLINE: <2024-04-20 Sat>
+:PROPERTIES:
+:ID: party
+:END:
+* TODO Call mom
+SCHEDULED: <2024-04-29 Mon +3d>
+:PROPERTIES:
+:ID: mom
+:C3_RULE: week(mon,thu)
+:END:
+* TODO Dust the shelves
+:PROPERTIES:
+:ID: dust
+:C3_REPEAT: +2w
+:END:";
+        let list = import(input)?;
+        // A schedule that wasn't done yet gets no date
+        let dust = list.todos.last().and_then(|todo| todo.schedule.as_ref()).unwrap();
+        assert_eq!((dust.days(), dust.date()), (14, None));
+        assert_eq!(export(&list), input);
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! The JSON of [Taskwarrior](https://taskwarrior.org)'s `task export`, an array of tasks (or
+//! older exports, with a task on each line) like:
+//!
+//! ```json
+//! [
+//!   {
+//!     "uuid": "5f3cb5a4-0e1e-4bd4-8f5c-3f0a5a2e5b7c",
+//!     "description": "Water the plants",
+//!     "status": "pending",
+//!     "project": "Home.Garden",
+//!     "priority": "M",
+//!     "due": "20240501T220000Z",
+//!     "recur": "3d",
+//!     "tags": ["outside"],
+//!     "annotations": [{ "entry": "20240428T090000Z", "description": "Not the cactus" }]
+//!   }
+//! ]
+//! ```
+//!
+//! - `description` is the message, with the `tags` added to it as `+tag`s
+//! - `H`, `M` and `L` are the priorities 1, 2 and 3
+//! - `completed` tasks are done, and `deleted` ones are left out
+//! - `due` is the due date, in the local time zone, and `recur` a repeating schedule
+//! - the `annotations` are the lines of the note
+//! - every project is a todo with its tasks under it, and `Home.Garden` is the todo `Garden`
+//!   under `Home`
+//!
+//! The instances Taskwarrior makes of a recurring task are left out when the recurring task is
+//! in the export, as the schedule of that one covers them.
+use chrono::{Local, NaiveDateTime, TimeZone};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io;
+
+use super::{invalid_data, is_id};
+use crate::date;
+use crate::todo_app::{Schedule, Todo, TodoList};
+
+#[derive(Deserialize, Debug)]
+struct Annotation {
+    description: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Task {
+    #[serde(default)]
+    uuid: String,
+    description: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    priority: Option<String>,
+    #[serde(default)]
+    due: Option<String>,
+    #[serde(default)]
+    recur: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+    /// The recurring task this task is an instance of
+    #[serde(default)]
+    parent: Option<String>,
+}
+
+/// The local date of a Taskwarrior date, which is in UTC
+fn parse_date(value: &str) -> io::Result<date::Type> {
+    let invalid = || invalid_data(format!("\"{value}\" isn't a date"));
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ").map_err(|_| invalid())?;
+    Ok(Local.from_utc_datetime(&time).date_naive())
+}
+
+/// The schedule of a `recur` value, like `weekly`, `3d`, `2wks` or `weekdays`
+fn parse_recur(value: &str) -> Option<Schedule> {
+    let value = value.trim().to_lowercase();
+    if value == "weekdays" {
+        return Some(Schedule::new_rule("weekdays".parse().ok()?));
+    }
+    let digits = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (count, unit) = value.split_at(digits);
+    let count: i64 = match count {
+        "" => 1,
+        count => count.parse().ok()?,
+    };
+    let days = match unit.trim() {
+        "d" | "day" | "days" | "daily" => 1,
+        "w" | "wk" | "wks" | "week" | "weeks" | "weekly" => 7,
+        "biweekly" | "fortnight" => 14,
+        "mo" | "mth" | "mths" | "month" | "months" | "monthly" => 30,
+        "q" | "qtr" | "qtrs" | "quarter" | "quarters" | "quarterly" => 91,
+        "y" | "yr" | "yrs" | "year" | "years" | "yearly" | "annual" => 365,
+        _ => return None,
+    };
+    Some(Schedule::new(count * days))
+}
+
+fn priority(value: &str) -> u8 {
+    match value {
+        "H" => 1,
+        "M" => 2,
+        "L" => 3,
+        _ => 0,
+    }
+}
+
+impl Task {
+    fn into_todo(self) -> io::Result<Todo> {
+        let mut message = self.description.trim().to_string();
+        if message.is_empty() {
+            return Err(invalid_data(format!(
+                "task {} has no description",
+                self.uuid
+            )));
+        }
+        for tag in &self.tags {
+            let tag = format!("+{tag}");
+            if !message.split_whitespace().any(|word| word == tag) {
+                message.push(' ');
+                message.push_str(&tag);
+            }
+        }
+        let due = self.due.as_deref().map(parse_date).transpose()?;
+        let schedule = match self.recur.as_deref() {
+            Some(recur) => Some(
+                parse_recur(recur)
+                    .ok_or_else(|| invalid_data(format!("\"{recur}\" isn't a recurrence")))?,
+            ),
+            None => None,
+        };
+        let priority = self.priority.as_deref().map_or(0, priority);
+        let done = self.status == "completed";
+        let id = if is_id(&self.uuid) {
+            self.uuid
+        } else {
+            String::new()
+        };
+        let mut todo = Todo::from_parts(id, message, priority, done, schedule, due, vec![]);
+        if !todo.has_id() {
+            todo.renew_id();
+        }
+        let note: Vec<&str> = self
+            .annotations
+            .iter()
+            .map(|annotation| annotation.description.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if !note.is_empty() {
+            todo.set_note(note.join("\n"))?;
+        }
+        Ok(todo)
+    }
+}
+
+/// The tasks of a project and the projects under it, in the order they're first seen
+#[derive(Default)]
+struct Project {
+    todos: Vec<Todo>,
+    projects: Vec<(String, Project)>,
+}
+
+impl Project {
+    fn sub_project(&mut self, name: &str) -> &mut Project {
+        let index = match self.projects.iter().position(|(other, _)| other == name) {
+            Some(index) => index,
+            None => {
+                self.projects.push((name.to_string(), Project::default()));
+                self.projects.len() - 1
+            }
+        };
+        &mut self.projects[index].1
+    }
+
+    /// The list of the project, with a todo for every project under it before its tasks. A
+    /// project is done when everything in it is. Projects of the same name, under other
+    /// projects or imported again, get their own file
+    fn into_list(self) -> TodoList {
+        let mut todo_list = TodoList::new();
+        for (name, project) in self.projects {
+            let sub_list = project.into_list();
+            let done = sub_list.todos.iter().all(Todo::done);
+            let mut todo = Todo::from_parts(String::new(), name, 0, done, None, None, vec![]);
+            todo.renew_id();
+            todo.add_unique_todo_dependency();
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list = sub_list;
+            }
+            todo_list.todos.push(todo);
+        }
+        todo_list.todos.extend(self.todos);
+        todo_list
+    }
+}
+
+fn read_tasks(input: &str) -> io::Result<Vec<Task>> {
+    if input.trim_start().starts_with('[') {
+        return serde_json::from_str(input).map_err(invalid_data);
+    }
+    input
+        .lines()
+        .map(|line| line.trim().trim_end_matches(','))
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).map_err(invalid_data))
+        .collect()
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let tasks = read_tasks(input)?;
+    let uuids: HashSet<String> = tasks.iter().map(|task| task.uuid.clone()).collect();
+    let mut root = Project::default();
+    for task in tasks {
+        if task.status == "deleted"
+            || task
+                .parent
+                .as_ref()
+                .is_some_and(|parent| uuids.contains(parent))
+        {
+            continue;
+        }
+        let mut project = &mut root;
+        for name in task.project.iter().flat_map(|project| project.split('.')) {
+            if !name.is_empty() {
+                project = project.sub_project(name);
+            }
+        }
+        project.todos.push(task.into_todo()?);
+    }
+    Ok(root.into_list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::convert::Format;
+    use crate::todo_app::{test_helpers::*, App};
+    use crate::AppArgs;
+    use std::fs::{self, remove_dir_all};
+
+    #[test]
+    fn test_import() -> io::Result<()> {
+        let input = r#"[
+{"id":1,"uuid":"5f3cb5a4-0e1e-4bd4-8f5c-3f0a5a2e5b7c","description":"Water the plants","status":"pending","project":"Home.Garden","priority":"M","due":"20240501T120000Z","recur":"3d","tags":["outside"],"annotations":[{"entry":"20240428T090000Z","description":"Not the cactus"},{"entry":"20240428T090100Z","description":"Or the fern"}],"urgency":4.2},
+{"id":2,"uuid":"a1","description":"Water the plants","status":"pending","parent":"5f3cb5a4-0e1e-4bd4-8f5c-3f0a5a2e5b7c","imask":1},
+{"id":0,"uuid":"b2","description":"Mow the lawn","status":"completed","project":"Home.Garden","end":"20240420T100000Z"},
+{"id":0,"uuid":"c3","description":"Old idea","status":"deleted"},
+{"id":3,"uuid":"d4","description":"Call mom +family","status":"pending","priority":"H","tags":["family","phone"]},
+{"id":4,"uuid":"e5","description":"Stretch","status":"recurring","recur":"weekdays","project":"Health"}
+]"#;
+        let list = import(input)?;
+        let messages: Vec<&str> = list
+            .todos
+            .iter()
+            .map(|todo| todo.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Home", "Health", "Call mom +family +phone"]);
+        assert_eq!(list.todos[2].priority(), 1);
+        assert_eq!(list.todos[2].id(), "d4");
+
+        let home = &list.todos[0];
+        assert!(!home.done());
+        let garden = &home
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap()
+            .todos[0];
+        assert_eq!(garden.message, "Garden");
+        let garden_list = garden
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap();
+        assert_eq!(garden_list.todos.len(), 2);
+        let plants = &garden_list.todos[0];
+        assert_eq!(plants.message, "Water the plants +outside");
+        assert_eq!(plants.priority(), 2);
+        assert_eq!(plants.due, Some(parse_date("20240501T120000Z")?));
+        assert_eq!(plants.schedule.as_ref().map(Schedule::days), Some(3));
+        let note = plants.dependency.as_ref().and_then(|dep| dep.note());
+        assert_eq!(note, Some("Not the cactus\nOr the fern"));
+        assert!(garden_list.todos[1].done());
+
+        let stretch = &list.todos[1]
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap()
+            .todos[0];
+        assert_eq!(
+            stretch
+                .schedule
+                .as_ref()
+                .and_then(Schedule::rule)
+                .map(String::from),
+            Some(String::from("weekdays"))
+        );
+
+        // Older exports have a task on each line
+        let input = "{\"description\":\"One\",\"status\":\"pending\"},\n{\"description\":\"Two\",\"status\":\"completed\",\"recur\":\"2wks\"}\n";
+        let list = import(input)?;
+        assert_eq!(list.todos.len(), 2);
+        assert!(list.todos[1].done());
+        assert_eq!(
+            list.todos[1].schedule.as_ref().map(Schedule::days),
+            Some(14)
+        );
+        // A project of only done tasks is done
+        let list = import(r#"[{"description":"Done","status":"completed","project":"Old"}]"#)?;
+        assert!(list.todos[0].done());
+        let list = import(r#"[{"description":"Two\nlines","status":"pending"}]"#)?;
+        assert_eq!(list.todos[0].message, "Two lines");
+
+        assert!(import(r#"[{"description":"Hi","due":"tomorrow"}]"#).is_err());
+        assert!(import(r#"[{"description":"Hi","recur":"sometimes"}]"#).is_err());
+        assert!(import(r#"[{"status":"pending"}]"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_twice() -> io::Result<()> {
+        let dir = dir("test-taskwarrior-import-twice")?;
+        let path = dir.join("tasks.json");
+        fs::write(
+            &path,
+            r#"[{"description":"Plant","project":"Home.Garden"},{"description":"Code","project":"Work.Garden"}]"#,
+        )?;
+        fs::create_dir_all(dir.join("notes"))?;
+        let args = || AppArgs {
+            todo_path: dir.join("todo"),
+            ..Default::default()
+        };
+        let mut app = App::new(args());
+        app.import(&path, Format::Taskwarrior)?;
+        app.import(&path, Format::Taskwarrior)?;
+        app.write()?;
+        let app = App::new(args());
+        remove_dir_all(dir)?;
+
+        let mut tasks = vec![];
+        let mut names = HashSet::new();
+        let mut stack = vec![&app.todo_list];
+        while let Some(list) = stack.pop() {
+            for todo in &list.todos {
+                match todo.dependency.as_ref() {
+                    Some(dependency) => {
+                        names.insert(dependency.name());
+                        stack.extend(dependency.todo_list());
+                    }
+                    None => tasks.push(todo.message.as_str()),
+                }
+            }
+        }
+        tasks.sort();
+        assert_eq!(tasks, vec!["Code", "Code", "Plant", "Plant"]);
+        assert_eq!(names.len(), 8);
+        Ok(())
+    }
+}
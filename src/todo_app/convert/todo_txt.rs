@@ -0,0 +1,282 @@
+//! The [todo.txt](https://github.com/todotxt/todo.txt) format. A todo is a line like
+//! `x (A) Call mom +family @phone due:2024-05-01 rec:1w id:0123456789ab parent:ba9876543210`:
+//!
+//! - `x` marks a done todo, and `(A)` to `(I)` are the priorities 1 to 9 (later letters are 9)
+//! - `due:` is the due date, `rec:` a repeating schedule of days (`d`), weeks (`w`), months
+//!   (`m`, 30 days), years (`y`, 365 days) or business days (`b`), and `t:` a reminder
+//! - `rule:` keeps the calendar rules of c3, like `rule:week(mon,thu)`
+//! - sub todos are on their own lines with `parent:` set to the `id:` of their todo
+//!
+//! Notes can't be written in todo.txt and are left out.
+use std::io;
+
+use super::{invalid_data, is_id, nest, FlatTodo};
+use crate::date;
+use crate::todo_app::{Recurrence, Schedule, Todo, TodoList};
+
+const PRIORITY_LETTERS: &str = "ABCDEFGHI";
+
+fn priority_letter(priority: u8) -> Option<char> {
+    PRIORITY_LETTERS
+        .chars()
+        .nth(usize::from(priority).checked_sub(1)?)
+}
+
+fn letter_priority(letter: char) -> Option<u8> {
+    if !letter.is_ascii_uppercase() {
+        return None;
+    }
+    let priority = letter as u8 - b'A' + 1;
+    Some(priority.min(9))
+}
+
+/// The `rec:`, `rule:` or `t:` item of a schedule
+fn schedule_item(schedule: &Schedule) -> Option<String> {
+    if schedule.is_reminder() {
+        return schedule
+            .date()
+            .map(|date| format!("t:{}", date::format(Some(date))));
+    }
+    match schedule.rule().map(String::from) {
+        Some(rule) if rule == "weekdays" => Some(String::from("rec:1b")),
+        Some(rule) => Some(format!("rule:{rule}")),
+        None => match schedule.days() {
+            0 => None,
+            days if days % 7 == 0 => Some(format!("rec:{}w", days / 7)),
+            days => Some(format!("rec:{days}d")),
+        },
+    }
+}
+
+fn parse_recurrence(value: &str) -> Option<Schedule> {
+    let value = value.strip_prefix('+').unwrap_or(value);
+    let (count, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    let count: i64 = match count {
+        "" => 1,
+        count => count.parse().ok()?,
+    };
+    let days = match unit {
+        "d" => count,
+        "w" => count * 7,
+        "m" => count * 30,
+        "y" => count * 365,
+        "b" => return Some(Schedule::new_rule("weekdays".parse().ok()?)),
+        _ => return None,
+    };
+    Some(Schedule::new(days))
+}
+
+fn write_list(todo_list: &TodoList, parent: Option<&str>, lines: &mut Vec<String>) {
+    for todo in &todo_list.todos {
+        let mut items: Vec<String> = vec![];
+        if todo.done() {
+            items.push(String::from("x"));
+        }
+        let letter = priority_letter(todo.priority());
+        match letter {
+            Some(letter) if !todo.done() => items.push(format!("({letter})")),
+            _ => {}
+        }
+        items.push(todo.message.clone());
+        if let Some(due) = todo.due {
+            items.push(format!("due:{}", date::format(Some(due))));
+        }
+        if let Some(item) = todo.schedule.as_ref().and_then(schedule_item) {
+            items.push(item);
+        }
+        // Done todos keep their priority in a key, like most todo.txt tools do
+        match letter {
+            Some(letter) if todo.done() => items.push(format!("pri:{letter}")),
+            _ => {}
+        }
+        if todo.has_id() {
+            items.push(format!("id:{}", todo.id()));
+        }
+        if let Some(parent) = parent {
+            items.push(format!("parent:{parent}"));
+        }
+        lines.push(items.join(" "));
+        if let Some(sub_list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
+            write_list(sub_list, Some(todo.id()), lines);
+        }
+    }
+}
+
+/// The list and the lists under it, one todo a line with the sub todos after their todo
+pub fn export(todo_list: &TodoList) -> String {
+    let mut lines = vec![];
+    write_list(todo_list, None, &mut lines);
+    lines.join("\n")
+}
+
+/// A todo of a line, with the `parent:` of it
+fn read_line(line: &str) -> io::Result<FlatTodo> {
+    let mut words = line.split_whitespace().peekable();
+    let done = words.next_if_eq(&"x").is_some();
+    let mut priority = words
+        .next_if(|word| word.len() == 3 && word.starts_with('(') && word.ends_with(')'))
+        .and_then(|word| letter_priority(word.chars().nth(1)?))
+        .unwrap_or(0);
+    // Completion and creation dates
+    while words.next_if(|word| date::parse(word).is_ok()).is_some() {}
+
+    let mut message: Vec<&str> = vec![];
+    let mut id_key = None;
+    let mut parent = None;
+    let mut schedule = None;
+    let mut due = None;
+    let parse_date = |value: &str| {
+        date::parse(value).map_err(|_| invalid_data(format!("\"{value}\" isn't a YYYY-MM-DD date")))
+    };
+    for word in words {
+        let Some((key, value)) = word
+            .split_once(':')
+            .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+        else {
+            message.push(word);
+            continue;
+        };
+        match key {
+            "due" => due = Some(parse_date(value)?),
+            "t" => schedule = Some(Schedule::new_reminder(parse_date(value)?)),
+            "rec" => {
+                schedule = Some(
+                    parse_recurrence(value)
+                        .ok_or_else(|| invalid_data(format!("\"{value}\" isn't a recurrence")))?,
+                )
+            }
+            "rule" => {
+                let rule: Recurrence = value
+                    .parse()
+                    .map_err(|_| invalid_data(format!("\"{value}\" isn't a schedule rule")))?;
+                schedule = Some(Schedule::new_rule(rule))
+            }
+            "pri" if value.len() == 1 => {
+                priority = value
+                    .chars()
+                    .next()
+                    .and_then(letter_priority)
+                    .unwrap_or(priority)
+            }
+            "id" => id_key = Some(value.to_string()),
+            "parent" => parent = Some(value.to_string()),
+            _ => message.push(word),
+        }
+    }
+    if message.is_empty() {
+        return Err(invalid_data(format!("\"{line}\" has no message")));
+    }
+    // Keys that can't be ids still tie the todos of the file together
+    let id = id_key.clone().filter(|key| is_id(key)).unwrap_or_default();
+    let todo = Todo::from_parts(id, message.join(" "), priority, done, schedule, due, vec![]);
+    Ok(FlatTodo {
+        todo,
+        key: id_key,
+        parent,
+    })
+}
+
+pub fn import(input: &str) -> io::Result<TodoList> {
+    let mut todos = vec![];
+    for (number, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let todo = read_line(line)
+            .map_err(|error| invalid_data(format!("line {}: {error}", number + 1)))?;
+        todos.push(todo);
+    }
+    Ok(nest(todos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() -> io::Result<()> {
+        let input = "\
+(A) 2024-04-01 Call mom +family @phone due:2024-05-01 id:mom
+x 2024-04-02 Pay rent rec:+1m pri:B
+Buy flowers t:2024-04-30 parent:mom
+Water the plants rec:1b http://example.com
+(Z) Loop a parent:b id:a
+Loop b parent:a id:b
+
+";
+        let list = import(input)?;
+        let messages: Vec<&str> = list
+            .todos
+            .iter()
+            .map(|todo| todo.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Call mom +family @phone",
+                "Pay rent",
+                "Water the plants http://example.com",
+                "Loop b"
+            ]
+        );
+        let mom = &list.todos[0];
+        assert_eq!(mom.priority(), 1);
+        assert_eq!(mom.due, date::parse("2024-05-01").ok());
+        let sub_list = mom
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap();
+        assert!(sub_list.todos[0]
+            .schedule
+            .as_ref()
+            .is_some_and(Schedule::is_reminder));
+        assert!(list.todos[1].done());
+        assert_eq!(list.todos[1].priority(), 2);
+        assert_eq!(
+            list.todos[1].schedule.as_ref().map(Schedule::days),
+            Some(30)
+        );
+        assert_eq!(
+            list.todos[2]
+                .schedule
+                .as_ref()
+                .and_then(Schedule::rule)
+                .map(String::from),
+            Some(String::from("weekdays"))
+        );
+        // "Loop a" is under "Loop b", and the parent of "Loop b" would make a loop
+        let loop_list = list.todos[3]
+            .dependency
+            .as_ref()
+            .and_then(|dep| dep.todo_list())
+            .unwrap();
+        assert_eq!(loop_list.todos[0].priority(), 9);
+
+        // A key that can't be an id still makes a parent
+        let list = import("Plan id:../x\nPack parent:../x")?;
+        assert!(!list.todos[0].has_id());
+        let sub_list = list.todos[0].dependency.as_ref().and_then(|dep| dep.todo_list());
+        assert_eq!(sub_list.map(|list| list.todos.len()), Some(1));
+
+        // Parents with the same message still get sub lists of their own
+        let list = import("Pack id:a\nPack id:b\nShirts parent:a\nShoes parent:b")?;
+        let name = |todo: &Todo| todo.dependency.as_ref().map(|dep| dep.name().to_string());
+        assert_ne!(name(&list.todos[0]), name(&list.todos[1]));
+
+        assert!(import("due:2024-05-01").is_err());
+        assert!(import("Hi due:tomorrow").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let input = "\
+(A) Call mom +family @phone due:2024-05-01 id:mom
+Buy flowers t:2024-04-30 id:flowers parent:mom
+x Pay rent rec:2w pri:B id:rent
+Water the plants rule:week(mon,thu) id:plants";
+        assert_eq!(export(&import(input)?), input);
+        Ok(())
+    }
+}
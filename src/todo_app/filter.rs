@@ -0,0 +1,584 @@
+//! A small language for filtering todos, like
+//! `prio<=2 and not done and sched:daily and "report"`. Terms are joined with `and` (also
+//! implied between two terms), `or` and `not`, and grouped with parentheses.
+//!
+//! - `done`, `undone` and `overdue`
+//! - `prio<=2`, with any of `<`, `<=`, `=` (or `:`), `!=`, `>=` and `>`
+//! - `due<today+7`, `due>=2024-05-01`, `due:none` and `due:any`. Dates are `YYYY-MM-DD`,
+//!   `today`, `tomorrow`, `yesterday` or `today` plus or minus days
+//! - `sched:daily`, `sched:weekly`, `sched:reminder`, `sched:none`, `sched:any`, a rule like
+//!   `sched:weekdays`, or the days of a repeating schedule compared like `sched<=7`
+//! - `tag:work`, `+project` and `@context`
+//! - `msg:"some text"`, `msg~regex` and `/regex/`
+//! - any other word or `"quoted text"` searches the messages like `/` in the TUI, also words
+//!   like `http://example.com` or `e=mc2` that only look like comparisons
+use regex::Regex;
+use std::rc::Rc;
+use std::{error, fmt};
+
+use super::restriction::{self, Restriction};
+use super::{Recurrence, Todo};
+use crate::date;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FilterErrorKind {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnclosedQuote,
+    UnclosedRegex,
+    UnclosedParen,
+    BadOperator(String, String),
+    BadValue(String, String),
+    BadRegex(String),
+}
+
+impl fmt::Display for FilterErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "filter ended where a term was expected"),
+            Self::UnexpectedToken(token) => write!(f, "unexpected \"{token}\""),
+            Self::UnclosedQuote => write!(f, "quote is never closed"),
+            Self::UnclosedRegex => write!(f, "regex is never closed with a /"),
+            Self::UnclosedParen => write!(f, "parenthesis is never closed"),
+            Self::BadOperator(field, operator) => {
+                write!(f, "\"{operator}\" can't be used with {field}")
+            }
+            Self::BadValue(field, value) => write!(f, "\"{value}\" isn't a valid {field}"),
+            Self::BadRegex(error) => write!(f, "bad regex: {error}"),
+        }
+    }
+}
+
+/// Why a filter couldn't be parsed. Column is the 1-based character the problem was found at
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FilterError {
+    pub column: usize,
+    pub kind: FilterErrorKind,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.column, self.kind)
+    }
+}
+
+impl error::Error for FilterError {}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Open,
+    Close,
+    /// A word, or text in quotes if quoted
+    Word {
+        text: String,
+        quoted: bool,
+    },
+    Regex(String),
+}
+
+#[derive(Debug)]
+struct Spanned {
+    token: Token,
+    /// Index of the first character of the token
+    index: usize,
+}
+
+#[inline(always)]
+fn error(index: usize, kind: FilterErrorKind) -> FilterError {
+    FilterError {
+        column: index + 1,
+        kind,
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Spanned>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        let start = index;
+        let token = match chars[index] {
+            c if c.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            '(' => {
+                index += 1;
+                Token::Open
+            }
+            ')' => {
+                index += 1;
+                Token::Close
+            }
+            '/' => {
+                let mut pattern = String::new();
+                index += 1;
+                loop {
+                    match chars.get(index) {
+                        None => return Err(error(start, FilterErrorKind::UnclosedRegex)),
+                        Some('/') => break,
+                        Some('\\') if chars.get(index + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            index += 1;
+                        }
+                        Some(&c) => pattern.push(c),
+                    }
+                    index += 1;
+                }
+                index += 1;
+                Token::Regex(pattern)
+            }
+            _ => {
+                let mut text = String::new();
+                let mut quoted = chars[index] == '"';
+                while let Some(&c) = chars.get(index) {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    if c != '"' {
+                        text.push(c);
+                        index += 1;
+                        continue;
+                    }
+                    let quote = index;
+                    index += 1;
+                    loop {
+                        match chars.get(index) {
+                            None => return Err(error(quote, FilterErrorKind::UnclosedQuote)),
+                            Some('"') => break,
+                            Some('\\') if chars.get(index + 1) == Some(&'"') => {
+                                text.push('"');
+                                index += 1;
+                            }
+                            Some(&c) => text.push(c),
+                        }
+                        index += 1;
+                    }
+                    index += 1;
+                    // Only a word that is all in quotes is a quoted text, `msg:"a b"` isn't
+                    quoted = quoted
+                        && chars
+                            .get(index)
+                            .is_none_or(|c| c.is_whitespace() || *c == '(' || *c == ')');
+                }
+                Token::Word { text, quoted }
+            }
+        };
+        tokens.push(Spanned {
+            token,
+            index: start,
+        });
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+    Matches,
+}
+
+const OPERATORS: [(&str, Comparison); 8] = [
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("!=", Comparison::NotEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+    ("=", Comparison::Equal),
+    (":", Comparison::Equal),
+    ("~", Comparison::Matches),
+];
+
+impl Comparison {
+    #[inline(always)]
+    fn holds<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Self::Less => left < right,
+            Self::LessOrEqual => left <= right,
+            Self::Equal | Self::Matches => left == right,
+            Self::NotEqual => left != right,
+            Self::GreaterOrEqual => left >= right,
+            Self::Greater => left > right,
+        }
+    }
+
+    #[inline(always)]
+    fn is_equality(self) -> bool {
+        matches!(self, Self::Equal | Self::NotEqual)
+    }
+}
+
+const FIELDS: [&str; 8] = [
+    "prio", "priority", "due", "sched", "schedule", "tag", "msg", "message",
+];
+
+/// Splits `field<op>value` words. None for words that aren't comparisons of a field
+fn split_comparison(word: &str) -> Option<(&str, &str, Comparison, &str)> {
+    let field_end = word.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (field, rest) = word.split_at(field_end);
+    if !FIELDS.contains(&field) {
+        return None;
+    }
+    OPERATORS.iter().find_map(|&(operator, comparison)| {
+        rest.strip_prefix(operator)
+            .map(|value| (field, operator, comparison, value))
+    })
+}
+
+fn parse_date(input: &str) -> Option<date::Type> {
+    let today = date::current();
+    let (base, days) = match input.find(['+', '-']) {
+        Some(index) if input[..index].chars().all(|c| c.is_ascii_alphabetic()) => {
+            let days: i64 = input[index + 1..].parse().ok()?;
+            let days = if &input[index..index + 1] == "-" {
+                -days
+            } else {
+                days
+            };
+            (&input[..index], days)
+        }
+        _ => (input, 0),
+    };
+    let base = match base {
+        "today" => today,
+        "tomorrow" => date::add_days(today, 1),
+        "yesterday" => date::add_days(today, -1),
+        _ if days == 0 => return date::parse(base).ok(),
+        _ => return None,
+    };
+    Some(date::add_days(base, days))
+}
+
+/// Builds the restriction of a `field<op>value` word
+fn comparison(
+    field: &str,
+    operator: &str,
+    comparison: Comparison,
+    value: &str,
+) -> Result<Restriction, FilterErrorKind> {
+    let bad_value = || FilterErrorKind::BadValue(field.to_string(), value.to_string());
+    let bad_operator = || FilterErrorKind::BadOperator(field.to_string(), operator.to_string());
+    if comparison == Comparison::Matches && !matches!(field, "msg" | "message") {
+        return Err(bad_operator());
+    }
+    let restriction: Restriction = match field {
+        "prio" | "priority" => {
+            let priority: u8 = value.parse().map_err(|_| bad_value())?;
+            Rc::new(move |todo: &Todo| comparison.holds(todo.priority(), priority))
+        }
+        "due" => match value {
+            "none" | "any" if !comparison.is_equality() => return Err(bad_operator()),
+            "none" => Rc::new(move |todo: &Todo| comparison.holds(todo.due.is_none(), true)),
+            "any" => Rc::new(move |todo: &Todo| comparison.holds(todo.due.is_some(), true)),
+            _ => {
+                let date = parse_date(value).ok_or_else(bad_value)?;
+                Rc::new(move |todo: &Todo| todo.due.is_some_and(|due| comparison.holds(due, date)))
+            }
+        },
+        "sched" | "schedule" => {
+            if let Ok(days) = value.parse::<i64>() {
+                return Ok(Rc::new(move |todo: &Todo| {
+                    todo.schedule
+                        .as_ref()
+                        .filter(|schedule| !schedule.is_reminder())
+                        .is_some_and(|schedule| comparison.holds(schedule.days(), days))
+                }));
+            }
+            if !comparison.is_equality() {
+                return Err(bad_operator());
+            }
+            let matches: Restriction = match value {
+                "daily" => restriction::schedule_day(1),
+                "weekly" => restriction::schedule_day(7),
+                "none" => Rc::new(|todo: &Todo| todo.schedule.is_none()),
+                "any" => Rc::new(|todo: &Todo| todo.schedule.is_some()),
+                "reminder" => Rc::new(|todo: &Todo| {
+                    todo.schedule
+                        .as_ref()
+                        .is_some_and(|schedule| schedule.is_reminder())
+                }),
+                _ => {
+                    let rule: Recurrence = value.parse().map_err(|_| bad_value())?;
+                    Rc::new(move |todo: &Todo| {
+                        todo.schedule.as_ref().and_then(|schedule| schedule.rule()) == Some(&rule)
+                    })
+                }
+            };
+            if comparison == Comparison::NotEqual {
+                restriction::not(matches)
+            } else {
+                matches
+            }
+        }
+        "tag" if comparison.is_equality() => {
+            let matches = restriction::tag(value.to_string());
+            if comparison == Comparison::NotEqual {
+                restriction::not(matches)
+            } else {
+                matches
+            }
+        }
+        "msg" | "message" => match comparison {
+            Comparison::Matches => restriction::regex(
+                Regex::new(value).map_err(|error| FilterErrorKind::BadRegex(error.to_string()))?,
+            ),
+            Comparison::Equal => restriction::matches(value.to_string()),
+            Comparison::NotEqual => restriction::not(restriction::matches(value.to_string())),
+            _ => return Err(bad_operator()),
+        },
+        // Tags are only compared with = and !=
+        _ => return Err(bad_operator()),
+    };
+    Ok(restriction)
+}
+
+/// Builds the restriction of a single unquoted word
+fn word(text: &str) -> Result<Restriction, FilterErrorKind> {
+    if let Some((field, operator, kind, value)) = split_comparison(text) {
+        return comparison(field, operator, kind, value);
+    }
+    Ok(match text {
+        "done" => restriction::done(),
+        "undone" => restriction::undone(),
+        "overdue" => Rc::new(|todo: &Todo| todo.is_overdue()),
+        tag if Todo::is_tag(tag) => restriction::tag(tag.to_string()),
+        text => restriction::matches(text.to_string()),
+    })
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    /// Index right after the input, for errors at its end
+    end: usize,
+}
+
+impl Parser {
+    #[inline(always)]
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.position)
+    }
+
+    /// Whether the next token is the unquoted keyword
+    #[inline(always)]
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Spanned { token: Token::Word { text, quoted: false }, .. }) if text.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<Restriction, FilterError> {
+        let mut restriction = self.and()?;
+        while self.at_keyword("or") {
+            self.position += 1;
+            restriction = restriction::or(restriction, self.and()?);
+        }
+        Ok(restriction)
+    }
+
+    fn and(&mut self) -> Result<Restriction, FilterError> {
+        let mut restriction = self.not()?;
+        loop {
+            if self.at_keyword("and") {
+                self.position += 1;
+            } else if self.at_keyword("or")
+                || matches!(
+                    self.peek(),
+                    None | Some(Spanned {
+                        token: Token::Close,
+                        ..
+                    })
+                )
+            {
+                break;
+            }
+            restriction = restriction::and(restriction, self.not()?);
+        }
+        Ok(restriction)
+    }
+
+    fn not(&mut self) -> Result<Restriction, FilterError> {
+        if self.at_keyword("not") {
+            self.position += 1;
+            return Ok(restriction::not(self.not()?));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Restriction, FilterError> {
+        let Some(Spanned { token, index }) = self.tokens.get(self.position) else {
+            return Err(error(self.end, FilterErrorKind::UnexpectedEnd));
+        };
+        let index = *index;
+        self.position += 1;
+        match token {
+            Token::Open => {
+                let restriction = self.or()?;
+                match self.peek() {
+                    Some(Spanned {
+                        token: Token::Close,
+                        ..
+                    }) => {
+                        self.position += 1;
+                        Ok(restriction)
+                    }
+                    _ => Err(error(index, FilterErrorKind::UnclosedParen)),
+                }
+            }
+            Token::Close => Err(error(
+                index,
+                FilterErrorKind::UnexpectedToken(String::from(")")),
+            )),
+            Token::Regex(pattern) => {
+                Regex::new(pattern)
+                    .map(restriction::regex)
+                    .map_err(|regex_error| {
+                        error(index, FilterErrorKind::BadRegex(regex_error.to_string()))
+                    })
+            }
+            Token::Word { text, quoted: true } => Ok(restriction::matches(text.clone())),
+            Token::Word {
+                text,
+                quoted: false,
+            } => match text.strip_prefix('!') {
+                Some(rest) if !rest.is_empty() => word(rest).map(restriction::not),
+                _ => word(text),
+            }
+            .map_err(|kind| error(index, kind)),
+        }
+    }
+}
+
+/// Parses a filter into a restriction. An empty filter matches every todo
+pub fn parse(input: &str) -> Result<Restriction, FilterError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+        end: input.chars().count(),
+    };
+    if parser.tokens.is_empty() {
+        return Ok(restriction::all());
+    }
+    let restriction = parser.or()?;
+    match parser.peek() {
+        Some(Spanned { index, .. }) => Err(error(
+            *index,
+            FilterErrorKind::UnexpectedToken(String::from(")")),
+        )),
+        None => Ok(restriction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn todo(line: &str) -> Todo {
+        line.parse().unwrap()
+    }
+
+    fn matching<'a>(filter: &str, todos: &'a [Todo]) -> Vec<&'a str> {
+        let restriction = parse(filter).unwrap();
+        todos
+            .iter()
+            .filter(|todo| restriction(todo))
+            .map(|todo| todo.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let due = date::format(Some(date::add_days(date::current(), 3)));
+        let todos = [
+            todo("[1] write the report +work [D1(2023-09-05)]"),
+            todo("[-3] done report"),
+            todo(&format!("[2] call mom @phone [due:{due}]")),
+            todo("[4] water the plants [D3(2023-09-05) rule:weekdays]"),
+        ];
+        assert_eq!(
+            matching(
+                "prio<=2 and not done and sched:daily and \"report\"",
+                &todos
+            ),
+            vec!["write the report +work"]
+        );
+        assert_eq!(
+            matching("done or +work", &todos),
+            vec!["write the report +work", "done report"]
+        );
+        assert_eq!(
+            matching("report !done", &todos),
+            vec!["write the report +work"]
+        );
+        assert_eq!(
+            matching("due<=today+3 due>today", &todos),
+            vec!["call mom @phone"]
+        );
+        assert_eq!(
+            matching("due:none (prio>3 or sched<=1)", &todos),
+            vec!["write the report +work", "water the plants"]
+        );
+        assert_eq!(matching("sched:weekdays", &todos), vec!["water the plants"]);
+        assert_eq!(
+            matching("/^(call|water) /", &todos),
+            vec!["call mom @phone", "water the plants"]
+        );
+        assert_eq!(
+            matching("msg~mom tag:phone", &todos),
+            vec!["call mom @phone"]
+        );
+        assert_eq!(
+            matching("\"or\"", &todos),
+            vec!["write the report +work", "done report"]
+        );
+        assert_eq!(matching("", &todos).len(), 4);
+
+        // Words that aren't comparisons of a field are searched as they are
+        let todos = [
+            todo("[0] read http://example.com"),
+            todo("[0] prove e=mc2"),
+        ];
+        assert_eq!(matching("http://example.com", &todos), vec!["read http://example.com"]);
+        assert_eq!(matching("e=mc2", &todos), vec!["prove e=mc2"]);
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let kind = |input: &str| parse(input).err().map(|error| (error.column, error.kind));
+        assert_eq!(
+            kind("prio<=x"),
+            Some((
+                1,
+                FilterErrorKind::BadValue(String::from("prio"), String::from("x"))
+            ))
+        );
+        assert_eq!(kind("done and"), Some((9, FilterErrorKind::UnexpectedEnd)));
+        assert_eq!(
+            kind("(done or undone"),
+            Some((1, FilterErrorKind::UnclosedParen))
+        );
+        assert_eq!(
+            kind("done \"report"),
+            Some((6, FilterErrorKind::UnclosedQuote))
+        );
+        assert_eq!(kind("size>3"), None);
+        assert_eq!(
+            kind("tag<3"),
+            Some((
+                1,
+                FilterErrorKind::BadOperator(String::from("tag"), String::from("<"))
+            ))
+        );
+        assert_eq!(
+            kind("done )"),
+            Some((6, FilterErrorKind::UnexpectedToken(String::from(")"))))
+        );
+        assert!(matches!(
+            kind("/(/"),
+            Some((1, FilterErrorKind::BadRegex(_)))
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::todo_app::{App, TodoList};
+use crate::todo_app::{App, TodoList, TreePath};
 use crate::TodoDisplay;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
@@ -30,40 +30,34 @@ pub fn fzf_search(app: &mut App) {
     write_todos(app, &mut stdin, selected);
     mem::drop(stdin);
     if handle.join().is_ok() {
-        let mut indices = fzf_indices.lock().unwrap();
-        if let Some(item) = indices.pop() {
-            app.index = item;
-            app.tree_path.append(indices.as_mut());
+        let indices = fzf_indices.lock().unwrap();
+        if let Some(id) = app.todo_at_path(&indices).map(|todo| todo.id().to_string()) {
+            app.select_id(&id);
         }
     }
 }
 
+/// Tree path of the line fzf printed, which starts with the indices of the todo
 #[inline(always)]
 pub fn read_indices(string: &[u8]) -> Option<Vec<usize>> {
-    if let Some(end_index) = string.iter().position(|ch| *ch == b' ') {
-        if let Ok(indices_str) = str::from_utf8(&string[0..end_index]) {
-             return Some(indices_str.split(',').flat_map(str::parse).collect());
-        }
-    }
-    None
+    let end_index = string.iter().position(|ch| *ch == b' ')?;
+    let path: TreePath = str::from_utf8(&string[0..end_index]).ok()?.parse().ok()?;
+    path.indices()
 }
 
 #[inline(always)]
 pub fn write_todos(app: &App, dst: &mut impl io::Write, selected: Arc<Mutex<bool>>) {
-    let mut stack: Vec<(&TodoList, String)> = vec![(app.current_list(), String::new())];
+    let mut stack: Vec<(&TodoList, Vec<usize>)> = vec![(app.current_list(), app.tree_path.clone())];
     while let Some((todolist, indices)) = stack.pop() {
         for (index, todo) in todolist.todos(app.get_restriction()).enumerate() {
             if *selected.lock().unwrap().deref() {
                 break;
             }
-            let current = todolist.true_position_in_list(index, app.get_restriction());
-            let _ = dst.write_all(format!("{indices},{current} {}\n",todo.display_with_args(&app.args.display_args)).as_bytes());
+            let mut path = indices.clone();
+            path.push(todolist.true_position_in_list(index, app.get_restriction()));
+            let _ = dst.write_all(format!("{} {}\n", TreePath::from(&path[..]), todo.display_with_args(&app.args.display_args)).as_bytes());
             if let Some(list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
-                let mut new_indices = indices.clone();
-                new_indices.push_str(&current.to_string());
-                new_indices.push(',');
-
-                stack.push((list, new_indices));
+                stack.push((list, path));
             }
         }
         if *selected.lock().unwrap().deref() {
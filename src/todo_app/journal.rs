@@ -0,0 +1,572 @@
+use chrono::{Local, TimeZone};
+use std::collections::HashMap;
+use std::fs::{self, create_dir_all, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
+
+use super::Todo;
+use crate::fileio::{self, Transaction};
+
+const JOURNAL_NAME: &str = "c3-journal";
+/// Once the journal is bigger than this, its oldest operations are dropped until it's half
+const MAX_JOURNAL_LEN: u64 = 4 * 1024 * 1024;
+
+/// A line of a todo list before and after an operation
+type LineChange<'a> = (Option<&'a str>, Option<&'a str>);
+
+/// Change a single file got in an operation. Paths are relative to the directory of the todo
+/// file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Change {
+    /// A line of a todo list file, None where the line doesn't exist
+    Line {
+        file: PathBuf,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// Content of a note or history file before it was changed or removed, None if the file
+    /// was created
+    File {
+        file: PathBuf,
+        before: Option<String>,
+    },
+}
+
+/// Everything a single write changed in the files of a tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub number: usize,
+    /// Unix time of the write
+    pub time: i64,
+    changes: Vec<Change>,
+}
+
+/// Append-only log of the operations written to a tree, kept next to its notes directory.
+/// Todo list files are logged line by line (matched with their ids), so any operation can be
+/// reverted without undoing the ones after it. Only the newest operations are kept, they're
+/// numbered on from the dropped ones.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    root: PathBuf,
+    todo_path: PathBuf,
+}
+
+fn escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+fn unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => output.push('\t'),
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+/// Lines of the journal never contain empty todo lines, so empty means there's no line
+#[inline(always)]
+fn escape_optional(input: &Option<String>) -> String {
+    input.as_deref().map(escape).unwrap_or_default()
+}
+
+#[inline(always)]
+fn unescape_optional(input: &str) -> Option<String> {
+    (!input.is_empty()).then(|| unescape(input))
+}
+
+/// Id of a todo line, without parsing all of it. c3 writes the id in the trailing brackets.
+fn id_of(line: &str) -> Option<&str> {
+    let (_, suffix) = line.strip_suffix(']')?.rsplit_once('[')?;
+    suffix
+        .split_whitespace()
+        .find_map(|item| item.strip_prefix("id:"))
+        .filter(|id| !id.is_empty())
+}
+
+/// The line as it was before c3 gave it an id
+fn without_id(line: &str) -> Option<String> {
+    let (message, suffix) = line.strip_suffix(']')?.rsplit_once('[')?;
+    let items: Vec<&str> = suffix
+        .split_whitespace()
+        .filter(|item| !item.starts_with("id:"))
+        .collect();
+    if items.is_empty() {
+        Some(message.trim_end().to_string())
+    } else {
+        Some(format!("{message}[{}]", items.join(" ")))
+    }
+}
+
+#[inline(always)]
+fn read_lossy(path: &Path) -> Option<String> {
+    fs::read(path)
+        .ok()
+        .map(|content| String::from_utf8_lossy(&content).into_owned())
+}
+
+#[inline(always)]
+fn todo_lines(content: &str) -> impl Iterator<Item = &str> {
+    content.lines().filter(|line| !line.trim().is_empty())
+}
+
+fn diff_lines(file: &Path, before: &str, after: &str, changes: &mut Vec<Change>) {
+    let mut push = |before: Option<&str>, after: Option<&str>| {
+        changes.push(Change::Line {
+            file: file.to_path_buf(),
+            before: before.map(String::from),
+            after: after.map(String::from),
+        })
+    };
+    let ids = |content| -> HashMap<&str, &str> {
+        todo_lines(content)
+            .filter_map(|line| Some((id_of(line)?, line)))
+            .collect()
+    };
+    let (before_ids, after_ids) = (ids(before), ids(after));
+    // Lines without ids can only be matched by their content
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in todo_lines(before) {
+        match id_of(line) {
+            Some(id) => match after_ids.get(id) {
+                Some(&after) if after == line => {}
+                after => push(Some(line), after.copied()),
+            },
+            None => *counts.entry(line).or_default() += 1,
+        }
+    }
+    for line in todo_lines(after) {
+        match id_of(line) {
+            Some(id) if !before_ids.contains_key(id) => {
+                // Lines that only got an id in this write are changes, not new todos
+                let old = without_id(line).and_then(|old| counts.get_key_value(old.as_str()));
+                match old {
+                    Some((&old, &count)) if count > 0 => {
+                        push(Some(old), Some(line));
+                        counts.insert(old, count - 1);
+                    }
+                    _ => push(None, Some(line)),
+                }
+            }
+            Some(_) => {}
+            None => *counts.entry(line).or_default() -= 1,
+        }
+    }
+    for line in todo_lines(before).chain(todo_lines(after)) {
+        if let Some(count) = counts.get_mut(line) {
+            if *count > 0 {
+                push(Some(line), None);
+                *count -= 1;
+            } else if *count < 0 {
+                push(None, Some(line));
+                *count += 1;
+            }
+        }
+    }
+}
+
+impl Journal {
+    pub fn new(todo_path: &Path) -> Self {
+        let root = todo_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Self {
+            path: root.join(JOURNAL_NAME),
+            root,
+            todo_path: todo_path.to_path_buf(),
+        }
+    }
+
+    #[inline(always)]
+    fn is_list(&self, path: &Path) -> bool {
+        path == self.todo_path || path.extension().is_some_and(|extension| extension == "todo")
+    }
+
+    fn changes(&self, before: Vec<(PathBuf, Option<String>)>, committed: &[PathBuf]) -> Vec<Change> {
+        let mut changes = vec![];
+        for (path, before) in before {
+            if !committed.contains(&path) {
+                continue;
+            }
+            let after = read_lossy(&path);
+            let file = path.strip_prefix(&self.root).unwrap_or(&path);
+            if self.is_list(&path) {
+                diff_lines(
+                    file,
+                    before.as_deref().unwrap_or_default(),
+                    after.as_deref().unwrap_or_default(),
+                    &mut changes,
+                );
+            } else if before != after {
+                changes.push(Change::File {
+                    file: file.to_path_buf(),
+                    before,
+                });
+            }
+        }
+        changes
+    }
+
+    fn append(&self, changes: &[Change]) -> io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let mut content = format!("op\t{time}\n");
+        for change in changes {
+            match change {
+                Change::Line { file, before, after } => content.push_str(&format!(
+                    "line\t{}\t{}\t{}\n",
+                    escape(&file.to_string_lossy()),
+                    escape_optional(before),
+                    escape_optional(after),
+                )),
+                Change::File {
+                    file,
+                    before: Some(before),
+                } => content.push_str(&format!(
+                    "file\t{}\t{}\n",
+                    escape(&file.to_string_lossy()),
+                    escape(before),
+                )),
+                Change::File { file, before: None } => {
+                    content.push_str(&format!("new\t{}\n", escape(&file.to_string_lossy())))
+                }
+            }
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_data()?;
+        if file.metadata()?.len() > MAX_JOURNAL_LEN {
+            self.trim(MAX_JOURNAL_LEN / 2)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest operations, keeping as many of the newest ones as fit in the length.
+    /// The number of the first one kept is written first, so the numbers stay the same
+    fn trim(&self, max_len: u64) -> io::Result<()> {
+        let content = fs::read_to_string(&self.path)?;
+        let mut first = 1;
+        let mut starts: Vec<usize> = vec![];
+        let mut start = 0;
+        for line in content.split_inclusive('\n') {
+            if let Some(number) = line.strip_prefix("first\t") {
+                first = number.trim_end().parse().unwrap_or(first);
+            } else if line.starts_with("op\t") {
+                starts.push(start);
+            }
+            start += line.len();
+        }
+        // The newest operation is kept even if it's longer
+        let kept = starts
+            .iter()
+            .position(|&start| (content.len() - start) as u64 <= max_len)
+            .unwrap_or(starts.len().saturating_sub(1));
+        if kept == 0 {
+            return Ok(());
+        }
+        fileio::write_atomically(&self.path, |writer| {
+            writeln!(writer, "first\t{}", first + kept)?;
+            writer.write_all(&content.as_bytes()[starts[kept]..])
+        })
+    }
+
+    /// Commits the transaction, logging what it changed. Files that got written before a
+    /// failed commit are logged too.
+    pub fn commit(&self, transaction: Transaction) -> io::Result<Vec<PathBuf>> {
+        let mut before: Vec<(PathBuf, Option<String>)> = vec![];
+        for path in transaction.paths() {
+            if !before.iter().any(|(seen, _)| seen == path) {
+                before.push((path.to_path_buf(), read_lossy(path)));
+            }
+        }
+        let result = transaction.commit();
+        let committed = match &result {
+            Ok(committed) => committed,
+            Err(partial) => &partial.committed,
+        };
+        let changes = self.changes(before, committed);
+        if !changes.is_empty() {
+            // The files are already written, failing to log them shouldn't fail the write
+            let _ = self.append(&changes);
+        }
+        Ok(result?)
+    }
+
+    /// Logged operations, oldest first
+    pub fn operations(&self) -> io::Result<Vec<Operation>> {
+        if !self.path.is_file() {
+            return Ok(vec![]);
+        }
+        let mut operations: Vec<Operation> = vec![];
+        let mut first = 1;
+        for line in fs::read_to_string(&self.path)?.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[..] {
+                ["first", number] => first = number.parse().unwrap_or(first),
+                ["op", time] => operations.push(Operation {
+                    number: first + operations.len(),
+                    time: time.parse().unwrap_or_default(),
+                    changes: vec![],
+                }),
+                ["line", file, before, after] => {
+                    if let Some(operation) = operations.last_mut() {
+                        operation.changes.push(Change::Line {
+                            file: PathBuf::from(unescape(file)),
+                            before: unescape_optional(before),
+                            after: unescape_optional(after),
+                        });
+                    }
+                }
+                ["file", file, before] => {
+                    if let Some(operation) = operations.last_mut() {
+                        operation.changes.push(Change::File {
+                            file: PathBuf::from(unescape(file)),
+                            before: Some(unescape(before)),
+                        });
+                    }
+                }
+                ["new", file] => {
+                    if let Some(operation) = operations.last_mut() {
+                        operation.changes.push(Change::File {
+                            file: PathBuf::from(unescape(file)),
+                            before: None,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(operations)
+    }
+
+    /// Puts back what the operation with the given number changed, leaving changes of the
+    /// other operations to the same todos alone. The revert is logged as an operation itself.
+    pub fn revert(&self, number: usize) -> io::Result<Vec<PathBuf>> {
+        let operation = self
+            .operations()?
+            .into_iter()
+            .find(|operation| operation.number == number)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("there's no operation {number} in the journal"),
+                )
+            })?;
+        let mut transaction = Transaction::new();
+        let mut lists: Vec<(&Path, Vec<LineChange>)> = vec![];
+        for change in &operation.changes {
+            match change {
+                Change::Line { file, before, after } => {
+                    let line = (before.as_deref(), after.as_deref());
+                    match lists.iter_mut().find(|(list, _)| list == file) {
+                        Some((_, lines)) => lines.push(line),
+                        None => lists.push((file, vec![line])),
+                    }
+                }
+                Change::File { file, before } => {
+                    let path = self.root.join(file);
+                    let Some(before) = before else {
+                        transaction.remove(&path);
+                        continue;
+                    };
+                    if let Some(parent) = path.parent() {
+                        create_dir_all(parent)?;
+                    }
+                    transaction.write(&path, |writer| writer.write_all(before.as_bytes()))?;
+                }
+            }
+        }
+        for (file, lines) in lists {
+            let path = self.root.join(file);
+            let content = read_lossy(&path).unwrap_or_default();
+            let mut current: Vec<&str> = todo_lines(&content).collect();
+            for (before, after) in lines {
+                // Giving a todo an id isn't worth reverting
+                if before.is_some() && after.and_then(without_id).as_deref() == before {
+                    continue;
+                }
+                match before.or(after).and_then(id_of) {
+                    Some(id) => current.retain(|line| id_of(line) != Some(id)),
+                    None => {
+                        let position = current.iter().position(|line| Some(*line) == after);
+                        if let Some(position) = position {
+                            current.remove(position);
+                        }
+                    }
+                }
+                if let Some(before) = before {
+                    current.push(before);
+                }
+            }
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            transaction.write(&path, |writer| {
+                for line in &current {
+                    writeln!(writer, "{line}")?;
+                }
+                Ok(())
+            })?;
+        }
+        self.commit(transaction)
+    }
+}
+
+#[inline(always)]
+fn message_of(line: &str) -> String {
+    Todo::from_str(line).map_or_else(|_| line.to_string(), |todo| todo.message)
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = Local
+            .timestamp_opt(self.time, 0)
+            .single()
+            .map_or(String::new(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+        write!(f, "{} {time}", self.number)?;
+        for change in &self.changes {
+            match change {
+                Change::Line { before, after, .. } => {
+                    let (sign, line) = match (before, after) {
+                        (None, Some(after)) => ('+', after),
+                        (Some(before), None) => ('-', before),
+                        (Some(_), Some(after)) => ('~', after),
+                        (None, None) => continue,
+                    };
+                    write!(f, "\n    {sign} {}", message_of(line))?;
+                }
+                Change::File { file, .. } => write!(f, "\n    * {}", file.display())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::test_helpers::*;
+    use crate::todo_app::App;
+    use std::fs::remove_dir_all;
+
+    fn messages(app: &App) -> Vec<String> {
+        app.current_list()
+            .iter()
+            .map(|todo| todo.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_escape() {
+        let input = "tab\there\\ and\nnew line";
+        assert_eq!(unescape(&escape(input)), input);
+        assert!(!escape(input).contains(['\t', '\n']));
+        assert_eq!(id_of("[1] message [D1(2023-09-05) id:0123456789ab]"), Some("0123456789ab"));
+        assert_eq!(id_of("[1] message [not an id]"), None);
+        assert_eq!(without_id("[1] message [D1(2023-09-05) id:0123456789ab]").unwrap(), "[1] message [D1(2023-09-05)]");
+        assert_eq!(without_id("[1] message [id:0123456789ab]").unwrap(), "[1] message");
+    }
+
+    #[test]
+    fn test_revert() -> io::Result<()> {
+        let dir = dir("test-journal-revert")?;
+        let mut app = write_test_todos(&dir)?;
+        let initial = messages(&app);
+        let journal = Journal::new(&app.args.todo_path);
+        let written = journal.operations()?.len();
+        assert!(written > 0);
+
+        app.current_list_mut().todos.retain(|todo| todo.message != "Hello");
+        app.append(String::from("New"));
+        app.write()?;
+        app.index = messages(&app).iter().position(|message| message == "Goodbye").unwrap();
+        let id = app.todo().unwrap().id().to_string();
+        app.set_current_priority(5);
+        app.write()?;
+        let operations = journal.operations()?;
+        assert_eq!(operations.len(), written + 2);
+
+        // Reverting the first one keeps what the second did
+        let removal = &operations[written];
+        assert_eq!(removal.changes.len(), 2);
+        journal.revert(removal.number)?;
+        app.read();
+        let mut expected = initial.clone();
+        expected.sort();
+        let mut reverted = messages(&app);
+        reverted.sort();
+        assert_eq!(reverted, expected);
+        assert_eq!(app.todo_by_id(&id).map(Todo::priority), Some(5));
+        assert!(journal.revert(operations.len() + 10).is_err());
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_new_note() -> io::Result<()> {
+        let dir = dir("test-journal-new-note")?;
+        let mut app = write_test_todos(&dir)?;
+        let journal = Journal::new(&app.args.todo_path);
+        app.append(String::from("Noted"));
+        app.todo_mut().unwrap().set_note(String::from("A new note"))?;
+        app.write()?;
+        let note_count = |dir: &Path| fs::read_dir(dir.join("notes")).map(Iterator::count);
+        let with_note = note_count(&dir)?;
+        let operations = journal.operations()?;
+        journal.revert(operations.len())?;
+        let reverted = note_count(&dir)?;
+        remove_dir_all(dir)?;
+
+        assert_eq!(reverted, with_note - 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim() -> io::Result<()> {
+        let dir = dir("test-journal-trim")?;
+        let mut app = write_test_todos(&dir)?;
+        let journal = Journal::new(&app.args.todo_path);
+        for message in ["One", "Two", "Three"] {
+            app.append(String::from(message));
+            app.write()?;
+        }
+        let operations = journal.operations()?;
+        let last = operations.last().unwrap().clone();
+        journal.trim(1)?;
+        let trimmed = journal.operations()?;
+        // Trimming again keeps the numbers
+        journal.trim(1)?;
+        let trimmed_again = journal.operations()?;
+        let revert = journal.revert(last.number);
+        remove_dir_all(dir)?;
+
+        assert!(operations.len() > 1);
+        assert_eq!(trimmed, vec![last.clone()]);
+        assert_eq!(trimmed_again, vec![last]);
+        assert!(revert.is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use super::Todo;
+use regex::Regex;
+use std::rc::Rc;
+
+pub type Restriction = Rc<dyn Fn(&Todo) -> bool>;
+
+#[inline]
+pub fn all() -> Restriction {
+    Rc::new(|_| true)
+}
+
+#[inline]
+pub fn undone() -> Restriction {
+    Rc::new(|todo| !todo.done())
+}
+
+#[inline]
+pub fn done() -> Restriction {
+    Rc::new(|todo| todo.done())
+}
+
+#[inline]
+pub fn and(first: Restriction, second: Restriction) -> Restriction {
+    Rc::new(move |todo| first(todo) && second(todo))
+}
+
+#[inline]
+pub fn or(first: Restriction, second: Restriction) -> Restriction {
+    Rc::new(move |todo| first(todo) || second(todo))
+}
+
+#[inline]
+pub fn not(restriction: Restriction) -> Restriction {
+    Rc::new(move |todo| !restriction(todo))
+}
+
+#[inline]
+pub fn matches(query: String) -> Restriction {
+    Rc::new(move |todo| todo.matches(query.as_str()))
+}
+
+/// Todos with a message the regex matches a part of
+#[inline]
+pub fn regex(regex: Regex) -> Restriction {
+    Rc::new(move |todo| regex.is_match(&todo.message))
+}
+
+#[inline]
+pub fn priority(priority: u8) -> Restriction {
+    Rc::new(move |todo| todo.priority() == priority)
+}
+
+/// Todos scheduled every `day` days, 0 being todos with no repeating schedule
+#[inline]
+pub fn schedule_day(day: i64) -> Restriction {
+    Rc::new(move |todo| {
+        todo.schedule
+            .as_ref()
+            .map_or(0, |sch| if sch.is_reminder() { 0 } else { sch.days() })
+            == day
+    })
+}
+
+/// Todos having the tag. A tag without `+` or `@` matches both projects and contexts with
+/// that name
+#[inline]
+pub fn tag(tag: String) -> Restriction {
+    Rc::new(move |todo| todo.has_tag(tag.as_str()))
+}
+
+#[inline]
+pub fn project(name: &str) -> Restriction {
+    tag(format!("+{name}"))
+}
+
+#[inline]
+pub fn context(name: &str) -> Restriction {
+    tag(format!("@{name}"))
+}
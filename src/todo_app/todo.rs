@@ -1,18 +1,27 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 //imports {{{
-use std::{fmt, fs, io, path::Path, str::FromStr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::{fmt, io, process, str::FromStr};
 mod dependency;
+pub mod history;
 mod note;
+pub mod recurrence;
 pub mod schedule;
 use super::TodoList;
-use crate::{DisplayArgs, TodoDisplay};
+use crate::fileio::Transaction;
+use crate::{date, DisplayArgs, TodoDisplay};
 use dependency::Dependency;
+use history::History;
 use note::{open_note_temp_editor, sha1};
-use schedule::Schedule;
+use recurrence::Recurrence;
+use schedule::{Schedule, RULE_KEY};
 // }}}
 
-#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Todo {
+    id: String,
     pub message: String,
     priority: u8,
     pub dependency: Option<Dependency>,
@@ -20,22 +29,44 @@ pub struct Todo {
     last_schedule: Option<Schedule>,
     done: bool,
     pub schedule: Option<Schedule>,
+    pub due: Option<date::Type>,
+    history: History,
+    unknown_suffix_items: Vec<String>,
 }
 
+// id and history are left out, two todos with the same content are equal even if they aren't the
+// same todo
+impl PartialEq for Todo {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+            && self.priority == other.priority
+            && self.dependency == other.dependency
+            && self.removed_dependency == other.removed_dependency
+            && self.last_schedule == other.last_schedule
+            && self.done == other.done
+            && self.schedule == other.schedule
+            && self.due == other.due
+            && self.unknown_suffix_items == other.unknown_suffix_items
+    }
+}
+
+impl Eq for Todo {}
+
 impl fmt::Display for Todo {
     #[inline(always)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let shcedule_str = self
             .schedule
             .as_ref()
-            .map(|schedule| schedule.display())
+            .map(|schedule| schedule.display_with_streak(self.current_streak()))
             .unwrap_or_default();
         let note_string = self.dependency.as_ref().map_or(".", |dep| dep.display());
+        let due_str = self.display_due();
         let Todo {
             priority, message, ..
         } = self;
 
-        write!(f, "{priority}{note_string} {message}{shcedule_str}")
+        write!(f, "{priority}{note_string} {message}{shcedule_str}{due_str}")
     }
 }
 
@@ -76,99 +107,238 @@ impl From<&Todo> for String {
             .map(|x| x.into())
             .unwrap_or_default();
 
-        let schedule_str: String = todo.schedule.as_ref().map(|x| x.into()).unwrap_or_default();
+        let mut suffix_items: Vec<String> = vec![];
+        if let Some(schedule) = todo.schedule.as_ref() {
+            suffix_items.push(schedule.into());
+        }
+        if todo.due.is_some() {
+            suffix_items.push(format!("{DUE_KEY}{}", date::format(todo.due)));
+        }
+        if !todo.id.is_empty() {
+            suffix_items.push(format!("{ID_KEY}{}", todo.id));
+        }
+        // Kept so items written by newer versions of c3 aren't lost
+        suffix_items.extend(todo.unknown_suffix_items.iter().cloned());
+        let suffix_str = if suffix_items.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", suffix_items.join(" "))
+        };
 
         format!(
-            "[{done_str}{}]{dep_str} {}{schedule_str}",
+            "[{done_str}{}]{dep_str} {}{suffix_str}",
             todo.priority, todo.message
         )
     }
 }
 
+const ID_KEY: &str = "id:";
+const DUE_KEY: &str = "due:";
+static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+fn new_id(message: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let count = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut id = sha1(&format!("{time} {} {count} {message}", process::id()));
+    id.truncate(12);
+    id
+}
+
+/// The text with its lines joined by spaces
+#[inline(always)]
+fn one_line(text: String) -> String {
+    if !text.contains(['\r', '\n']) {
+        return text;
+    }
+    text.split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TodoError {
-    ReadFailed,
+    ReadFailed(ParseError),
     NoteEmpty,
     DependencyCreationFailed,
 }
 
-#[derive(Default, PartialEq)]
-enum State {
-    #[default]
-    Priority,
-    Dependency,
-    Message,
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseErrorKind {
+    MissingPriority,
+    BadPriority,
+    MissingSpace,
+    EmptyMessage,
+    UnterminatedSchedule,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::MissingPriority => "line should start with a priority like [1]",
+            Self::BadPriority => "priority should be a single digit, optionally after a -",
+            Self::MissingSpace => "expected a space before the message",
+            Self::EmptyMessage => "todo message is empty",
+            Self::UnterminatedSchedule => "schedule bracket is never closed",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// Why a line isn't a todo. Column is the 1-based character the problem was found at
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.column, self.kind)
+    }
+}
+
+#[inline(always)]
+fn parse_error(index: usize, kind: ParseErrorKind) -> TodoError {
+    TodoError::ReadFailed(ParseError {
+        column: index + 1,
+        kind,
+    })
+}
+
+#[inline(always)]
+fn looks_like_suffix(input: &str) -> bool {
+    [ID_KEY, DUE_KEY, RULE_KEY, "R("].iter().any(|key| input.starts_with(key))
+        || input.strip_prefix('D').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+#[derive(Default)]
+struct Suffix {
+    schedule: Option<Schedule>,
+    id: String,
+    due: Option<date::Type>,
+    unknown_items: Vec<String>,
+}
+
+impl Suffix {
+    /// Parses the content of the trailing brackets. None if nothing in it is known to c3, which
+    /// means the brackets are just a part of the message
+    fn parse(input: &str) -> Option<Self> {
+        let mut suffix = Suffix::default();
+        let mut rule: Option<Recurrence> = None;
+        let mut known = false;
+        for item in input.split_whitespace() {
+            let mut item_known = true;
+            if let Some(value) = item.strip_prefix(ID_KEY) {
+                // An id that can't be one is left out, so the todo gets a new one
+                if Todo::is_valid_id(value) {
+                    suffix.id = value.to_string();
+                }
+            } else if let Some(due) = item.strip_prefix(DUE_KEY).and_then(|value| date::parse(value).ok()) {
+                suffix.due = Some(due);
+            } else if let Some(value) = item.strip_prefix(RULE_KEY).and_then(|value| value.parse().ok()) {
+                rule = Some(value);
+            } else if let Some(schedule) = suffix.schedule.is_none().then(|| item.parse().ok()).flatten() {
+                suffix.schedule = Some(schedule);
+            } else {
+                item_known = false;
+                suffix.unknown_items.push(item.to_string());
+            }
+            known = known || item_known;
+        }
+        if let (Some(schedule), Some(rule)) = (suffix.schedule.as_mut(), rule) {
+            schedule.set_rule(rule);
+        }
+        known.then_some(suffix)
+    }
 }
 
 impl FromStr for Todo {
     type Err = TodoError;
 
     fn from_str(input: &str) -> Result<Todo, Self::Err> {
-        let mut state = State::default();
-        let mut priority: u8 = 0;
+        let chars: Vec<char> = input.chars().collect();
+        if chars.first() != Some(&'[') {
+            return Err(parse_error(0, ParseErrorKind::MissingPriority));
+        }
+        let mut i = 1;
         let mut done = false;
+        if chars.get(i) == Some(&'-') {
+            done = true;
+            i += 1;
+        }
+        let priority = match chars.get(i).and_then(|c| c.to_digit(10)) {
+            Some(priority) => priority as u8,
+            None => return Err(parse_error(i, ParseErrorKind::BadPriority)),
+        };
+        i += 1;
+        if chars.get(i) != Some(&']') {
+            return Err(parse_error(i, ParseErrorKind::BadPriority));
+        }
+        i += 1;
+
         let mut dependency_string = String::new();
-        let mut schedule_string = String::new();
-        let mut message = String::new();
-        let mut schedule_start_index: Option<usize> = None;
-        if input.ends_with(']') {
-            schedule_start_index = input.chars().collect::<Vec<_>>().into_iter().rposition(|c| c == '[');
-            if let Some(start) = schedule_start_index {
-                let end = input.chars().count();
-                schedule_string = input.chars().skip(start+1).take(end-start-2).collect();
+        if chars.get(i) == Some(&'>') {
+            i += 1;
+            while let Some(&c) = chars.get(i).filter(|&&c| c != ' ') {
+                dependency_string.push(c);
+                i += 1;
             }
         }
+        match chars.get(i) {
+            Some(' ') => i += 1,
+            None => return Err(parse_error(i, ParseErrorKind::EmptyMessage)),
+            Some(_) => return Err(parse_error(i, ParseErrorKind::MissingSpace)),
+        }
 
-        for (i, c) in input.chars().enumerate() {
-            match state {
-                State::Priority => {
-                    if c == '-' {
-                        done = true;
-                    } else if c.is_ascii_digit() {
-                        priority = c.to_digit(10).unwrap() as u8;
-                    } else if c == ' ' {
-                        state = State::Message;
-                    } else if c == '>' {
-                        state = State::Dependency;
-                    }
-                }
-                State::Dependency => {
-                    if c == ' ' {
-                        state = State::Message;
-                    } else {
-                        dependency_string.push(c);
-                    }
-                }
-                State::Message if schedule_start_index.is_none() => {
-                    message.push(c);
-                }
-                State::Message => {
-                    if i == schedule_start_index.unwrap() - 1 {
-                        break;
-                    } else {
-                        message.push(c);
-                    }
+        let rest = &chars[i..];
+        let mut message_end = rest.len();
+        let mut suffix = None;
+        let suffix_start = rest.iter().rposition(|&c| c == '[');
+        if let Some(start) = suffix_start {
+            let content: String = rest[start + 1..].iter().collect();
+            if let Some(content) = content.strip_suffix(']') {
+                suffix = Suffix::parse(content);
+                if suffix.is_some() {
+                    message_end = start;
                 }
+            } else if looks_like_suffix(&content) {
+                return Err(parse_error(i + start, ParseErrorKind::UnterminatedSchedule));
             }
         }
-        if state == State::Message && !message.is_empty() {
-            let schedule: Option<Schedule> = schedule_string.parse().ok();
-            let dependency = dependency_string.parse().ok();
+        let mut message: String = rest[..message_end].iter().collect();
+        if message_end != rest.len() && message.ends_with(' ') {
+            message.pop();
+        }
+        if message.is_empty() {
+            return Err(parse_error(i, ParseErrorKind::EmptyMessage));
+        }
 
-            if let Some(schedule) = schedule.as_ref() {
-                done = schedule.date_should_be_done();
-            }
-            Ok(Todo {
-                dependency,
-                schedule,
-                message,
-                priority,
-                done,
-                ..Default::default()
-            })
-        } else {
-            Err(TodoError::ReadFailed)
+        let Suffix {
+            schedule,
+            id,
+            due,
+            unknown_items,
+        } = suffix.unwrap_or_default();
+        let dependency = dependency_string.parse().ok();
+        if let Some(schedule) = schedule.as_ref() {
+            done = schedule.date_should_be_done();
         }
+        Ok(Todo {
+            id,
+            due,
+            dependency,
+            schedule,
+            message,
+            priority,
+            done,
+            unknown_suffix_items: unknown_items,
+            ..Default::default()
+        })
     }
 }
 
@@ -176,12 +346,119 @@ impl Todo {
     #[inline]
     pub fn new(message: String, priority: u8) -> Self {
         Todo {
+            id: new_id(&message),
             message,
             priority: Self::fixed_priority(priority),
             ..Default::default()
         }
     }
 
+    /// A todo with everything a line of a todo file holds, for formats that keep all of it.
+    /// Line breaks, that would split the line of the todo, are turned into spaces.
+    pub(crate) fn from_parts(
+        id: String,
+        message: String,
+        priority: u8,
+        done: bool,
+        schedule: Option<Schedule>,
+        due: Option<date::Type>,
+        unknown_suffix_items: Vec<String>,
+    ) -> Self {
+        let unknown_suffix_items = unknown_suffix_items
+            .iter()
+            .flat_map(|item| item.split_whitespace())
+            .map(String::from)
+            .collect();
+        Todo {
+            id,
+            message: one_line(message),
+            priority: Self::fixed_priority(priority),
+            done,
+            schedule,
+            due,
+            unknown_suffix_items,
+            ..Default::default()
+        }
+    }
+
+    /// Items of the suffix of the todo line this version of c3 doesn't know
+    #[inline]
+    pub(crate) fn unknown_suffix_items(&self) -> &[String] {
+        &self.unknown_suffix_items
+    }
+
+    #[inline]
+    pub(crate) fn add_history(&mut self, date: date::Type) {
+        self.history.add(date);
+    }
+
+    #[inline]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[inline]
+    pub fn has_id(&self) -> bool {
+        !self.id.is_empty()
+    }
+
+    /// Whether the text can be the id of a todo. Ids name the files of their todos, so they're
+    /// only letters, digits, `-`, `_` and `@`
+    #[inline]
+    pub fn is_valid_id(id: &str) -> bool {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '@'))
+    }
+
+    /// Gives the todo an id it already had, like one given to its line before
+    #[inline]
+    pub(super) fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    #[inline]
+    pub fn renew_id(&mut self) {
+        self.id = new_id(&self.message);
+    }
+
+    /// Whether a word of a message is a `+project` or `@context` tag
+    #[inline]
+    pub fn is_tag(word: &str) -> bool {
+        let mut chars = word.chars();
+        matches!(chars.next(), Some('+') | Some('@'))
+            && chars.next().is_some_and(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    #[inline]
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.message
+            .split_whitespace()
+            .filter(|word| Self::is_tag(word))
+            .map(|word| word.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_'))
+    }
+
+    #[inline]
+    pub fn projects(&self) -> impl Iterator<Item = &str> {
+        self.tags().filter_map(|tag| tag.strip_prefix('+'))
+    }
+
+    #[inline]
+    pub fn contexts(&self) -> impl Iterator<Item = &str> {
+        self.tags().filter_map(|tag| tag.strip_prefix('@'))
+    }
+
+    /// A tag without `+` or `@` matches both a project and a context with that name
+    #[inline]
+    pub fn has_tag(&self, tag: &str) -> bool {
+        if Self::is_tag(tag) {
+            self.tags().any(|item| item == tag)
+        } else {
+            self.tags().any(|item| &item[1..] == tag)
+        }
+    }
+
     #[inline]
     pub fn matches(&self, query: &str) -> bool {
         self.message.contains(query) || self.message.to_lowercase().contains(query)
@@ -204,6 +481,37 @@ impl Todo {
         true
     }
 
+    /// Days left until the due date, negative if the todo is overdue
+    #[inline]
+    pub fn days_until_due(&self) -> Option<i64> {
+        self.due.map(|due| date::diff_days(Some(due), Some(date::current())))
+    }
+
+    #[inline]
+    pub fn is_overdue(&self) -> bool {
+        !self.done && self.days_until_due().is_some_and(|days| days < 0)
+    }
+
+    #[inline]
+    pub fn set_due(&mut self, due: Option<date::Type>) {
+        self.due = due;
+    }
+
+    #[inline(always)]
+    fn display_due(&self) -> String {
+        if self.done {
+            return self.due.map_or(String::new(), |due| format!(" (due {})", date::display(Some(due))));
+        }
+        match self.days_until_due() {
+            None => String::new(),
+            Some(0) => String::from(" (due today)"),
+            Some(1) => String::from(" (due tomorrow)"),
+            Some(-1) => String::from(" (overdue 1 day)"),
+            Some(days) if days < 0 => format!(" (overdue {} days)", -days),
+            Some(days) => format!(" (due in {days} days)"),
+        }
+    }
+
     pub fn abandonment_coefficient(&self) -> f64 {
         self.schedule.as_ref().map_or(1., |sch| {
             if sch.is_reminder() {
@@ -225,22 +533,34 @@ impl Todo {
         }
     }
 
+    /// Like `add_todo_dependency`, but the file gets a name of its own, for todos that might
+    /// have the same message and priority as others. It's not named after the id, as that can
+    /// still be renewed when the todo is added to a tree
     #[inline]
-    pub fn delete_dependency_file(&mut self, path: &Path) -> io::Result<()> {
-        if let Some(dependency) = &mut self.dependency {
-            dependency.todo_list.remove_dependency_files(path)?;
-            let _ = fs::remove_file(path.join(dependency.name()));
+    pub(crate) fn add_unique_todo_dependency(&mut self) {
+        if self.dependency.is_none() {
+            self.dependency = Some(Dependency::new_todo_list(sha1(&new_id(&self.message))));
         }
-        Ok(())
     }
 
+    /// Stages removal of the files of the todo and its sub todos from the notes directory
     #[inline]
-    pub fn delete_removed_dependent_files(&mut self, path: &Path) -> io::Result<()> {
-        if let Some(dependency) = &mut self.removed_dependency {
-            let _ = dependency.todo_list.remove_dependency_files(path);
-            let _ = fs::remove_file(path.join(dependency.name()));
+    pub fn stage_dependency_removal(&self, path: &Path, transaction: &mut Transaction) {
+        if let Some(history_path) = self.history_path(path) {
+            transaction.remove(&history_path);
+        }
+        if let Some(dependency) = &self.dependency {
+            dependency.todo_list.stage_dependency_removal(path, transaction);
+            transaction.remove(&path.join(dependency.name()));
+        }
+    }
+
+    #[inline]
+    pub fn stage_removed_dependency(&self, path: &Path, transaction: &mut Transaction) {
+        if let Some(dependency) = &self.removed_dependency {
+            dependency.todo_list.stage_dependency_removal(path, transaction);
+            transaction.remove(&path.join(dependency.name()));
         }
-        Ok(())
     }
 
     #[inline]
@@ -268,7 +588,7 @@ impl Todo {
     pub fn edit_note(&mut self) -> io::Result<bool> {
         let note = self.dependency.as_ref().and_then(|dep| dep.note());
         let new_note = open_note_temp_editor(note)?;
-        if !new_note.is_empty() && note.map_or(true, |note| note != new_note) {
+        if !new_note.is_empty() && note.is_none_or(|note| note != new_note) {
             self.set_note(new_note)?;
             Ok(true)
         } else {
@@ -308,6 +628,14 @@ impl Todo {
         }
     }
 
+    #[inline]
+    pub fn enable_rule(&mut self, rule: Recurrence) {
+        match self.schedule.as_mut() {
+            Some(schedule) if !schedule.is_reminder() => schedule.set_rule(rule),
+            _ => self.schedule = Some(Schedule::new_rule(rule)),
+        }
+    }
+
     #[inline]
     pub fn enable_day(&mut self, day: i64) {
         if self.schedule.is_none() {
@@ -320,10 +648,98 @@ impl Todo {
     pub fn set_done(&mut self, done: bool) {
         if let Some(schedule) = self.schedule.as_mut() {
             schedule.set_current_date();
+            if !schedule.is_reminder() && done != self.done {
+                if done {
+                    self.history.add(date::current());
+                } else {
+                    self.history.remove(date::current());
+                }
+            }
         }
         self.done = done;
     }
 
+    /// The history file in the notes directory, None without a valid id to name it after
+    #[inline(always)]
+    fn history_path(&self, path: &Path) -> Option<PathBuf> {
+        Self::is_valid_id(&self.id).then(|| path.join(format!("{}.history", self.id)))
+    }
+
+    #[inline]
+    pub(super) fn read_history(&mut self, path: &Path) -> io::Result<()> {
+        if let Some(history_path) = self.history_path(path).filter(|_| self.schedule.is_some()) {
+            self.history.read(&history_path)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(super) fn stage_history(&self, path: &Path, transaction: &mut Transaction) -> io::Result<()> {
+        if let Some(history_path) = self.history_path(path) {
+            self.history.stage(&history_path, transaction)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(super) fn touch_history(&mut self) {
+        self.history.touch();
+    }
+
+    /// Gives the todo and the todos under it new ids and files, for a copy of a todo that's
+    /// still in the tree
+    pub(super) fn renew(&mut self) {
+        self.renew_id();
+        self.touch_history();
+        if let Some(dependency) = self.dependency.as_mut() {
+            dependency.renew(sha1(&new_id(&self.message)));
+        }
+    }
+
+    /// Makes the files of the todo and of the todos under it get written again, as a write
+    /// might have removed them since
+    pub(super) fn touch_files(&mut self, notes_dir: &Path) {
+        self.touch_history();
+        if let Some(dependency) = self.dependency.as_mut() {
+            dependency.touch(notes_dir);
+        }
+    }
+
+    #[inline]
+    pub(super) fn mark_history_written(&mut self) {
+        self.history.mark_written();
+    }
+
+    /// Dates the todo was done in, only recorded for repeating schedules
+    #[inline]
+    pub fn history(&self) -> &[date::Type] {
+        self.history.dates()
+    }
+
+    #[inline(always)]
+    fn next_due_function(&self) -> impl Fn(date::Type) -> Option<date::Type> + '_ {
+        move |date| self.schedule.as_ref().and_then(|schedule| schedule.next_due(date))
+    }
+
+    /// How many times in a row the todo was done without getting overdue, 0 if it's overdue now
+    #[inline]
+    pub fn current_streak(&self) -> usize {
+        if self.history.is_empty() {
+            return 0;
+        }
+        self.history.current_streak(self.next_due_function())
+    }
+
+    #[inline]
+    pub fn longest_streak(&self) -> usize {
+        self.history.longest_streak(self.next_due_function())
+    }
+
+    #[inline]
+    pub fn completion_rate(&self) -> Option<f64> {
+        self.history.completion_rate(self.next_due_function())
+    }
+
     #[inline]
     pub fn decrease_priority(&mut self) {
         if Self::standardize_priority(self.priority) < 9 {
@@ -351,7 +767,7 @@ impl Todo {
     #[inline(always)]
     fn cmp_value(&self) -> u8 {
         let mut priority = Self::standardize_priority(self.priority) * 2;
-        if self.schedule.as_ref().map_or(false, Schedule::is_reminder) {
+        if self.schedule.as_ref().is_some_and(Schedule::is_reminder) {
             priority -= 1;
         }
         if self.done() {
@@ -390,7 +806,7 @@ mod tests {
         let mut todo = Todo::new("Test".to_string(), 1);
         let _ = todo.set_note("Note".to_string());
 
-        let expected = "[1]>2c924e3088204ee77ba681f72be3444357932fca Test";
+        let expected = format!("[1]>2c924e3088204ee77ba681f72be3444357932fca Test [id:{}]", todo.id());
         let result: String = (&todo).into();
 
         assert_eq!(result, expected);
@@ -400,6 +816,10 @@ mod tests {
     fn test_try_from_string() {
         let input = "[1]>2c924e3088204ee77ba681f72be3444357932fca Test";
         let expected = Ok(Todo {
+            id: String::new(),
+            due: None,
+            history: History::default(),
+            unknown_suffix_items: vec![],
             last_schedule: None,
             removed_dependency: None,
             schedule: None,
@@ -417,6 +837,43 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    fn parse_error_of(input: &str) -> Option<ParseError> {
+        match input.parse::<Todo>() {
+            Err(TodoError::ReadFailed(error)) => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |column, kind| Some(ParseError { column, kind });
+        assert_eq!(parse_error_of("hello"), error(1, ParseErrorKind::MissingPriority));
+        assert_eq!(parse_error_of("[x] hello"), error(2, ParseErrorKind::BadPriority));
+        assert_eq!(parse_error_of("[-12] hello"), error(4, ParseErrorKind::BadPriority));
+        assert_eq!(parse_error_of("[1]hello"), error(4, ParseErrorKind::MissingSpace));
+        assert_eq!(parse_error_of("[1]"), error(4, ParseErrorKind::EmptyMessage));
+        assert_eq!(parse_error_of("[1] "), error(5, ParseErrorKind::EmptyMessage));
+        assert_eq!(parse_error_of("[1] [D1(2023-09-21)]"), error(5, ParseErrorKind::EmptyMessage));
+        assert_eq!(
+            parse_error_of("[1] hello [D1(2023-09-21)"),
+            error(11, ParseErrorKind::UnterminatedSchedule)
+        );
+    }
+
+    #[test]
+    fn test_parse_brackets_in_message() {
+        let todo: Todo = "[1] read [chapter 2] of the book".parse().unwrap();
+        assert_eq!(todo.message, "read [chapter 2] of the book");
+        let todo: Todo = "[1] fix [urgent]".parse().unwrap();
+        assert_eq!(todo.message, "fix [urgent]");
+        let todo: Todo = "[1] an [unclosed bracket".parse().unwrap();
+        assert_eq!(todo.message, "an [unclosed bracket");
+        let todo: Todo = "[1] check [D2(2023-09-21) color:red]".parse().unwrap();
+        assert_eq!(todo.message, "check");
+        assert_eq!(todo.unknown_suffix_items, vec!["color:red"]);
+        assert!(String::from(&todo).starts_with("[1] check [D2(2023-09-21) color:red"));
+    }
+
     #[test]
     fn test_new_todo() {
         let message = "New Todo";
@@ -426,7 +883,7 @@ mod tests {
 
         assert_eq!(todo.message, message);
         assert_eq!(todo.priority, 2);
-        assert_eq!(todo.done, false);
+        assert!(!todo.done);
     }
 
     #[test]
@@ -502,10 +959,10 @@ mod tests {
         let mut todo = Todo::new("Test".to_string(), 1);
 
         todo.toggle_done();
-        assert_eq!(todo.done(), true);
+        assert!(todo.done());
 
         todo.toggle_done();
-        assert_eq!(todo.done(), false);
+        assert!(!todo.done());
     }
 
     #[test]
@@ -569,6 +1026,100 @@ mod tests {
         assert_eq!(todo, expected);
     }
 
+    #[test]
+    fn test_id() {
+        let input = "[2] this one has an id [D1(2023-09-05) id:0123456789ab]";
+        let todo = Todo::from_str(input).unwrap();
+        assert_eq!(todo.id(), "0123456789ab");
+        assert_eq!(todo.message, "this one has an id");
+        assert_eq!(todo.schedule, "D1(2023-09-05)".parse().ok());
+        assert_eq!(String::from(&todo), input);
+
+        let todo = Todo::from_str("[2] only id [id:0123456789ab]").unwrap();
+        assert_eq!(todo.id(), "0123456789ab");
+        assert!(todo.schedule.is_none());
+
+        let todo = Todo::from_str("[2] bad id [D1() id:../../x]").unwrap();
+        assert!(!todo.has_id());
+        assert_eq!(todo.message, "bad id");
+        assert!(!Todo::is_valid_id("a b") && !Todo::is_valid_id("a.b"));
+    }
+
+    #[test]
+    fn test_history_path() {
+        let notes = Path::new("notes");
+        let todo = Todo::new(String::from("Test"), 1);
+        let expected = notes.join(format!("{}.history", todo.id()));
+        assert_eq!(todo.history_path(notes), Some(expected));
+        let schedule = "D1()".parse().ok();
+        let id = String::from("../x");
+        let todo = Todo::from_parts(id, String::from("Test"), 1, false, schedule, None, vec![]);
+        assert_eq!(todo.history_path(notes), None);
+    }
+
+    #[test]
+    fn test_id_unique() {
+        let first = Todo::new("Test".to_string(), 1);
+        let second = Todo::new("Test".to_string(), 1);
+        assert!(first.has_id());
+        assert_ne!(first.id(), second.id());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_due() {
+        let input = "[1] hand in the report [D7() due:2023-09-10 id:0123456789ab]";
+        let todo = Todo::from_str(input).unwrap();
+        assert_eq!(todo.due, date::parse("2023-09-10").ok());
+        assert_eq!(todo.schedule, "D7()".parse().ok());
+        assert_eq!(String::from(&todo), input);
+    }
+
+    #[test]
+    fn test_due_display() {
+        let mut todo = Todo::new("hand in the report".to_string(), 1);
+        todo.set_due(Some(date::add_days(date::current(), 3)));
+        assert_eq!(todo.to_string(), "1. hand in the report (due in 3 days)");
+        todo.set_due(Some(date::current()));
+        assert_eq!(todo.to_string(), "1. hand in the report (due today)");
+        todo.set_due(Some(date::add_days(date::current(), -2)));
+        assert!(todo.is_overdue());
+        assert_eq!(todo.to_string(), "1. hand in the report (overdue 2 days)");
+        todo.toggle_done();
+        assert!(!todo.is_overdue());
+    }
+
+    #[test]
+    fn test_rule() {
+        let input = "[1] water the plants [D3(2023-09-05) rule:week(mon,thu) id:0123456789ab]";
+        let todo = Todo::from_str(input).unwrap();
+        let rule: Recurrence = "mon,thu".parse().unwrap();
+        assert_eq!(todo.schedule.as_ref().and_then(Schedule::rule), Some(&rule));
+        assert!(!todo.done());
+        assert_eq!(String::from(&todo), input);
+
+        let mut todo = Todo::new("water the plants".to_string(), 1);
+        todo.enable_rule("weekdays".parse().unwrap());
+        todo.set_done(true);
+        let next = todo.schedule.as_ref().unwrap().next_occurrence().unwrap();
+        assert!(next > date::current());
+        assert!(todo.schedule.as_ref().unwrap().date_should_be_done());
+        todo.enable_day(1);
+        assert!(todo.schedule.as_ref().unwrap().rule().is_none());
+    }
+
+    #[test]
+    fn test_tags() {
+        let todo = Todo::new("write +report for @work +c3, not 3 + 4 or a@b".to_string(), 1);
+        assert_eq!(todo.tags().collect::<Vec<_>>(), vec!["+report", "@work", "+c3"]);
+        assert_eq!(todo.projects().collect::<Vec<_>>(), vec!["report", "c3"]);
+        assert_eq!(todo.contexts().collect::<Vec<_>>(), vec!["work"]);
+        assert!(todo.has_tag("+report"));
+        assert!(todo.has_tag("work"));
+        assert!(!todo.has_tag("+work"));
+        assert!(!todo.has_tag("b"));
+    }
+
     #[test]
     fn test_multicharacter() {
         let input = "[0] 三个字, 三个字 [D1()]";
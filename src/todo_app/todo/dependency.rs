@@ -3,9 +3,9 @@
 use crate::todo_app::todo_list::TodoCmp;
 
 use super::TodoList;
+use crate::fileio::{self, Fingerprint, Transaction};
 use std::str::FromStr;
 use std::{
-    fs::File,
     io::{self, Write},
     path::Path,
 };
@@ -24,6 +24,7 @@ pub struct Dependency {
     mode: DependencyMode,
     note: String,
     written: bool,
+    fingerprint: Option<Fingerprint>,
     pub(crate) todo_list: TodoList,
 }
 
@@ -74,7 +75,10 @@ impl Dependency {
         let name_todo = format!("{}.todo", self.name);
         match self.mode {
             DependencyMode::Note if path.join(&self.name).is_file() => {
-                self.note = std::fs::read_to_string(file_path)?;
+                let (fingerprint, content) = Fingerprint::read(&file_path)?;
+                self.note = String::from_utf8(content)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                self.fingerprint = Some(fingerprint);
             }
             DependencyMode::Note | DependencyMode::TodoList
                 // Sometimes calcurse likes to remove the extra .todo from the file name
@@ -99,38 +103,88 @@ impl Dependency {
     }
 
     #[inline]
-    pub fn write(&mut self, path: &Path) -> io::Result<()> {
+    pub fn stage(&self, path: &Path, transaction: &mut Transaction) -> io::Result<()> {
+        match self.mode {
+            DependencyMode::TodoList => self.todo_list.stage(&path.join(&self.name), transaction),
+            DependencyMode::Note if !self.written => self.stage_note(path, transaction),
+            _ => Ok(()),
+        }
+    }
+
+    #[inline(always)]
+    fn stage_note(&self, path: &Path, transaction: &mut Transaction) -> io::Result<()> {
+        transaction.write(&path.join(&self.name), |writer| write!(writer, "{}", self.note))
+    }
+
+    #[inline]
+    pub fn force_stage(&self, path: &Path, transaction: &mut Transaction) -> io::Result<()> {
         match self.mode {
             DependencyMode::TodoList => {
-                self.todo_list.write(&path.join(&self.name))?;
+                self.todo_list.force_stage(&path.join(&self.name), transaction)
             }
+            DependencyMode::Note => self.stage_note(path, transaction),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn mark_written(&mut self, path: &Path) {
+        match self.mode {
+            DependencyMode::TodoList => self.todo_list.mark_written(&path.join(&self.name), path),
             DependencyMode::Note if !self.written => {
-                self.write_note(path)?;
+                self.fingerprint = Fingerprint::read(&path.join(&self.name))
+                    .ok()
+                    .map(|(fingerprint, _)| fingerprint);
             }
             _ => {}
-        };
+        }
         self.written = true;
-        Ok(())
     }
 
-    #[inline(always)]
-    fn write_note(&self, path: &Path) -> io::Result<()> {
-        let mut file = File::create(path.join(&self.name))?;
-        write!(file, "{}", self.note)?;
-        Ok(())
+    /// Makes the note or the sub list with everything under it get written again. A file that
+    /// isn't there anymore was removed by c3, not changed by something else.
+    pub(crate) fn touch(&mut self, path: &Path) {
+        let file_path = path.join(&self.name);
+        self.written = false;
+        if !file_path.exists() {
+            self.fingerprint = None;
+        }
+        if self.is_list() {
+            self.todo_list.touch(&file_path, path);
+        }
+    }
+
+    /// Moves the note or the sub list with the todos under it to new files, named after the
+    /// hash, so that a copy doesn't share them with the todo it was copied from
+    pub(crate) fn renew(&mut self, hash: String) {
+        self.name = match self.mode {
+            DependencyMode::TodoList => format!("{hash}.todo"),
+            DependencyMode::Note => hash,
+        };
+        self.written = false;
+        self.fingerprint = None;
+        if self.is_list() {
+            self.todo_list.renew();
+        }
+    }
+
+    /// Whether the note file was changed by something else since it was read or written
+    #[inline]
+    pub fn is_modified(&self, path: &Path) -> bool {
+        self.is_note() && fileio::is_modified(&path.join(&self.name), self.fingerprint.as_ref())
     }
 
     #[inline]
-    pub fn force_write(&self, path: &Path) -> io::Result<()> {
+    pub(crate) fn merge_external_changes(&mut self, path: &Path, todo_cmp: TodoCmp) -> io::Result<()> {
         match self.mode {
             DependencyMode::TodoList => {
-                self.todo_list.force_write(&path.join(&self.name))?;
+                self.todo_list.merge_external_changes(&path.join(&self.name), path, true)
             }
-            DependencyMode::Note => {
-                self.write_note(path)?;
+            // Notes changed in c3 are written to a new file, so the file only changed outside
+            DependencyMode::Note if self.written && self.is_modified(path) => {
+                self.read(path, todo_cmp)
             }
-        };
-        Ok(())
+            _ => Ok(()),
+        }
     }
 
     #[inline(always)]
@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::date;
+use crate::fileio::Transaction;
+
+/// Dates a scheduled todo was done in, kept sorted in a file next to the dependency files
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct History {
+    dates: Vec<date::Type>,
+    changed: bool,
+}
+
+impl History {
+    #[inline]
+    pub fn dates(&self) -> &[date::Type] {
+        &self.dates
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.dates.is_empty()
+    }
+
+    pub fn add(&mut self, date: date::Type) {
+        if let Err(index) = self.dates.binary_search(&date) {
+            self.dates.insert(index, date);
+            self.changed = true;
+        }
+    }
+
+    pub fn remove(&mut self, date: date::Type) {
+        if let Ok(index) = self.dates.binary_search(&date) {
+            self.dates.remove(index);
+            self.changed = true;
+        }
+    }
+
+    pub fn read(&mut self, path: &Path) -> io::Result<()> {
+        if path.is_file() {
+            let mut dates: Vec<date::Type> = fs::read_to_string(path)?
+                .lines()
+                .flat_map(|line| date::parse(line.trim()))
+                .collect();
+            dates.sort();
+            dates.dedup();
+            self.dates = dates;
+        }
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Stages the history file, or its removal if there's nothing left in it
+    pub fn stage(&self, path: &Path, transaction: &mut Transaction) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        if self.dates.is_empty() {
+            transaction.remove(path);
+            Ok(())
+        } else {
+            transaction.write(path, |writer| {
+                for date in &self.dates {
+                    writeln!(writer, "{}", date::format(Some(*date)))?;
+                }
+                Ok(())
+            })
+        }
+    }
+
+    /// Makes the history get written again, even if its dates didn't change
+    #[inline]
+    pub fn touch(&mut self) {
+        self.changed = true;
+    }
+
+    #[inline]
+    pub fn mark_written(&mut self) {
+        self.changed = false;
+    }
+
+    /// Lengths of the runs of completions where each one was done before the todo got overdue
+    /// from the last one. `next_due` gives the date a todo done in a date should be done again.
+    fn streaks(&self, next_due: impl Fn(date::Type) -> Option<date::Type>) -> Vec<usize> {
+        let mut streaks = vec![];
+        let mut current = 0;
+        let mut last: Option<date::Type> = None;
+        for &date in &self.dates {
+            match last.and_then(&next_due) {
+                Some(due) if date <= due => current += 1,
+                _ => {
+                    if current > 0 {
+                        streaks.push(current);
+                    }
+                    current = 1;
+                }
+            }
+            last = Some(date);
+        }
+        if current > 0 {
+            streaks.push(current);
+        }
+        streaks
+    }
+
+    pub fn current_streak(&self, next_due: impl Fn(date::Type) -> Option<date::Type>) -> usize {
+        let alive = self
+            .dates
+            .last()
+            .and_then(|&last| next_due(last))
+            .is_some_and(|due| date::current() <= due);
+        if alive {
+            self.streaks(next_due).last().copied().unwrap_or_default()
+        } else {
+            0
+        }
+    }
+
+    pub fn longest_streak(&self, next_due: impl Fn(date::Type) -> Option<date::Type>) -> usize {
+        self.streaks(next_due).into_iter().max().unwrap_or_default()
+    }
+
+    /// How many of the times the todo was due since its first completion it was actually done,
+    /// from 0 to 1
+    pub fn completion_rate(
+        &self,
+        next_due: impl Fn(date::Type) -> Option<date::Type>,
+    ) -> Option<f64> {
+        let first = *self.dates.first()?;
+        let today = date::current();
+        let mut expected = 1;
+        let mut due = first;
+        while let Some(next) = next_due(due).filter(|&next| next <= today && next > due) {
+            expected += 1;
+            due = next;
+        }
+        Some((self.dates.len() as f64 / expected as f64).min(1.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write(history: &mut History, path: &Path) -> io::Result<()> {
+        let mut transaction = Transaction::new();
+        history.stage(path, &mut transaction)?;
+        transaction.commit()?;
+        history.mark_written();
+        Ok(())
+    }
+
+    fn days_ago(days: i64) -> date::Type {
+        date::add_days(date::current(), -days)
+    }
+
+    fn daily(date: date::Type) -> Option<date::Type> {
+        Some(date::add_days(date, 1))
+    }
+
+    #[test]
+    fn test_streaks() {
+        let mut history = History::default();
+        for days in [9, 8, 7, 5, 4, 3, 2, 1] {
+            history.add(days_ago(days));
+        }
+        history.add(days_ago(1));
+        assert_eq!(history.dates().len(), 8);
+        assert_eq!(history.current_streak(daily), 5);
+        assert_eq!(history.longest_streak(daily), 5);
+        assert_eq!(history.completion_rate(daily), Some(8. / 10.));
+        history.remove(days_ago(1));
+        history.remove(days_ago(2));
+        assert_eq!(history.current_streak(daily), 0);
+        assert_eq!(history.longest_streak(daily), 3);
+    }
+
+    #[test]
+    fn test_read_write() -> io::Result<()> {
+        let dir = PathBuf::from("test-history-read-write");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("history");
+        let mut history = History::default();
+        history.add(days_ago(1));
+        history.add(days_ago(3));
+        write(&mut history, &path)?;
+        let mut read_history = History::default();
+        read_history.read(&path)?;
+        assert_eq!(read_history.dates(), history.dates());
+        read_history.remove(days_ago(1));
+        read_history.remove(days_ago(3));
+        write(&mut read_history, &path)?;
+        let exists = path.exists();
+        fs::remove_dir_all(dir)?;
+        assert!(!exists);
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use chrono::{Datelike, Weekday};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::date;
+
+const WORK_DAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+// Every valid rule happens at least once a year, so there's no need to look further than that.
+const MAX_DAYS_BETWEEN: i64 = 366;
+
+/// Calendar based repeating rules for scheduled todos
+#[derive(Eq, Debug, PartialEq, Clone)]
+pub enum Recurrence {
+    /// Every one of these days of the week
+    Weekdays(Vec<Weekday>),
+    /// These days of each month, negative days counting from the end (-1 is the last day)
+    MonthDays(Vec<i8>),
+    /// The nth given weekday of each month, negative counting from the end (-1 is the last)
+    MonthWeekday(i8, Weekday),
+}
+
+#[derive(Debug)]
+pub struct RecurrenceMalformed;
+
+fn parse_weekday(input: &str) -> Result<Weekday, RecurrenceMalformed> {
+    input.parse().map_err(|_| RecurrenceMalformed)
+}
+
+fn parse_day(input: &str) -> Result<i8, RecurrenceMalformed> {
+    match input.parse::<i8>() {
+        Ok(day) if day != 0 && (-31..=31).contains(&day) => Ok(day),
+        _ => Err(RecurrenceMalformed),
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = RecurrenceMalformed;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim().to_lowercase();
+        if input == "weekdays" {
+            return Ok(Self::Weekdays(WORK_DAYS.to_vec()));
+        }
+        let (kind, items) = match input.split_once('(') {
+            Some((kind, rest)) => (kind, rest.strip_suffix(')').ok_or(RecurrenceMalformed)?),
+            None => ("week", input.as_str()),
+        };
+        let items: Vec<&str> = items.split(',').map(str::trim).collect();
+        match kind {
+            "week" => {
+                let mut days = items
+                    .into_iter()
+                    .map(parse_weekday)
+                    .collect::<Result<Vec<_>, _>>()?;
+                days.sort_by_key(Weekday::num_days_from_monday);
+                days.dedup();
+                Ok(Self::Weekdays(days))
+            }
+            "month" => match items[..] {
+                [weekday, nth] if parse_weekday(weekday).is_ok() => {
+                    let nth = parse_day(nth)?;
+                    if !(-5..=5).contains(&nth) {
+                        return Err(RecurrenceMalformed);
+                    }
+                    Ok(Self::MonthWeekday(nth, parse_weekday(weekday)?))
+                }
+                _ => {
+                    let mut days = items
+                        .into_iter()
+                        .map(parse_day)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    days.sort();
+                    days.dedup();
+                    Ok(Self::MonthDays(days))
+                }
+            },
+            _ => Err(RecurrenceMalformed),
+        }
+    }
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl From<&Recurrence> for String {
+    fn from(recurrence: &Recurrence) -> String {
+        match recurrence {
+            Recurrence::Weekdays(days) if days[..] == WORK_DAYS => String::from("weekdays"),
+            Recurrence::Weekdays(days) => format!("week({})", join(days).to_lowercase()),
+            Recurrence::MonthDays(days) => format!("month({})", join(days)),
+            Recurrence::MonthWeekday(nth, weekday) => {
+                format!("month({},{nth})", weekday.to_string().to_lowercase())
+            }
+        }
+    }
+}
+
+fn ordinal(nth: i8) -> String {
+    match nth {
+        -1 => String::from("last"),
+        -2 => String::from("second to last"),
+        any if any < 0 => format!("{} to last", ordinal(-any)),
+        any => {
+            let suffix = match (any % 10, any % 100) {
+                (_, 11..=13) => "th",
+                (1, _) => "st",
+                (2, _) => "nd",
+                (3, _) => "rd",
+                _ => "th",
+            };
+            format!("{any}{suffix}")
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Weekdays(days) if days[..] == WORK_DAYS => write!(f, "Every weekday"),
+            Self::Weekdays(days) => {
+                let days: Vec<String> = days.iter().map(Weekday::to_string).collect();
+                write!(f, "Every {}", days.join(", "))
+            }
+            Self::MonthDays(days) => {
+                let days: Vec<String> = days.iter().map(|&day| ordinal(day)).collect();
+                write!(f, "Monthly on the {}", days.join(", "))
+            }
+            Self::MonthWeekday(nth, weekday) => {
+                write!(f, "Monthly on the {} {weekday}", ordinal(*nth))
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn days_in_month(date: date::Type) -> u32 {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    date::Type::from_ymd_opt(year, month, 1)
+        .and_then(|first| first.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+impl Recurrence {
+    pub fn matches(&self, date: date::Type) -> bool {
+        let day = date.day() as i8;
+        let last = days_in_month(date) as i8;
+        match self {
+            Self::Weekdays(days) => days.contains(&date.weekday()),
+            Self::MonthDays(days) => days
+                .iter()
+                .any(|&nth| nth == day || (nth < 0 && last + nth + 1 == day)),
+            Self::MonthWeekday(nth, weekday) => {
+                date.weekday() == *weekday
+                    && if *nth > 0 {
+                        (day - 1) / 7 + 1 == *nth
+                    } else {
+                        (last - day) / 7 + 1 == -nth
+                    }
+            }
+        }
+    }
+
+    /// First date after the given date that the rule happens
+    pub fn next_occurrence(&self, after: date::Type) -> Option<date::Type> {
+        (1..=MAX_DAYS_BETWEEN)
+            .map(|days| date::add_days(after, days))
+            .find(|&date| self.matches(date))
+    }
+
+    /// Average days between two occurrences. Used as the day interval of the schedule, for
+    /// sorting and for versions of c3 that don't know about rules.
+    pub fn days(&self) -> i64 {
+        match self {
+            Self::Weekdays(days) => (7 / days.len().max(1) as i64).max(1),
+            Self::MonthDays(days) => (30 / days.len().max(1) as i64).max(1),
+            Self::MonthWeekday(..) => 30,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(input: &str) -> date::Type {
+        date::parse(input).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("weekdays".parse::<Recurrence>().ok(), Some(Recurrence::Weekdays(WORK_DAYS.to_vec())));
+        assert_eq!(
+            "thu,Monday".parse::<Recurrence>().ok(),
+            Some(Recurrence::Weekdays(vec![Weekday::Mon, Weekday::Thu]))
+        );
+        assert_eq!("month(15,1)".parse::<Recurrence>().ok(), Some(Recurrence::MonthDays(vec![1, 15])));
+        assert_eq!(
+            "month(fri,-1)".parse::<Recurrence>().ok(),
+            Some(Recurrence::MonthWeekday(-1, Weekday::Fri))
+        );
+        assert!("month(0)".parse::<Recurrence>().is_err());
+        assert!("month(fri,6)".parse::<Recurrence>().is_err());
+        assert!("fortnight".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn test_into_string() {
+        for input in ["weekdays", "week(mon,thu)", "month(-1,1)", "month(fri,-1)"] {
+            let recurrence: Recurrence = input.parse().unwrap();
+            assert_eq!(String::from(&recurrence), input);
+        }
+    }
+
+    #[test]
+    fn test_next_occurrence() {
+        // 2024-05-03 is a friday
+        let friday = date("2024-05-03");
+        let weekdays: Recurrence = "weekdays".parse().unwrap();
+        assert_eq!(weekdays.next_occurrence(friday), Some(date("2024-05-06")));
+        let mon_thu: Recurrence = "mon,thu".parse().unwrap();
+        assert_eq!(mon_thu.next_occurrence(date("2024-05-06")), Some(date("2024-05-09")));
+        let first: Recurrence = "month(1)".parse().unwrap();
+        assert_eq!(first.next_occurrence(friday), Some(date("2024-06-01")));
+        let last_day: Recurrence = "month(-1)".parse().unwrap();
+        assert_eq!(last_day.next_occurrence(date("2024-02-01")), Some(date("2024-02-29")));
+        let last_friday: Recurrence = "month(fri,-1)".parse().unwrap();
+        assert_eq!(last_friday.next_occurrence(friday), Some(date("2024-05-31")));
+        let first_monday: Recurrence = "month(mon,1)".parse().unwrap();
+        assert_eq!(first_monday.next_occurrence(friday), Some(date("2024-05-06")));
+    }
+
+    #[test]
+    fn test_display() {
+        let last_friday: Recurrence = "month(fri,-1)".parse().unwrap();
+        assert_eq!(last_friday.to_string(), "Monthly on the last Fri");
+        let days: Recurrence = "month(1,22)".parse().unwrap();
+        assert_eq!(days.to_string(), "Monthly on the 1st, 22nd");
+        let mon_thu: Recurrence = "mon,thu".parse().unwrap();
+        assert_eq!(mon_thu.to_string(), "Every Mon, Thu");
+    }
+}
@@ -1,7 +1,10 @@
 use std::str::FromStr;
 
+use super::recurrence::Recurrence;
 use crate::date;
 
+pub(super) const RULE_KEY: &str = "rule:";
+
 #[derive(Eq, Debug, PartialEq, Clone, Default)]
 pub enum ScheduleMode {
     #[default]
@@ -14,15 +17,7 @@ pub struct Schedule {
     day: i64,
     saved_date: Option<date::Type>,
     mode: ScheduleMode,
-}
-
-#[derive(Default)]
-enum State {
-    #[default]
-    Type,
-    Days,
-    PreDate,
-    Date,
+    rule: Option<Recurrence>,
 }
 
 pub struct NotScheduled;
@@ -30,61 +25,24 @@ pub struct NotScheduled;
 impl FromStr for Schedule {
     type Err = NotScheduled;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut date_string = String::new();
-        let mut state = State::default();
-        let mut mode = None;
-        let mut day_str = String::new();
-
-        for c in s.to_string().chars() {
-            match state {
-                State::Type => {
-                    if c == 'D' {
-                        mode = Some(ScheduleMode::Scheduled);
-                        state = State::Days;
-                    } else if c == 'R' {
-                        mode = Some(ScheduleMode::Reminder);
-                        state = State::PreDate;
-                    } else {
-                        break;
-                    }
-                }
-                State::Days => {
-                    if c.is_ascii_digit() {
-                        day_str.push(c);
-                    } else if c == '(' {
-                        state = State::Date;
-                    }
-                }
-                State::PreDate => {
-                    if c == '(' {
-                        state = State::Date;
-                    }
-                }
-                State::Date => {
-                    if c == ')' {
-                        break;
-                    } else {
-                        date_string.push(c)
-                    }
-                }
+        let (head, date_string) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or(NotScheduled)?;
+        let (mode, day) = match head.split_at_checked(1) {
+            Some(("R", "")) => (ScheduleMode::Reminder, 0),
+            Some(("D", day)) if day.bytes().all(|b| b.is_ascii_digit()) => {
+                (ScheduleMode::Scheduled, day.parse().unwrap_or(0))
             }
-        }
-
-        let day: i64 = day_str.parse().unwrap_or(0);
-
-        let date = match date::parse(&date_string) {
-            Ok(value) => Some(value),
-            Err(_) => None,
+            _ => return Err(NotScheduled),
         };
-        if let Some(mode) = mode {
-            Ok(Schedule {
-                day,
-                saved_date: date,
-                mode,
-            })
-        } else {
-            Err(Self::Err {})
-        }
+
+        Ok(Schedule {
+            day,
+            saved_date: date::parse(date_string).ok(),
+            mode,
+            rule: None,
+        })
     }
 }
 
@@ -92,9 +50,12 @@ impl From<&Schedule> for String {
     fn from(schedule: &Schedule) -> String {
         let date_str = date::format(schedule.saved_date);
 
-        match schedule.mode {
-            ScheduleMode::Reminder => format!(" [R({date_str})]"),
-            ScheduleMode::Scheduled => format!(" [D{}({date_str})]", schedule.day),
+        match (&schedule.mode, &schedule.rule) {
+            (ScheduleMode::Reminder, _) => format!("R({date_str})"),
+            (ScheduleMode::Scheduled, None) => format!("D{}({date_str})", schedule.day),
+            (ScheduleMode::Scheduled, Some(rule)) => {
+                format!("D{}({date_str}) {RULE_KEY}{}", schedule.day, String::from(rule))
+            }
         }
     }
 }
@@ -107,6 +68,14 @@ impl Schedule {
         }
     }
 
+    pub fn new_rule(rule: Recurrence) -> Self {
+        Schedule {
+            day: rule.days(),
+            rule: Some(rule),
+            ..Default::default()
+        }
+    }
+
     pub fn new_reminder(date: date::Type) -> Self {
         Self {
             mode: ScheduleMode::Reminder,
@@ -150,8 +119,44 @@ impl Schedule {
         }
     }
 
+    /// Date a todo done in the given date is due again
+    #[inline]
+    pub fn next_due(&self, after: date::Type) -> Option<date::Type> {
+        match (&self.mode, &self.rule) {
+            (ScheduleMode::Reminder, _) => None,
+            (ScheduleMode::Scheduled, Some(rule)) => rule.next_occurrence(after),
+            (ScheduleMode::Scheduled, None) if self.day > 0 => Some(date::add_days(after, self.day)),
+            (ScheduleMode::Scheduled, None) => None,
+        }
+    }
+
+    /// Next day the todo should be done again, for calendar rules only
+    #[inline]
+    pub fn next_occurrence(&self) -> Option<date::Type> {
+        match (&self.mode, &self.rule, self.saved_date) {
+            (ScheduleMode::Scheduled, Some(rule), Some(date)) => rule.next_occurrence(date),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    fn display_rule(&self, rule: &Recurrence, streak_str: &str) -> String {
+        let days_to_next = self
+            .next_occurrence()
+            .map(|next| date::diff_days(Some(next), Some(date::current())));
+        let inner_str = match days_to_next {
+            Some(1) => String::from(", next tomorrow"),
+            Some(any) if any > 1 => format!(", next in {any} days"),
+            _ => String::new(),
+        };
+        format!(" ({rule}{inner_str}{streak_str})")
+    }
+
     #[inline(always)]
-    fn display_scheduled(&self) -> String {
+    fn display_scheduled(&self, streak_str: &str) -> String {
+        if let Some(rule) = self.rule.as_ref() {
+            return self.display_rule(rule, streak_str);
+        }
         let inner_str = match self.current_minus_saved_date() {
             ..=0 => String::new(),
             1 => String::from(", last done yesterday"),
@@ -160,17 +165,25 @@ impl Schedule {
             any => format!(", last done {} days ago", any),
         };
         match self.day {
-            1 => format!(" (Daily{inner_str})"),
-            7 => format!(" (Weekly{inner_str})"),
-            day if day % 7 == 0 => format!(" (Each {} weeks{inner_str})", day / 7),
-            day => format!(" (Each {day} days{inner_str})"),
+            1 => format!(" (Daily{inner_str}{streak_str})"),
+            7 => format!(" (Weekly{inner_str}{streak_str})"),
+            day if day % 7 == 0 => format!(" (Each {} weeks{inner_str}{streak_str})", day / 7),
+            day => format!(" (Each {day} days{inner_str}{streak_str})"),
         }
     }
 
     pub fn display(&self) -> String {
+        self.display_with_streak(0)
+    }
+
+    pub fn display_with_streak(&self, streak: usize) -> String {
+        let streak_str = match streak {
+            0 => String::new(),
+            streak => format!(", streak {streak}"),
+        };
         match self.mode {
             ScheduleMode::Reminder => self.display_reminder(),
-            ScheduleMode::Scheduled => self.display_scheduled(),
+            ScheduleMode::Scheduled => self.display_scheduled(&streak_str),
         }
     }
 
@@ -188,6 +201,30 @@ impl Schedule {
 
     pub fn set_day(&mut self, day: i64) {
         self.day = day;
+        self.rule = None;
+    }
+
+    pub fn set_rule(&mut self, rule: Recurrence) {
+        if self.mode == ScheduleMode::Scheduled {
+            self.day = rule.days();
+            self.rule = Some(rule);
+        }
+    }
+
+    #[inline]
+    pub fn rule(&self) -> Option<&Recurrence> {
+        self.rule.as_ref()
+    }
+
+    /// Date the todo was last done in, or the date of a reminder
+    #[inline]
+    pub fn date(&self) -> Option<date::Type> {
+        self.saved_date
+    }
+
+    #[inline]
+    pub fn set_date(&mut self, date: Option<date::Type>) {
+        self.saved_date = date;
     }
 
     pub fn set_current_date(&mut self) {
@@ -204,6 +241,9 @@ impl Schedule {
     pub fn date_should_be_done(&self) -> bool {
         match self.mode {
             ScheduleMode::Reminder => self.saved_date != Some(date::current()),
+            ScheduleMode::Scheduled if self.rule.is_some() => self
+                .next_occurrence()
+                .is_some_and(|next| next > date::current()),
             ScheduleMode::Scheduled => {
                 self.saved_date.is_some() && self.current_minus_saved_date() < self.day
             }
@@ -1,28 +1,66 @@
 // vim:fileencoding=utf-8:foldmethod=marker
 // imports {{{
 use std::cmp;
-use std::fs::{read, File};
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead, BufWriter, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use super::todo::{ParseError, TodoError};
 use super::{App, Restriction, SortMethod, Todo};
+use crate::fileio::{self, Fingerprint, Transaction};
 use crate::{DisplayArgs, TodoDisplay};
 //}}}
 
 pub type TodoCmp = fn(&Todo, &Todo) -> cmp::Ordering;
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub struct TodoList {
     pub todos: Vec<Todo>,
     pub changed: bool,
     pub todo_cmp: TodoCmp,
+    malformed_lines: Vec<MalformedLine>,
+    fingerprint: Option<Fingerprint>,
+    // Lines the file had when it was last read or written by the ids of their todos, the base
+    // to tell which side changed a todo when merging
+    base: HashMap<String, String>,
+    // Ids given to todos on read that aren't in the file yet. They're written along with the
+    // next change, so that only reading the tree never writes it
+    unsaved_ids: bool,
+    // The ids of those todos by their lines in the file, to tell which todo a line without an
+    // id is when merging
+    ids_of_lines: HashMap<String, String>,
 }
 
+/// A line of a todo file that couldn't be parsed. It's written back as it was at its line, so
+/// nothing gets lost until the user fixes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedLine {
+    pub number: usize,
+    pub content: String,
+    pub error: ParseError,
+}
+
+// todo_cmp is left out, function pointers can't be reliably compared
+impl PartialEq for TodoList {
+    fn eq(&self, other: &Self) -> bool {
+        self.todos == other.todos
+            && self.changed == other.changed
+            && self.malformed_lines == other.malformed_lines
+    }
+}
+
+impl Eq for TodoList {}
+
 impl Default for TodoList {
     fn default() -> Self {
         Self {
             todos: Vec::new(),
             changed: false,
             todo_cmp: SortMethod::default().cmp_function(),
+            malformed_lines: Vec::new(),
+            fingerprint: None,
+            base: HashMap::new(),
+            unsaved_ids: false,
+            ids_of_lines: HashMap::new(),
         }
     }
 }
@@ -82,16 +120,15 @@ impl TodoList {
     }
 
     #[inline]
-    pub(super) fn delete_removed_dependent_files(&mut self, filename: &Path) -> io::Result<()> {
-        for todo in &mut self.todos {
-            if let Some(dependency) = todo.dependency.as_mut() {
+    pub(super) fn stage_removed_dependencies(&self, filename: &Path, transaction: &mut Transaction) {
+        for todo in &self.todos {
+            if let Some(dependency) = todo.dependency.as_ref() {
                 dependency
                     .todo_list
-                    .delete_removed_dependent_files(filename)?;
+                    .stage_removed_dependencies(filename, transaction);
             }
-            todo.delete_removed_dependent_files(filename)?;
+            todo.stage_removed_dependency(filename, transaction);
         }
-        Ok(())
     }
 
     #[inline]
@@ -123,25 +160,217 @@ impl TodoList {
         }
     }
 
-    pub(super) fn remove_dependency_files(&mut self, filename: &Path) -> io::Result<()> {
-        for todo in &mut self.todos {
-            todo.delete_dependency_file(filename)?;
+    pub(super) fn stage_dependency_removal(&self, filename: &Path, transaction: &mut Transaction) {
+        for todo in &self.todos {
+            todo.stage_dependency_removal(filename, transaction);
         }
-        Ok(())
     }
 
     pub fn read(filename: &Path) -> Self {
         if !filename.is_file() {
             return Self::new();
         }
-        let file_data = read(filename).unwrap();
-        Self {
-            todos: file_data
-                .lines()
-                .map_while(Result::ok)
-                .flat_map(|line| line.parse())
-                .collect(),
-            ..Default::default()
+        let (fingerprint, file_data) = Fingerprint::read(filename).unwrap();
+        let mut todo_list = Self::new();
+        todo_list.fingerprint = Some(fingerprint);
+        for (index, line) in file_data.lines().map_while(Result::ok).enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(todo) => todo_list.todos.push(todo),
+                Err(TodoError::ReadFailed(error)) => todo_list.malformed_lines.push(MalformedLine {
+                    number: index + 1,
+                    content: line,
+                    error,
+                }),
+                Err(_) => {}
+            }
+        }
+        todo_list.base = todo_list.lines_by_id();
+        todo_list
+    }
+
+    #[inline(always)]
+    fn lines_by_id(&self) -> HashMap<String, String> {
+        self.todos
+            .iter()
+            .filter(|todo| todo.has_id())
+            .map(|todo| (todo.id().to_string(), String::from(todo)))
+            .collect()
+    }
+
+    /// Files of this list and its dependencies that were changed by something else since c3
+    /// read or wrote them, along with whether they have unsaved changes here
+    pub(super) fn collect_external_changes(
+        &self,
+        filename: &Path,
+        notes_dir: &Path,
+        tree: bool,
+        output: &mut Vec<(PathBuf, bool)>,
+    ) {
+        if fileio::is_modified(filename, self.fingerprint.as_ref()) {
+            output.push((filename.to_path_buf(), self.changed));
+        }
+        if !tree {
+            return;
+        }
+        for dependency in self.todos.iter().flat_map(|todo| todo.dependency.as_ref()) {
+            if let Some(todo_list) = dependency.todo_list() {
+                todo_list.collect_external_changes(
+                    &notes_dir.join(dependency.name()),
+                    notes_dir,
+                    tree,
+                    output,
+                );
+            } else if dependency.is_modified(notes_dir) {
+                // Notes are named after their content, writing one never loses another change
+                output.push((notes_dir.join(dependency.name()), false));
+            }
+        }
+    }
+
+    pub(super) fn merge_external_changes(
+        &mut self,
+        filename: &Path,
+        notes_dir: &Path,
+        tree: bool,
+    ) -> io::Result<()> {
+        if fileio::is_modified(filename, self.fingerprint.as_ref()) {
+            self.merge(TodoList::read(filename), notes_dir, tree)?;
+        }
+        if tree {
+            let todo_cmp = self.todo_cmp;
+            for todo in &mut self.todos {
+                if let Some(dependency) = todo.dependency.as_mut() {
+                    dependency.merge_external_changes(notes_dir, todo_cmp)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Line level three way merge of the version of this list on the disk into it, with the
+    /// lines of the file when c3 last read or wrote it as the base. Todos are matched by id, or
+    /// by content for lines without one. The side that changed a todo wins over the one that
+    /// didn't, and c3 wins when both did. A todo that's only on one side is kept, unless it's
+    /// in the base and the other side removed it without it being changed in c3.
+    fn merge(&mut self, mut theirs: TodoList, notes_dir: &Path, tree: bool) -> io::Result<()> {
+        // Lines that are still without an id are the todos c3 gave an id to
+        let mut ids_of_lines = std::mem::take(&mut self.ids_of_lines);
+        for todo in theirs.todos.iter_mut().filter(|todo| !todo.has_id()) {
+            if let Some(id) = ids_of_lines.remove(&String::from(&*todo)) {
+                todo.set_id(id);
+                self.unsaved_ids = true;
+            }
+        }
+        let their_lines = theirs.lines_by_id();
+        let base = std::mem::take(&mut self.base);
+        let unchanged = |todo: &Todo| base.get(todo.id()).is_some_and(|line| *line == String::from(todo));
+        self.todos
+            .retain(|todo| their_lines.contains_key(todo.id()) || !unchanged(todo));
+        for todo in theirs.todos {
+            if !todo.has_id() {
+                if !self.todos.contains(&todo) {
+                    self.push_theirs(todo, None, notes_dir, tree)?;
+                }
+                continue;
+            }
+            match self.position_of_id(todo.id()) {
+                Some(index) if unchanged(&self.todos[index]) => {
+                    let ours = self.todos.remove(index);
+                    self.push_theirs(todo, Some(ours), notes_dir, tree)?;
+                }
+                // Removed in c3, or changed on both sides
+                _ if base.contains_key(todo.id()) => {}
+                None => self.push_theirs(todo, None, notes_dir, tree)?,
+                Some(_) => {}
+            }
+        }
+        self.base = their_lines;
+        self.fingerprint = theirs.fingerprint;
+        self.malformed_lines = theirs.malformed_lines;
+        self.changed = true;
+        self.sort();
+        Ok(())
+    }
+
+    /// Adds their version of a todo, with the sub list or note c3 already read for it if it
+    /// still points to the same file
+    fn push_theirs(
+        &mut self,
+        mut todo: Todo,
+        ours: Option<Todo>,
+        notes_dir: &Path,
+        tree: bool,
+    ) -> io::Result<()> {
+        let our_dependency = ours.and_then(|ours| ours.dependency);
+        let same_file = match (&todo.dependency, &our_dependency) {
+            (Some(their_dependency), Some(our_dependency)) => {
+                their_dependency.name() == our_dependency.name()
+            }
+            _ => false,
+        };
+        if same_file {
+            todo.dependency = our_dependency;
+        } else if let Some(dependency) = todo.dependency.as_mut().filter(|_| tree) {
+            dependency.read(notes_dir, self.todo_cmp)?;
+        }
+        todo.read_history(notes_dir)?;
+        self.todos.push(todo);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn malformed_lines(&self) -> &[MalformedLine] {
+        &self.malformed_lines
+    }
+
+    /// Malformed lines of this list and all its dependency lists, with the file they're in
+    pub(super) fn collect_malformed_lines<'a>(
+        &'a self,
+        path: &Path,
+        notes_dir: &Path,
+        output: &mut Vec<(PathBuf, &'a MalformedLine)>,
+    ) {
+        output.extend(self.malformed_lines.iter().map(|line| (path.to_path_buf(), line)));
+        for dependency in self.todos.iter().flat_map(|todo| todo.dependency.as_ref()) {
+            if let Some(todo_list) = dependency.todo_list() {
+                todo_list.collect_malformed_lines(&notes_dir.join(dependency.name()), notes_dir, output);
+            }
+        }
+    }
+
+    pub(super) fn read_histories(&mut self, folder_name: &Path) -> io::Result<()> {
+        for todo in &mut self.todos {
+            todo.read_history(folder_name)?;
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list.read_histories(folder_name)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn stage_histories(
+        &self,
+        folder_name: &Path,
+        transaction: &mut Transaction,
+    ) -> io::Result<()> {
+        for todo in &self.todos {
+            todo.stage_history(folder_name, transaction)?;
+            if let Some(dependency) = todo.dependency.as_ref() {
+                dependency.todo_list.stage_histories(folder_name, transaction)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn mark_histories_written(&mut self) {
+        for todo in &mut self.todos {
+            todo.mark_history_written();
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list.mark_histories_written();
+            }
         }
     }
 
@@ -167,48 +396,115 @@ impl TodoList {
 
     #[inline]
     fn write_to_buf<W: Write>(&self, writer: &mut BufWriter<W>) -> io::Result<()> {
+        // Malformed lines go back to their line numbers, or after the todos if there are fewer
+        let mut malformed_lines = self.malformed_lines.iter().peekable();
+        let mut number = 1;
         for todo_string in self.todos.iter().map(String::from) {
+            while let Some(line) = malformed_lines.next_if(|line| line.number <= number) {
+                writeln!(writer, "{}", line.content)?;
+                number += 1;
+            }
             writeln!(writer, "{todo_string}")?;
+            number += 1;
+        }
+        for line in malformed_lines {
+            writeln!(writer, "{}", line.content)?;
         }
         writer.flush()?;
         Ok(())
     }
 
     #[inline]
-    pub(super) fn write_dependencies(&mut self, filename: &Path) -> io::Result<()> {
-        for todo in &mut self.todos {
-            if let Some(dependency) = todo.dependency.as_mut() {
-                dependency.todo_list.write_dependencies(filename)?;
-                dependency.write(filename)?;
+    pub(super) fn stage_dependencies(
+        &self,
+        filename: &Path,
+        transaction: &mut Transaction,
+    ) -> io::Result<()> {
+        for todo in &self.todos {
+            if let Some(dependency) = todo.dependency.as_ref() {
+                dependency.todo_list.stage_dependencies(filename, transaction)?;
+                dependency.stage(filename, transaction)?;
             }
         }
         Ok(())
     }
 
     #[inline]
-    pub(super) fn force_write_dependencies(&self, filename: &Path) -> io::Result<()> {
+    pub(super) fn force_stage_dependencies(
+        &self,
+        filename: &Path,
+        transaction: &mut Transaction,
+    ) -> io::Result<()> {
         for todo in &self.todos {
             if let Some(dependency) = todo.dependency.as_ref() {
-                dependency.todo_list.force_write_dependencies(filename)?;
-                dependency.force_write(filename)?;
+                dependency.todo_list.force_stage_dependencies(filename, transaction)?;
+                dependency.force_stage(filename, transaction)?;
             }
         }
         Ok(())
     }
 
+    /// Clears the changed flags of this list and its dependencies after they're committed, and
+    /// takes new fingerprints of the written files
+    pub(super) fn mark_written(&mut self, filename: &Path, notes_dir: &Path) {
+        if self.changed || self.unsaved_ids {
+            self.fingerprint = Fingerprint::read(filename).ok().map(|(fingerprint, _)| fingerprint);
+            self.base = self.lines_by_id();
+            self.ids_of_lines.clear();
+            self.changed = false;
+            self.unsaved_ids = false;
+        }
+        for todo in &mut self.todos {
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.mark_written(notes_dir);
+            }
+        }
+    }
+
     #[inline]
-    pub fn force_write(&self, filename: &Path) -> io::Result<()> {
-        let file = File::create(filename)?;
-        let mut writer = BufWriter::new(file);
-        self.write_to_buf(&mut writer)?;
+    pub fn write_dependencies(&mut self, filename: &Path) -> io::Result<()> {
+        let mut transaction = Transaction::new();
+        self.stage_dependencies(filename, &mut transaction)?;
+        transaction.commit()?;
+        for todo in &mut self.todos {
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.mark_written(filename);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(super) fn stage(&self, filename: &Path, transaction: &mut Transaction) -> io::Result<()> {
+        if self.changed || self.unsaved_ids {
+            self.force_stage(filename, transaction)?;
+        }
         Ok(())
     }
 
+    #[inline]
+    pub(super) fn force_stage(
+        &self,
+        filename: &Path,
+        transaction: &mut Transaction,
+    ) -> io::Result<()> {
+        transaction.write(filename, |writer| self.write_to_buf(writer))
+    }
+
+    #[inline]
+    pub fn force_write(&self, filename: &Path) -> io::Result<()> {
+        fileio::write_atomically(filename, |writer| self.write_to_buf(writer))
+    }
+
     #[inline]
     pub fn write(&mut self, filename: &Path) -> io::Result<()> {
-        if self.changed {
+        if self.changed || self.unsaved_ids {
             self.force_write(filename)?;
+            self.fingerprint = Fingerprint::read(filename).ok().map(|(fingerprint, _)| fingerprint);
+            self.base = self.lines_by_id();
+            self.ids_of_lines.clear();
             self.changed = false;
+            self.unsaved_ids = false;
         }
         Ok(())
     }
@@ -255,13 +551,23 @@ impl TodoList {
         min: usize,
         max: usize,
     ) -> Vec<String> {
+        self.restricted_slice(restriction, min, max)
+            .map(|todo| todo.display_with_args(args))
+            .collect()
+    }
+
+    /// The todos `display_slice` displays
+    pub fn restricted_slice<'a>(
+        &'a self,
+        restriction: &'a Restriction,
+        min: usize,
+        max: usize,
+    ) -> impl Iterator<Item = &'a Todo> + 'a {
         self.todos
             .iter()
-            .filter(|todo| restriction(todo))
+            .filter(move |todo| restriction(todo))
             .skip(min)
             .take(max)
-            .map(|todo| todo.display_with_args(args))
-            .collect()
     }
 
     pub fn len(&self, restriction: &Restriction) -> usize {
@@ -273,11 +579,93 @@ impl TodoList {
     }
 
     pub fn true_position_in_list(&self, index: usize, restriction: &Restriction) -> usize {
-        let binding = self.filter(restriction).nth(index).unwrap();
         self.todos
             .iter()
-            .position(|x| x == binding)
-            .unwrap_or_default()
+            .enumerate()
+            .filter(|(_, todo)| restriction(todo))
+            .nth(index)
+            .map_or(0, |(i, _)| i)
+    }
+
+    #[inline]
+    pub fn position_of_id(&self, id: &str) -> Option<usize> {
+        self.todos.iter().position(|todo| todo.id() == id)
+    }
+
+    #[inline]
+    pub fn restricted_position_of_id(&self, id: &str, restriction: &Restriction) -> Option<usize> {
+        self.filter(restriction).position(|todo| todo.id() == id)
+    }
+
+    /// Path of true indices from this list to the todo with the given id, the last index being
+    /// the position of the todo in its own list
+    pub fn path_of_id(&self, id: &str) -> Option<Vec<usize>> {
+        let mut stack: Vec<(&TodoList, Vec<usize>)> = vec![(self, vec![])];
+        while let Some((list, path)) = stack.pop() {
+            for (i, todo) in list.todos.iter().enumerate() {
+                let mut todo_path = path.clone();
+                todo_path.push(i);
+                if todo.id() == id {
+                    return Some(todo_path);
+                }
+                if let Some(todo_list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
+                    stack.push((todo_list, todo_path));
+                }
+            }
+        }
+        None
+    }
+
+    /// Marks the list changed if it has ids that aren't in its file yet, so that they're
+    /// written and stay the same the next time it's read
+    #[inline]
+    pub fn keep_ids(&mut self) {
+        self.changed |= self.unsaved_ids;
+    }
+
+    /// Makes the list at the path and the files of its todos get written again, like
+    /// `Dependency::touch`
+    pub(crate) fn touch(&mut self, filename: &Path, notes_dir: &Path) {
+        self.changed = true;
+        if !filename.exists() {
+            self.fingerprint = None;
+        }
+        for todo in &mut self.todos {
+            todo.touch_files(notes_dir);
+        }
+    }
+
+    /// Gives the todos new ids and files, like `Todo::renew`. The list is written to the new
+    /// file of its dependency
+    pub(super) fn renew(&mut self) {
+        self.changed = true;
+        self.fingerprint = None;
+        self.base.clear();
+        for todo in &mut self.todos {
+            todo.renew();
+        }
+    }
+
+    /// Gives a fresh id to todos of the tree that have no id or share it with another todo. The
+    /// lists aren't marked changed, the ids are written with their next change
+    pub(super) fn ensure_unique_ids(&mut self, ids: &mut HashSet<String>) {
+        for todo in &mut self.todos {
+            if !todo.has_id() {
+                let line = String::from(&*todo);
+                todo.renew_id();
+                self.unsaved_ids = true;
+                // Their line is the base of these todos, as if it had the id
+                self.base.insert(todo.id().to_string(), String::from(&*todo));
+                self.ids_of_lines.insert(line, todo.id().to_string());
+            } else if ids.contains(todo.id()) {
+                todo.renew_id();
+                self.unsaved_ids = true;
+            }
+            ids.insert(todo.id().to_string());
+            if let Some(dependency) = todo.dependency.as_mut() {
+                dependency.todo_list.ensure_unique_ids(ids);
+            }
+        }
     }
 
     pub fn remove(&mut self, index: usize, restriction: &Restriction) -> Todo {
@@ -417,8 +805,8 @@ mod tests {
             Todo::new("this one is 2 and done".to_string(), 2),
             Todo::new("this one is 0 and done".to_string(), 0),
         ];
-        for i in 0..expected_done.len() {
-            expected_done[i].toggle_done();
+        for todo in expected_done.iter_mut() {
+            todo.toggle_done();
         }
         assert_eq!(
             expected_done,
@@ -454,33 +842,63 @@ mod tests {
 [-0] this one is 0 and done
 ";
 
-        remove_dir_all(&path.parent().unwrap()).expect("Remove test failed");
+        remove_dir_all(path.parent().unwrap()).expect("Remove test failed");
         let _ = remove_file(path);
         assert_eq!(contents, expected)
     }
 
+    #[test]
+    fn test_malformed_lines() -> io::Result<()> {
+        let dir = PathBuf::from("todo-list-test-malformed");
+        create_dir_all(&dir)?;
+        let path = dir.join("tmplist");
+        fs::write(&path, "[1] fine [id:aaaa]\nnot a todo\n\n[1]broken\n")?;
+        let mut todo_list = TodoList::read(&path);
+        let numbers: Vec<usize> = todo_list.malformed_lines().iter().map(|line| line.number).collect();
+        todo_list.push(Todo::new("new".to_string(), 2));
+        todo_list.write(&path)?;
+        let contents = fs::read_to_string(&path)?;
+        remove_dir_all(dir)?;
+
+        assert_eq!(numbers, vec![2, 4]);
+        assert!(contents.starts_with("[1] fine [id:aaaa]\nnot a todo\n[2] new [id:"));
+        assert!(contents.ends_with("]\n[1]broken\n"));
+        assert_eq!(contents.lines().count(), 4);
+        Ok(())
+    }
+
     #[test]
     fn test_push() {
         let mut todo_list = get_todo_list();
         let path = PathBuf::from("todo-list-test-push/tmplist");
-        let _ = create_dir_all(&path.parent().unwrap());
+        let _ = create_dir_all(path.parent().unwrap());
         todo_list.push(Todo::new("Show me your warface".to_string(), 0));
         todo_list.reorder_last();
         let _ = todo_list.write(&path);
 
         let contents = fs::read_to_string(&path).expect("Reading file failed :(");
-        let expected = "[1] this todo has prio 1
+        let expected = format!("[1] this todo has prio 1
 [2] this one has prio 2
-[0] Show me your warface
+[0] Show me your warface [id:{}]
 [-2] this one is 2 and done
 [-0] this one is 0 and done
-";
+", todo_list.todos[2].id());
 
-        remove_dir_all(&path.parent().unwrap()).expect("Remove test failed");
+        remove_dir_all(path.parent().unwrap()).expect("Remove test failed");
         let _ = remove_file(path);
         assert_eq!(contents, expected);
     }
 
+    #[test]
+    fn test_true_position_identical_todos() {
+        let mut todo_list = TodoList::new();
+        todo_list.push(Todo::new("same".to_string(), 1));
+        todo_list.push(Todo::new("same".to_string(), 1));
+        let id = todo_list.todos[1].id().to_string();
+        let restriction: Restriction = std::rc::Rc::new(move |todo| todo.id() == id);
+        assert_eq!(todo_list.true_position_in_list(0, &restriction), 1);
+    }
+
     #[test]
     fn test_initially_sorted() {
         let todo_list = get_todo_list();
@@ -494,7 +912,7 @@ mod tests {
     #[test]
     fn test_write_dependencies() -> io::Result<()> {
         let mut todo_list = get_todo_list();
-        let _ = todo_list.todos[0].add_todo_dependency();
+        todo_list.todos[0].add_todo_dependency();
 
         let path = PathBuf::from("test-write-dependency/tmplist");
         let dependency_path = fileio::append_notes_to_path_parent(&path);
@@ -520,7 +938,7 @@ mod tests {
 
         todo_list.todos[0].remove_dependency();
         todo_list.write(&path)?;
-        remove_dir_all(&path.parent().unwrap())?;
+        remove_dir_all(path.parent().unwrap())?;
         Ok(())
     }
 }
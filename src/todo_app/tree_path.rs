@@ -0,0 +1,246 @@
+use std::str::FromStr;
+use std::{error, fmt};
+
+use super::{App, TodoList};
+
+const SEPARATOR: char = '/';
+
+/// A step of a tree path, naming a todo in a list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Index of the todo in the list, counting done todos too
+    Index(usize),
+    /// Start of the message of the todo, case insensitive
+    Prefix(String),
+}
+
+/// A textual name for a todo: `/` separated steps from the root list, each one either the
+/// index of a todo in its list (`2/0/3`) or the start of its message (`Work/Report`). `\/` is a
+/// `/` inside a message. The empty path (or just `/`) names the root list.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TreePath(pub Vec<Step>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TreePathError {
+    /// No todo matches the step
+    NotFound(String),
+    /// More than one todo starts with the prefix, and none of them is an exact match
+    Ambiguous(String),
+    /// The todo of the step has a note instead of a list, so nothing can be under it
+    NotAList(String),
+}
+
+impl fmt::Display for TreePathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(step) => write!(f, "no todo matches \"{step}\""),
+            Self::Ambiguous(step) => write!(f, "more than one todo starts with \"{step}\""),
+            Self::NotAList(step) => write!(f, "\"{step}\" doesn't have sub todos"),
+        }
+    }
+}
+
+impl error::Error for TreePathError {}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Prefix(prefix) => write!(f, "{}", prefix.replace(SEPARATOR, "\\/")),
+        }
+    }
+}
+
+impl fmt::Display for TreePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<String> = self.0.iter().map(Step::to_string).collect();
+        write!(f, "{}", steps.join("/"))
+    }
+}
+
+impl FromStr for TreePath {
+    type Err = TreePathError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut steps = vec![];
+        let mut step = String::new();
+        let mut chars = input.chars().peekable();
+        let mut push = |step: &mut String| {
+            let text = std::mem::take(step);
+            let text = text.trim();
+            if !text.is_empty() {
+                steps.push(match text.parse() {
+                    Ok(index) => Step::Index(index),
+                    Err(_) => Step::Prefix(text.to_string()),
+                });
+            }
+        };
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&SEPARATOR) => {
+                    step.push(SEPARATOR);
+                    chars.next();
+                }
+                SEPARATOR => push(&mut step),
+                c => step.push(c),
+            }
+        }
+        push(&mut step);
+        Ok(Self(steps))
+    }
+}
+
+impl From<&[usize]> for TreePath {
+    fn from(indices: &[usize]) -> Self {
+        Self(indices.iter().map(|&index| Step::Index(index)).collect())
+    }
+}
+
+impl TreePath {
+    /// The indices of the path, None if any step of it is a message prefix
+    pub fn indices(&self) -> Option<Vec<usize>> {
+        self.0
+            .iter()
+            .map(|step| match step {
+                Step::Index(index) => Some(*index),
+                Step::Prefix(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl Step {
+    /// Index of the todo the step names in the list
+    fn position_in(&self, list: &TodoList) -> Result<usize, TreePathError> {
+        let not_found = || TreePathError::NotFound(self.to_string());
+        match self {
+            Self::Index(index) if *index < list.todos.len() => Ok(*index),
+            Self::Index(_) => Err(not_found()),
+            Self::Prefix(prefix) => {
+                let prefix = prefix.to_lowercase();
+                let matches: Vec<usize> = list
+                    .todos
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, todo)| todo.message.to_lowercase().starts_with(&prefix))
+                    .map(|(index, _)| index)
+                    .collect();
+                match matches[..] {
+                    [] => Err(not_found()),
+                    [index] => Ok(index),
+                    _ => matches
+                        .into_iter()
+                        .find(|&index| list.todos[index].message.to_lowercase() == prefix)
+                        .ok_or_else(|| TreePathError::Ambiguous(self.to_string())),
+                }
+            }
+        }
+    }
+}
+
+impl App {
+    /// Resolves a tree path to the true indices of the todos in their lists, like the ones
+    /// `tree_path` holds. The root list resolves to an empty path.
+    pub fn resolve_tree_path(&self, path: &TreePath) -> Result<Vec<usize>, TreePathError> {
+        let mut list = Some(&self.todo_list);
+        let mut indices = vec![];
+        let mut last_step: Option<&Step> = None;
+        for step in &path.0 {
+            let Some(current) = list else {
+                let parent = last_step.map(Step::to_string).unwrap_or_default();
+                return Err(TreePathError::NotAList(parent));
+            };
+            let index = step.position_in(current)?;
+            indices.push(index);
+            list = current.todos[index]
+                .dependency
+                .as_ref()
+                .and_then(|dep| dep.todo_list());
+            last_step = Some(step);
+        }
+        Ok(indices)
+    }
+
+    /// Tree path of a todo given by its id, or by a `TreePath` like `2/0/3` or `Work/Report`
+    pub fn path_of_target(&self, target: &str) -> Result<Vec<usize>, TreePathError> {
+        if let Some(path) = self.todo_list.path_of_id(target) {
+            return Ok(path);
+        }
+        let path = self.resolve_tree_path(&target.parse()?)?;
+        if path.is_empty() {
+            return Err(TreePathError::NotFound(target.to_string()));
+        }
+        Ok(path)
+    }
+
+    /// Tree path of a list: the root list for an empty path or `/`, otherwise the sub list of
+    /// the todo `path_of_target` gives
+    pub fn path_of_list(&self, target: &str) -> Result<Vec<usize>, TreePathError> {
+        if target.trim().trim_matches(SEPARATOR).is_empty() {
+            Ok(vec![])
+        } else {
+            self.path_of_target(target)
+        }
+    }
+
+    /// The list at the path `path_of_list` gives, None if the todo has no sub list
+    pub fn list_of_path(&self, path: &[usize]) -> Option<&TodoList> {
+        match self.todo_at_path(path) {
+            None if path.is_empty() => Some(&self.todo_list),
+            None => None,
+            Some(todo) => todo.dependency.as_ref().and_then(|dep| dep.todo_list()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::test_helpers::*;
+    use std::fs::remove_dir_all;
+    use std::io;
+
+    #[test]
+    fn test_parse() {
+        let path: TreePath = "2/Work\\/Home/ 3 /".parse().unwrap();
+        assert_eq!(
+            path.0,
+            vec![
+                Step::Index(2),
+                Step::Prefix(String::from("Work/Home")),
+                Step::Index(3)
+            ]
+        );
+        assert_eq!(path.to_string(), "2/Work\\/Home/3");
+        assert_eq!("/".parse::<TreePath>().unwrap(), TreePath::default());
+        assert_eq!(TreePath::from(&[1, 0][..]).to_string(), "1/0");
+        assert_eq!("1/0".parse::<TreePath>().unwrap().indices(), Some(vec![1, 0]));
+        assert_eq!(path.indices(), None);
+    }
+
+    #[test]
+    fn test_resolve() -> io::Result<()> {
+        let dir = dir("test-tree-path-resolve")?;
+        let app = write_test_todos(&dir)?;
+        let parent = app
+            .todo_list
+            .todos
+            .iter()
+            .position(|todo| todo.message == "Hello there")
+            .unwrap();
+        assert_eq!(app.path_of_target("hello there/is there any"), Ok(vec![parent, 0]));
+        assert_eq!(app.path_of_target(&format!("{parent}/0/Just")), Ok(vec![parent, 0, 0]));
+        // "Hello" is the whole message of a todo, "Hello there" only starts with it
+        assert!(app.path_of_target("Hello").is_ok());
+        assert_eq!(app.path_of_target("Hel"), Err(TreePathError::Ambiguous(String::from("Hel"))));
+        assert_eq!(app.path_of_target("Nope"), Err(TreePathError::NotFound(String::from("Nope"))));
+        assert_eq!(
+            app.path_of_target("Goodbye/0"),
+            Err(TreePathError::NotAList(String::from("Goodbye")))
+        );
+        assert_eq!(app.path_of_list("/"), Ok(vec![]));
+        assert!(app.list_of_path(&[parent]).is_some());
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+}
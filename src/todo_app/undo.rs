@@ -0,0 +1,345 @@
+use std::collections::VecDeque;
+
+use super::{App, Todo, TodoList};
+
+const UNDO_LIMIT: usize = 1000;
+
+/// A todo of a list before a change, with its position in the list. `before` is None if the
+/// todo didn't exist. Applying an edit gives the edit that reverts it.
+#[derive(Debug, Clone)]
+struct Edit {
+    parent: Option<String>,
+    id: String,
+    before: Option<(usize, Todo)>,
+}
+
+/// Edits made by a single operation, with the cursor from before it
+#[derive(Debug, Clone)]
+struct Step {
+    edits: Vec<Edit>,
+    index: usize,
+    tree_path: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+}
+
+impl UndoHistory {
+    #[inline]
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+impl App {
+    #[inline(always)]
+    fn cursor_step(&self) -> Step {
+        Step {
+            edits: vec![],
+            index: self.index,
+            tree_path: self.tree_path.clone(),
+        }
+    }
+
+    /// Starts a new undo step, that puts the cursor back to where it is now when undone
+    pub(super) fn begin_undo_step(&mut self) {
+        let step = self.cursor_step();
+        let history = &mut self.undo_history;
+        history.redo.clear();
+        if history.undo.len() == UNDO_LIMIT {
+            history.undo.pop_front();
+        }
+        history.undo.push_back(step);
+    }
+
+    #[inline(always)]
+    pub(super) fn current_parent_id(&self) -> Option<String> {
+        let (&index, path) = self.tree_path.split_last()?;
+        Self::list_at_path(&self.todo_list, path)
+            .todos
+            .get(index)
+            .map(|todo| todo.id().to_string())
+    }
+
+    #[inline(always)]
+    fn record(&mut self, id: String, before: Option<(usize, Todo)>) {
+        let parent = self.current_parent_id();
+        if let Some(step) = self.undo_history.undo.back_mut() {
+            step.edits.push(Edit { parent, id, before });
+        }
+    }
+
+    /// Records the todo under the cursor in the current undo step, before it gets changed
+    pub(super) fn record_current(&mut self) {
+        let restriction = self.restriction.clone();
+        let list = self.current_list();
+        if list.is_empty(&restriction) {
+            return;
+        }
+        let true_index = list.true_position_in_list(self.index, &restriction);
+        if let Some(todo) = list.todos.get(true_index).cloned() {
+            self.record(todo.id().to_string(), Some((true_index, todo)));
+        }
+    }
+
+    /// Records that the todo with the id was added to the current list
+    #[inline]
+    pub(super) fn record_added(&mut self, id: String) {
+        self.record(id, None);
+    }
+
+    /// Rewrites the current list through `rewrite` in a single undo step. The old todos are
+    /// recorded as if they were removed one by one, and the new ones as added.
+    pub(super) fn rewrite_current_list(&mut self, rewrite: impl FnOnce(&mut Self)) {
+        let before = self.current_list().todos.clone();
+        self.begin_undo_step();
+        rewrite(self);
+        if self.current_list().todos == before {
+            self.undo_history.undo.pop_back();
+            return;
+        }
+        for todo in before {
+            self.record(todo.id().to_string(), Some((0, todo)));
+        }
+        let ids: Vec<String> = self
+            .current_list()
+            .iter()
+            .map(|todo| todo.id().to_string())
+            .collect();
+        for id in ids {
+            self.record_added(id);
+        }
+    }
+
+    /// Starts an undo step with the todo under the cursor in it. Used before changing the todo
+    /// through `todo_mut`.
+    #[inline]
+    pub fn save_undo_point(&mut self) {
+        if self.todo().is_some() {
+            self.begin_undo_step();
+            self.record_current();
+        }
+    }
+
+    fn edit_list(&mut self, parent: Option<&str>) -> Option<&mut TodoList> {
+        match parent {
+            None => Some(&mut self.todo_list),
+            Some(parent) => self
+                .todo_by_id_mut(parent)?
+                .dependency
+                .as_mut()
+                .map(|dependency| &mut dependency.todo_list),
+        }
+    }
+
+    fn is_noop(&mut self, edit: &Edit) -> bool {
+        let Some(list) = self.edit_list(edit.parent.as_deref()) else {
+            return true;
+        };
+        let current = list
+            .position_of_id(&edit.id)
+            .map(|position| (position, &list.todos[position]));
+        current == edit.before.as_ref().map(|(position, todo)| (*position, todo))
+    }
+
+    fn apply_edit(&mut self, edit: Edit) -> Edit {
+        let Edit { parent, id, before } = edit;
+        let notes_dir = self.notes_dir.clone();
+        let Some(list) = self.edit_list(parent.as_deref()) else {
+            return Edit { parent, id, before };
+        };
+        let after = list
+            .position_of_id(&id)
+            .map(|position| (position, list.todos.remove(position)));
+        let is_restored = before.is_some();
+        if let Some((position, mut todo)) = before {
+            // Its files might have been removed by a write since
+            todo.touch_files(&notes_dir);
+            list.todos.insert(position.min(list.todos.len()), todo);
+        }
+        list.changed = true;
+        if is_restored {
+            self.removed_todos.retain(|todo| todo.id() != id);
+        } else if let Some((_, todo)) = after.as_ref() {
+            self.removed_todos.push(todo.clone());
+        }
+        Edit {
+            parent,
+            id,
+            before: after,
+        }
+    }
+
+    /// Applies the edits of the step in reverse, returning the step that reverts it
+    fn apply_step(&mut self, step: Step) -> Step {
+        let mut inverse = self.cursor_step();
+        inverse.edits = step
+            .edits
+            .into_iter()
+            .rev()
+            .map(|edit| self.apply_edit(edit))
+            .collect();
+        self.tree_path = step.tree_path;
+        self.index = step.index;
+        let len = self.max_tree_length();
+        self.tree_path.truncate(len);
+        self.fix_index();
+        inverse
+    }
+
+    /// Reverts the last change made through the app. Returns false if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        while let Some(step) = self.undo_history.undo.pop_back() {
+            let edits = step.edits.clone();
+            if edits.iter().all(|edit| self.is_noop(edit)) {
+                continue;
+            }
+            let redo = self.apply_step(step);
+            self.undo_history.redo.push(redo);
+            return true;
+        }
+        false
+    }
+
+    /// Makes the last undone change again. Returns false if there's nothing to redo
+    pub fn redo(&mut self) -> bool {
+        if let Some(step) = self.undo_history.redo.pop() {
+            let undo = self.apply_step(step);
+            self.undo_history.undo.push_back(undo);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    pub fn clear_undo_history(&mut self) {
+        self.undo_history.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo_app::test_helpers::*;
+    use std::collections::HashSet;
+    use std::fs::{self, remove_dir_all};
+    use std::io;
+
+    fn messages(app: &App) -> Vec<String> {
+        app.current_list()
+            .iter()
+            .map(|todo| todo.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_undo_redo() -> io::Result<()> {
+        let dir = dir("test-undo-redo")?;
+        let mut app = write_test_todos(&dir)?;
+        app.clear_undo_history();
+        let initial = messages(&app);
+        app.append(String::from("New"));
+        let appended = messages(&app);
+        app.index = 0;
+        app.set_current_priority(5);
+        app.index = 1;
+        app.remove_todo();
+        let removed = app.removed_todos.len();
+
+        assert!(app.undo());
+        assert!(app.removed_todos.len() < removed);
+        assert_eq!(app.index, 1);
+        assert!(app.undo());
+        assert_eq!(messages(&app), appended);
+        assert_eq!(app.index, 0);
+        assert!(app.undo());
+        assert_eq!(messages(&app), initial);
+        assert!(!app.undo());
+        assert!(app.redo());
+        assert_eq!(messages(&app), appended);
+        app.go_down();
+        app.toggle_current_done();
+        assert!(app.undo_history.redo.is_empty());
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_tree() -> io::Result<()> {
+        let dir = dir("test-undo-tree")?;
+        let mut app = write_test_todos(&dir)?;
+        app.clear_undo_history();
+        app.add_dependency_traverse_down();
+        app.append(String::from("Nested"));
+        let tree_path = app.tree_path.clone();
+        app.traverse_up();
+        app.remove_current_dependent();
+
+        assert!(app.undo());
+        assert!(app.todo().unwrap().dependency.is_some());
+        assert!(app.undo());
+        assert_eq!(app.tree_path, tree_path);
+        assert!(!messages(&app).contains(&String::from("Nested")));
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_removal_after_write() -> io::Result<()> {
+        let dir = dir("test-undo-removal-after-write")?;
+        let mut app = write_test_todos(&dir)?;
+        let sub_list = |app: &App| -> Option<Vec<String>> {
+            let todo = app.todo_list.todos.iter().find(|todo| todo.message == "Hello there")?;
+            let list = todo.dependency.as_ref()?.todo_list()?;
+            Some(list.iter().map(|todo| todo.message.clone()).collect())
+        };
+        let before = sub_list(&app);
+        app.index = messages(&app).iter().position(|message| message == "Hello there").unwrap();
+        app.remove_todo();
+        app.write()?;
+        assert!(app.undo());
+        app.write()?;
+        app.read();
+        let note_count = fs::read_dir(dir.join("notes"))?.count();
+        remove_dir_all(dir)?;
+
+        assert!(before.as_ref().is_some_and(|list| !list.is_empty()));
+        assert_eq!(sub_list(&app), before);
+        // Two sub lists, a note and the histories aren't there without a schedule
+        assert_eq!(note_count, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_list_rewrite() -> io::Result<()> {
+        let dir = dir("test-undo-list-rewrite")?;
+        let mut app = write_test_todos(&dir)?;
+        app.clear_undo_history();
+        let initial = messages(&app);
+        app.rewrite_current_list(|app| {
+            app.batch_edit_current_list("1 3 Changed\n2 New".lines())
+        });
+        let rewritten = messages(&app);
+        let mut appended = TodoList::default();
+        appended.push(app.current_list().todos[0].clone());
+        app.append_list(appended);
+        let ids: Vec<&str> = app.current_list().iter().map(|todo| todo.id()).collect();
+        let unique_ids = ids.iter().collect::<HashSet<_>>().len();
+
+        assert_ne!(rewritten, initial);
+        assert_eq!(unique_ids, ids.len());
+        assert!(app.undo());
+        assert_eq!(messages(&app), rewritten);
+        assert!(app.undo());
+        assert_eq!(messages(&app), initial);
+        assert!(app.redo());
+        assert_eq!(messages(&app), rewritten);
+        remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -18,30 +18,39 @@ use nix::{
 };
 use ratatui::{prelude::*, widgets::*};
 use std::io::Write;
-use std::ops::Not;
 use std::{
     io::{self, BufRead, BufReader},
     path::PathBuf,
     process::{Command, Stdio},
     rc::Rc,
     str,
+    time::{Duration, Instant},
 };
 use tui_textarea::{CursorMove, Input, TextArea};
 mod help;
+pub mod keymap;
+mod outline;
 mod potato;
+pub mod theme;
 mod todo_buffer;
 use todo_buffer::TodoBuffer;
 mod tree_search;
 use c3::{
     date,
-    todo_app::{fzf_search::fzf_search, App, Restriction, Schedule, Todo},
+    fileio::ExternalChanges,
+    todo_app::{filter, fzf_search::fzf_search, restriction, App, Restriction, Todo, TodoList},
+    TodoDisplay,
 };
 pub use tree_search::TreeSearch;
-
 use help::HelpPage;
+use keymap::{Action, KeyPress, Keymap, Lookup};
+use outline::{Fold, Outline, Row};
 use potato::Potato;
+use theme::{Theme, ThemeName};
 // }}}
 
+const EXTERNAL_CHANGES_CHECK_MS: u64 = 2000;
+
 #[derive(Debug)]
 pub enum HandlerOperation {
     Nothing,
@@ -69,7 +78,16 @@ pub struct TuiApp<'a> {
     last_restriction: Option<Restriction>,
     show_right: bool,
     show_help: bool,
+    outline: Outline,
+    /// Ids of the marked todos, in the order they were marked
+    marks: Vec<String>,
+    /// Id of the todo the range being marked starts from
+    visual_start: Option<String>,
     help_page: HelpPage,
+    keymap: Keymap,
+    theme: Theme,
+    /// Keys pressed so far of a binding like `gg`
+    pending_keys: Vec<KeyPress>,
     mode: Mode,
     on_submit: Option<fn(&mut Self, String) -> ()>,
     on_delete: Option<fn(&mut Self, String, String) -> ()>,
@@ -78,6 +96,9 @@ pub struct TuiApp<'a> {
     potato_module: Potato,
     textarea: TextArea<'a>,
     todo_app: &'a mut App,
+    last_external_changes_check: Instant,
+    /// Error of what a prompt did, returned once the prompt is closed like the errors of keys
+    prompt_error: Option<io::Error>,
 }
 
 #[derive(Parser, Debug)]
@@ -95,17 +116,31 @@ pub struct TuiArgs {
     #[arg(short = 'm', long)]
     enable_module: bool,
 
+    /// Start in the outline, with the whole tree shown inline
+    #[arg(long)]
+    outline: bool,
+
     /// Don't use glow for notes
     #[arg(short = 'G', long)]
     no_glow: bool,
+
+    /// Command that renders notes given to its stdin, with its arguments
+    #[arg(long, value_name = "COMMAND", default_value_t=String::from("glow"))]
+    note_viewer: String,
+
+    /// Colours of the todos, changed by the [theme] section of the config
+    #[arg(long, value_enum, default_value_t)]
+    pub theme: ThemeName,
 }
 
 impl<'a> TuiApp<'a> {
     #[inline]
-    pub fn new(app: &'a mut App, args: TuiArgs) -> Self {
+    pub fn new(app: &'a mut App, args: TuiArgs, keymap: Keymap, theme: Theme) -> Self {
         let mut textarea = TextArea::default();
         textarea.set_cursor_line_style(Style::default());
-        let app_help_page = TuiApp::get_default_help_page();
+        let app_help_page = TuiApp::help_page(&keymap);
+        let mut outline = Outline::default();
+        outline.enabled = args.outline;
         TuiApp {
             tree_search: Default::default(),
             todo_buffer: Default::default(),
@@ -117,41 +152,30 @@ impl<'a> TuiApp<'a> {
             on_input: None,
             on_delete: None,
             show_right: true,
+            outline,
+            marks: vec![],
+            visual_start: None,
             help_page: app_help_page,
+            keymap,
+            theme,
+            pending_keys: vec![],
             show_help: false,
             mode: Default::default(),
             last_restriction: None,
+            last_external_changes_check: Instant::now(),
+            prompt_error: None,
         }
     }
 
-    fn get_default_help_page() -> HelpPage {
+    /// The help of the keys of the keymap
+    fn help_page(keymap: &Keymap) -> HelpPage {
         let mut help_page = HelpPage::default();
-
-        help_page.add_entry("q", "Quit the application");
-        help_page.add_entry("j", "Move down");
-        help_page.add_entry("k", "Move up");
-        help_page.add_entry("h", "Move left");
-        help_page.add_entry("l", "Move right");
-        help_page.add_entry("i", "Increase day by 1");
-        help_page.add_entry("I", "Decrease day by 1");
-        help_page.add_entry("o", "Append todo");
-        help_page.add_entry("O", "Output todo");
-        help_page.add_entry("a", "Prepend todo");
-        help_page.add_entry("A", "Append todo at first");
-        help_page.add_entry("e", "Edit todo");
-        help_page.add_entry("E", "Edit todo (start)");
-        help_page.add_entry("r", "Batch edit messages");
-        help_page.add_entry("s", "Skip potato module");
-        help_page.add_entry("H", "Increase potato timer");
-        help_page.add_entry("c", "Toggle potato pause");
-        help_page.add_entry("C", "Quit potato module");
-        help_page.add_entry("L", "Decrease potato timer");
-        help_page.add_entry("f", "Restart potato module");
-        help_page.add_entry("F", "FZF search");
-        help_page.add_entry("+", "Increase pomodoro");
-        help_page.add_entry("-", "Decrease pomodoro");
-        help_page.add_entry(".", "Next potato module");
-        help_page.add_entry(",", "Previous potato module");
+        for (action, keys) in keymap.actions() {
+            if !keys.is_empty() {
+                help_page.add_entry(&keys.join(", "), action.description());
+            }
+        }
+        help_page.add_entry("0-9", "Set todo priority");
 
         help_page
     }
@@ -167,7 +191,12 @@ impl<'a> TuiApp<'a> {
             .todo_app
             .current_list()
             .len(self.todo_app.get_restriction());
-        let todo_string = format!("Todos ({size}){changed_str}");
+        let read_only_str = if self.todo_app.is_read_only() {
+            " (read only)"
+        } else {
+            ""
+        };
+        let todo_string = format!("Todos ({size}){changed_str}{read_only_str}");
 
         if let Some(parent) = self.todo_app.parent() {
             format!("{todo_string} {}", parent.message)
@@ -177,7 +206,8 @@ impl<'a> TuiApp<'a> {
     }
 
     #[inline]
-    pub fn quit(&self) -> io::Result<()> {
+    pub fn quit(&mut self) -> io::Result<()> {
+        self.todo_app.unlock();
         shutdown()?;
         std::process::exit(0);
     }
@@ -242,7 +272,7 @@ impl<'a> TuiApp<'a> {
     #[inline]
     fn on_search(&mut self, query: String) {
         self.todo_app.set_restriction_with_last(
-            Rc::new(move |todo| todo.matches(query.as_str())),
+            restriction::matches(query),
             self.last_restriction.clone(),
         )
     }
@@ -273,7 +303,7 @@ impl<'a> TuiApp<'a> {
         let current_not_matches = self
             .todo_app
             .todo()
-            .map_or(true, |todo| !todo.matches(&query));
+            .is_none_or(|todo| !todo.matches(&query));
 
         self.tree_search.search(
             query,
@@ -288,24 +318,16 @@ impl<'a> TuiApp<'a> {
 
     #[inline]
     pub fn schedule_prompt(&mut self) {
-        self.set_text_mode(Self::on_schedule, "Change schedule day", "");
+        self.set_text_mode(
+            Self::on_schedule,
+            "Change schedule day or rule",
+            "7, 2w, weekdays, mon,thu, month(1,15), month(fri,-1)",
+        );
     }
 
     #[inline]
     fn on_schedule(&mut self, str: String) {
-        let day = if str.ends_with('w') {
-            str[..str.len()-1].parse::<i64>().map(|days| days*7)
-        } else {
-            str.parse::<i64>()
-        };
-        if let Ok(day) = day {
-            if day <= 0 {
-                return;
-            }
-            if let Some(todo) = self.todo_app.todo_mut() {
-                todo.enable_day(day as i64);
-            }
-        }
+        self.todo_app.schedule_current(&str);
     }
 
     #[inline]
@@ -313,6 +335,11 @@ impl<'a> TuiApp<'a> {
         self.set_text_mode(Self::on_reminder, "Date reminder", "");
     }
 
+    #[inline]
+    pub fn due_prompt(&mut self) {
+        self.set_text_mode(Self::on_due, "Due date (empty to remove)", "");
+    }
+
     fn nnn_paths() -> Option<impl Iterator<Item = PathBuf>> {
         let mut output = Command::new("nnn")
             .args(["-p", "-"])
@@ -359,10 +386,23 @@ impl<'a> TuiApp<'a> {
     #[inline]
     fn on_reminder(&mut self, str: String) {
         if let Ok(date) = date::parse_user_input(&str) {
-            if let Some(todo) = self.todo_app.todo_mut() {
-                todo.schedule = Some(Schedule::new_reminder(date));
-                self.todo_app.reorder_current();
-            }
+            self.todo_app.remind_current(date);
+        }
+    }
+
+    #[inline]
+    fn on_due(&mut self, str: String) {
+        let due = if str.is_empty() {
+            None
+        } else if let Ok(date) = date::parse_user_input(&str) {
+            Some(date)
+        } else {
+            return;
+        };
+        self.todo_app.save_undo_point();
+        if let Some(todo) = self.todo_app.todo_mut() {
+            todo.set_due(due);
+            self.todo_app.reorder_current();
         }
     }
 
@@ -402,6 +442,26 @@ impl<'a> TuiApp<'a> {
         self.on_delete = Some(Self::on_priority_delete);
     }
 
+    #[inline]
+    pub fn tag_restriction_prompt(&mut self) {
+        const TITLE: &str = "Limit tag";
+        const PLACEHOLDER: &str = "Enter +project, @context or a name to show";
+        self.last_restriction = Some(self.todo_app.get_restriction().clone());
+        self.set_text_mode(Self::on_tag_prompt, TITLE, PLACEHOLDER);
+        self.set_responsive_text_mode(Self::on_tag_prompt, TITLE, PLACEHOLDER);
+        self.on_delete = Some(Self::on_priority_delete);
+    }
+
+    #[inline]
+    pub fn filter_prompt(&mut self) {
+        const TITLE: &str = "Filter todos";
+        const PLACEHOLDER: &str = "Enter a filter like prio<=2 and not done";
+        self.last_restriction = Some(self.todo_app.get_restriction().clone());
+        self.on_submit = None;
+        self.set_responsive_text_mode(Self::on_filter_prompt, TITLE, PLACEHOLDER);
+        self.on_delete = Some(Self::on_filter_delete);
+    }
+
     #[inline]
     pub fn append_prompt(&mut self) {
         self.set_text_mode(
@@ -413,7 +473,8 @@ impl<'a> TuiApp<'a> {
 
     #[inline]
     pub fn quit_save_prompt(&mut self) {
-        if self.todo_app.current_list().changed || self.todo_app.is_changed() {
+        let is_changed = self.todo_app.current_list().changed || self.todo_app.is_changed();
+        if is_changed && !self.todo_app.is_read_only() {
             self.set_text_mode(
                 Self::on_save_prompt,
                 "You have done changes. You wanna save? [n: no, y: yes, c: cancel] (default: n)",
@@ -432,7 +493,7 @@ impl<'a> TuiApp<'a> {
         let priority = str.parse();
         if let Ok(priority) = priority {
             self.todo_app.set_restriction_with_last(
-                Rc::new(move |todo| todo.priority() == priority),
+                restriction::priority(priority),
                 self.last_restriction.clone(),
             )
         }
@@ -446,28 +507,108 @@ impl<'a> TuiApp<'a> {
         let schedule_day = str.parse();
         if let Ok(schedule_day) = schedule_day {
             self.todo_app.set_restriction_with_last(
-                Rc::new(move |todo| {
-                    todo.schedule
-                        .as_ref()
-                        .map_or(0, |sch| if sch.is_reminder() { 0 } else { sch.days() })
-                        == schedule_day
-                }),
+                restriction::schedule_day(schedule_day),
                 self.last_restriction.clone(),
             )
         }
     }
 
+    #[inline]
+    fn on_tag_prompt(&mut self, str: String) {
+        if str.is_empty() {
+            return self.todo_app.update_show_done_restriction();
+        }
+        self.todo_app.set_restriction_with_last(
+            restriction::tag(str),
+            self.last_restriction.clone(),
+        )
+    }
+
+    /// Shows the parse error in the title of the prompt until the filter is valid again
+    #[inline]
+    fn on_filter_prompt(&mut self, str: String) {
+        if str.is_empty() {
+            self.textarea.set_block(default_block("Filter todos"));
+            return self.todo_app.update_show_done_restriction();
+        }
+        match filter::parse(&str) {
+            Ok(filter) => {
+                self.textarea.set_block(default_block("Filter todos"));
+                self.todo_app.set_restriction_with_last(filter, self.last_restriction.clone())
+            }
+            Err(error) => self
+                .textarea
+                .set_block(default_block(format!("Invalid filter: {error}"))),
+        }
+    }
+
+    #[inline]
+    fn on_filter_delete(&mut self, new: String, old: String) {
+        self.on_priority_delete(new.clone(), old);
+        if !new.is_empty() {
+            self.on_filter_prompt(new)
+        }
+    }
+
     #[inline]
     fn on_save_prompt(&mut self, str: String) {
         let lower = str.to_lowercase();
         if lower.starts_with('y') {
-            let _ = self.todo_app.write();
+            if let Err(error) = self.todo_app.write() {
+                if ExternalChanges::of(&error).is_some() {
+                    return self.external_changes_prompt();
+                }
+                self.prompt_error = Some(error);
+                return;
+            }
         } else if lower.starts_with('c') {
             return;
         }
         let _ = self.quit();
     }
 
+    #[inline]
+    pub fn external_changes_prompt(&mut self) {
+        self.set_text_mode(
+            Self::on_external_changes_prompt,
+            "Todos were changed outside c3. [r: reload, o: overwrite, m: merge] (default: m)",
+            "r/o/M",
+        );
+    }
+
+    #[inline]
+    fn on_external_changes_prompt(&mut self, str: String) {
+        let lower = str.to_lowercase();
+        let result = if lower.starts_with('r') {
+            self.todo_app.read();
+            Ok(())
+        } else if lower.starts_with('o') {
+            self.todo_app.overwrite().map(|_| ())
+        } else {
+            self.todo_app.merge_external_changes()
+        };
+        self.prompt_error = result.err();
+    }
+
+    /// Reloads the tree if it was changed outside c3, or asks what to do if it was changed in
+    /// c3 too. Returns whether anything happened
+    #[inline]
+    fn check_external_changes(&mut self) -> bool {
+        if self.mode != Mode::Normal || self.todo_app.external_changes().is_empty() {
+            return false;
+        }
+        if self.todo_app.is_changed() {
+            // Changes of a read only c3 can't be saved, but aren't thrown away either
+            if self.todo_app.is_read_only() {
+                return false;
+            }
+            self.external_changes_prompt();
+        } else {
+            self.todo_app.read();
+        }
+        true
+    }
+
     #[inline]
     fn on_append_todo(&mut self, str: String) {
         self.todo_app.append(str);
@@ -481,6 +622,7 @@ impl<'a> TuiApp<'a> {
     #[inline]
     fn on_edit_todo(&mut self, str: String) {
         if !str.is_empty() {
+            self.todo_app.save_undo_point();
             if let Some(todo) = self.todo_app.todo_mut() {
                 todo.message = str;
             }
@@ -508,6 +650,9 @@ impl<'a> TuiApp<'a> {
                     on_submit(self, message);
                 }
                 self.turn_off_text_mode();
+                if let Some(error) = self.prompt_error.take() {
+                    return Err(error);
+                }
             }
             EditorOperation::Cancel => {
                 self.turn_off_text_mode();
@@ -549,17 +694,23 @@ impl<'a> TuiApp<'a> {
 
     #[inline]
     pub fn handle_key_and_return_operation(&mut self) -> io::Result<HandlerOperation> {
+        let check_interval = Duration::from_millis(EXTERNAL_CHANGES_CHECK_MS);
+        if self.last_external_changes_check.elapsed() >= check_interval {
+            self.last_external_changes_check = Instant::now();
+            self.todo_app.refresh_lock();
+            if self.check_external_changes() {
+                return Ok(HandlerOperation::Nothing);
+            }
+        }
         let input_handler = match self.mode {
             Mode::Editing => Self::handle_text_input,
             Mode::Normal => Self::handle_normal_input,
         };
+        let mut timeout = check_interval;
         if self.args.enable_module {
-            if event::poll(std::time::Duration::from_millis(
-                self.potato_module.update_time_ms(),
-            ))? {
-                return input_handler(self);
-            }
-        } else {
+            timeout = timeout.min(Duration::from_millis(self.potato_module.update_time_ms()));
+        }
+        if event::poll(timeout)? {
             return input_handler(self);
         }
         Ok(HandlerOperation::Nothing)
@@ -567,138 +718,430 @@ impl<'a> TuiApp<'a> {
 
     #[inline]
     fn write(&mut self) -> io::Result<()> {
-        self.todo_app.write()
+        match self.todo_app.write() {
+            Err(error) if ExternalChanges::of(&error).is_some() => {
+                self.external_changes_prompt();
+                Ok(())
+            }
+            // The title already says it's read only
+            Err(_) if self.todo_app.is_read_only() => Ok(()),
+            result => result.map(|_| ()),
+        }
     }
 
     #[inline]
     fn handle_normal_input(&mut self) -> io::Result<HandlerOperation> {
         let event = event::read()?;
-        if let Key(key) = event {
-            if key.kind == event::KeyEventKind::Press {
-                match key.code {
-                    #[cfg(unix)]
-                    Char('z') if key.modifiers == KeyModifiers::CONTROL => {
-                        shutdown()?;
-                        let _ = kill(getpid(), Signal::SIGTSTP);
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char('o') if key.modifiers == KeyModifiers::CONTROL => {
-                        self.nnn_open();
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char('x') => {
-                        self.todo_app.remove_todo();
-                        if let Some(todo) = self.todo_app.removed_todos.pop() {
-                            self.todo_buffer.yank(todo);
+        let Key(key) = event else {
+            return Ok(HandlerOperation::Nothing);
+        };
+        if key.kind != event::KeyEventKind::Press {
+            return Ok(HandlerOperation::Nothing);
+        }
+        if key.code == KeyCode::Esc {
+            // Esc without keys to cancel drops the marks
+            if self.pending_keys.is_empty() {
+                self.clear_selection();
+            }
+            self.pending_keys.clear();
+            return Ok(HandlerOperation::Nothing);
+        }
+        self.pending_keys.push(KeyPress::from(key));
+        let mut lookup = self.keymap.lookup(&self.pending_keys, self.args.enable_module);
+        // A key that doesn't go on with the keys before it starts over
+        if lookup == Lookup::None && self.pending_keys.len() > 1 {
+            self.pending_keys = vec![KeyPress::from(key)];
+            lookup = self.keymap.lookup(&self.pending_keys, self.args.enable_module);
+        }
+        match lookup {
+            Lookup::Pending => Ok(HandlerOperation::Nothing),
+            Lookup::Action(action) => {
+                self.pending_keys.clear();
+                self.run_action(action)
+            }
+            Lookup::None => {
+                self.pending_keys.clear();
+                if let Char(c) = key.code {
+                    if let Some(priority) = c.to_digit(10) {
+                        let selected = self.selected_ids();
+                        if selected.is_empty() {
+                            self.todo_app.set_current_priority(priority as u8);
+                        } else {
+                            self.todo_app.set_priority_by_ids(&selected, priority as u8);
+                            self.clear_selection();
                         }
                     }
-                    Char('d') => self.todo_app.toggle_current_daily(),
-                    Char('W') => self.todo_app.toggle_current_weekly(),
-                    Char('S') => self.schedule_prompt(),
-                    Char('m') => self.reminder_prompt(),
-                    Char('M') => {
-                        if let Some(todo) = self.todo_app.todo_mut() {
-                            todo.toggle_schedule();
+                }
+                Ok(HandlerOperation::Nothing)
+            }
+        }
+    }
+
+    fn run_action(&mut self, action: Action) -> io::Result<HandlerOperation> {
+        if self.outline.enabled && self.move_in_outline(action) {
+            return Ok(HandlerOperation::Nothing);
+        }
+        let selected = self.selected_ids();
+        if !selected.is_empty() && self.run_bulk_action(action, &selected) {
+            return Ok(HandlerOperation::Nothing);
+        }
+        match action {
+            Action::Suspend => {
+                #[cfg(unix)]
+                {
+                    shutdown()?;
+                    let _ = kill(getpid(), Signal::SIGTSTP);
+                    return Ok(HandlerOperation::Restart);
+                }
+            }
+            Action::OpenFile => {
+                self.nnn_open();
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::Cut => {
+                self.todo_app.remove_todo();
+                if let Some(todo) = self.todo_app.removed_todos.pop() {
+                    self.todo_buffer.yank([todo]);
+                }
+            }
+            Action::ToggleDaily => self.todo_app.toggle_current_daily(),
+            Action::ToggleWeekly => self.todo_app.toggle_current_weekly(),
+            Action::SetSchedule => self.schedule_prompt(),
+            Action::SetReminder => self.reminder_prompt(),
+            Action::SetDue => self.due_prompt(),
+            Action::ToggleSchedule => {
+                self.todo_app.save_undo_point();
+                if let Some(todo) = self.todo_app.todo_mut() {
+                    todo.toggle_schedule();
+                }
+            }
+            Action::ToggleShowDone => self.todo_app.toggle_show_done(),
+            Action::RestrictPriority => self.priority_prompt(),
+            Action::RestrictSchedule => self.schedule_restriction_prompt(),
+            Action::RestrictTag => self.tag_restriction_prompt(),
+            Action::Filter => self.filter_prompt(),
+            Action::Yank => {
+                let todo = self.todo_app.todo().cloned();
+                self.todo_buffer.yank(todo);
+            }
+            Action::Paste => {
+                let todos = self.todo_buffer.get();
+                if !todos.is_empty() {
+                    self.todo_app.paste_todos(todos);
+                }
+            }
+            Action::IncreaseDay => self.todo_app.increase_day_by(1),
+            Action::DecreaseDay => self.todo_app.increase_day_by(-1),
+            Action::AppendFile => {
+                self.nnn_append_todo();
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::OutputFile => {
+                self.nnn_output_todo();
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::MoveDown => self.todo_app.go_down(),
+            Action::MoveUp => self.todo_app.go_up(),
+            Action::GoIn => self.todo_app.add_dependency_traverse_down(),
+            Action::EnterDependency => self.todo_app.traverse_down(),
+            Action::GoOut => {
+                self.todo_app.traverse_up();
+            }
+            Action::GoTop => self.todo_app.index = 0,
+            Action::GoBottom => self.todo_app.index = self.todo_app.bottom(),
+            Action::GoRoot => self.todo_app.go_root(),
+            Action::Write => self.write()?,
+            Action::MoveTodoDown => self.todo_app.move_current_down(),
+            Action::MoveTodoUp => self.todo_app.move_current_up(),
+            Action::ToggleRight => self.show_right = !self.show_right,
+            Action::ToggleOutline => self.outline.enabled = !self.outline.enabled,
+            Action::ToggleFold => {
+                if let Some(id) = self.selected_parent_id() {
+                    self.outline.toggle(&id);
+                }
+            }
+            Action::ExpandAll => self.outline.expand_all(),
+            Action::CollapseAll => self.collapse_outline(),
+            Action::ToggleModule => self.args.enable_module = !self.args.enable_module,
+            Action::EditNote => {
+                self.todo_app.edit_or_add_note();
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::AddDependency => self.todo_app.add_dependency(),
+            Action::Delete => {
+                self.todo_app.remove_todo();
+            }
+            Action::Read => self.todo_app.read(),
+            Action::RemoveDependency => self.todo_app.remove_current_dependent(),
+            Action::ToggleDone => self.todo_app.toggle_current_done(),
+            Action::ToggleMark => {
+                if let Some(id) = self.todo_app.todo().map(|todo| todo.id().to_string()) {
+                    match self.marks.iter().position(|mark| *mark == id) {
+                        Some(position) => {
+                            self.marks.remove(position);
                         }
+                        None => self.marks.push(id),
                     }
-                    Char('!') => self.todo_app.toggle_show_done(),
-                    Char('@') => self.priority_prompt(),
-                    Char('%') => self.schedule_restriction_prompt(),
-                    Char('y') => {
-                        let todo = self.todo_app.todo().cloned();
-                        self.todo_buffer.yank(todo);
-                    }
-                    Char('p') => {
-                        if let Some(todo) = self.todo_buffer.get() {
-                            let list = self.todo_app.current_list_mut();
-                            list.push(todo);
-                            self.todo_app.index = list.reorder_last();
+                    return self.run_action(Action::MoveDown);
+                }
+            }
+            Action::Visual => match self.visual_start.take() {
+                // The range stays marked when it ends
+                Some(start) => {
+                    for id in self.range_ids(&start) {
+                        if !self.marks.contains(&id) {
+                            self.marks.push(id);
                         }
                     }
-                    Char('i') => self.todo_app.increase_day_by(1),
-                    Char('I') => self.todo_app.increase_day_by(-1),
-                    Char('o') => {
-                        self.nnn_append_todo();
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char('O') => {
-                        self.nnn_output_todo();
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    KeyCode::Down | Char('j') => self.todo_app.go_down(),
-                    KeyCode::Up | Char('k') => self.todo_app.go_up(),
-                    KeyCode::Right | Char('l') => self.todo_app.add_dependency_traverse_down(),
-                    KeyCode::Enter => self.todo_app.traverse_down(),
-                    KeyCode::Left | Char('h') => {
-                        self.todo_app.traverse_up();
-                    }
-                    KeyCode::Home | Char('g') => {
-                        self.todo_app.index = 0;
-                    }
-                    KeyCode::End | Char('G') => self.todo_app.index = self.todo_app.bottom(),
-                    Char('w') => self.write()?,
-                    Char('J') => self.todo_app.move_current_down(),
-                    Char('K') => self.todo_app.move_current_up(),
-                    Char(']') => self.show_right = !self.show_right,
-                    Char('P') => self.args.enable_module = !self.args.enable_module,
-                    Char('>') => {
-                        self.todo_app.edit_or_add_note();
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char('t') => self.todo_app.add_dependency(),
-                    Char('D') => {
-                        self.todo_app.remove_todo();
-                    }
-                    Char('R') => self.todo_app.read(),
-                    Char('T') => self.todo_app.remove_current_dependent(),
-                    Char(' ') => self.todo_app.toggle_current_done(),
-                    Char('n') => {
-                        self.tree_search.next();
-                        self.tree_search.set_to_app(self.todo_app);
-                    }
-                    Char('a') => self.prepend_prompt(),
-                    Char('/') => self.search_prompt(),
-                    Char('\'') => self.tree_search_prompt(),
-                    Char('A') => self.append_prompt(),
-                    Char('e') | Char('E') => self.edit_prompt(key.code == Char('E')),
-                    Char('r') if key.modifiers == KeyModifiers::CONTROL => self.edit_prompt(false),
-                    Char('~') => self.todo_app.go_root(),
-                    Char('q') => self.quit_save_prompt(),
-                    Char('r') => {
-                        self.todo_app.batch_editor_messages();
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char(c) if c.is_ascii_digit() => {
-                        let priority = c.to_digit(10).unwrap();
-                        self.todo_app.set_current_priority(priority as u8);
-                    }
-                    Char('?') => self.show_help = !self.show_help,
-
-                    Char('s') => self.potato_module.skip(),
-                    Char('H') => self.potato_module.increase_timer(),
-                    Char('c') => self.potato_module.toggle_pause(),
-                    Char('C') => self.potato_module.quit(),
-                    Char('L') => self.potato_module.decrease_timer(),
-                    Char('f') => self.potato_module.restart(),
-                    Char('F') => {
-                        fzf_search(self.todo_app);
-                        return Ok(HandlerOperation::Restart);
-                    }
-                    Char('+') | Char('=') => self.potato_module.increase_pomodoro(),
-                    Char('-') => self.potato_module.decrease_pomodoro(),
-                    Char('.') => self.potato_module.next(),
-                    Char(',') => self.potato_module.prev(),
-                    _ => {}
                 }
+                None => self.visual_start = self.todo_app.todo().map(|todo| todo.id().to_string()),
+            },
+            // Only does something with marked todos
+            Action::MoveMarked => {}
+            Action::SearchNext => {
+                self.tree_search.next();
+                self.tree_search.set_to_app(self.todo_app);
+            }
+            Action::Prepend => self.prepend_prompt(),
+            Action::Append => self.append_prompt(),
+            Action::Search => self.search_prompt(),
+            Action::TreeSearch => self.tree_search_prompt(),
+            Action::FzfSearch => {
+                fzf_search(self.todo_app);
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::Edit => self.edit_prompt(false),
+            Action::EditStart => self.edit_prompt(true),
+            Action::BatchEdit => {
+                self.todo_app.batch_editor_messages();
+                return Ok(HandlerOperation::Restart);
+            }
+            Action::Undo => {
+                self.todo_app.undo();
+            }
+            Action::Redo => {
+                self.todo_app.redo();
             }
+            Action::Help => self.show_help = !self.show_help,
+            Action::Quit => self.quit_save_prompt(),
+            Action::PotatoSkip => self.potato_module.skip(),
+            Action::PotatoIncreaseTimer => self.potato_module.increase_timer(),
+            Action::PotatoDecreaseTimer => self.potato_module.decrease_timer(),
+            Action::PotatoTogglePause => self.potato_module.toggle_pause(),
+            Action::PotatoQuit => self.potato_module.quit(),
+            Action::PotatoRestart => self.potato_module.restart(),
+            Action::PotatoIncreasePomodoro => self.potato_module.increase_pomodoro(),
+            Action::PotatoDecreasePomodoro => self.potato_module.decrease_pomodoro(),
+            Action::PotatoNext => self.potato_module.next(),
+            Action::PotatoPrev => self.potato_module.prev(),
         }
         Ok(HandlerOperation::Nothing)
     }
 
+    /// Ids of the marked todos and the ones in the range being marked
+    fn selected_ids(&mut self) -> Vec<String> {
+        // Marked todos might be gone, like after an undo
+        let todo_app = &self.todo_app;
+        self.marks.retain(|id| todo_app.todo_by_id(id).is_some());
+        let mut ids = self.marks.clone();
+        if let Some(start) = self.visual_start.clone() {
+            for id in self.range_ids(&start) {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+
+    /// Ids of the todos from the one with the id to the selected one, in the outline or the
+    /// current list
+    fn range_ids(&mut self, start: &str) -> Vec<String> {
+        let shown: Vec<String> = if self.outline.enabled {
+            let (rows, _) = self.outline_rows();
+            rows.iter()
+                .filter_map(|row| self.todo_app.todo_at_path(&row.path()))
+                .map(|todo| todo.id().to_string())
+                .collect()
+        } else {
+            self.todo_app
+                .current_list()
+                .todos(self.todo_app.get_restriction())
+                .map(|todo| todo.id().to_string())
+                .collect()
+        };
+        let end = self.todo_app.todo().map(|todo| todo.id());
+        let start_position = shown.iter().position(|id| id == start);
+        let end_position = shown.iter().position(|id| Some(id.as_str()) == end);
+        match (start_position, end_position) {
+            (Some(start), Some(end)) => shown[start.min(end)..=start.max(end)].to_vec(),
+            // The range started in another list
+            _ => vec![start.to_string()],
+        }
+    }
+
+    #[inline]
+    fn clear_selection(&mut self) {
+        self.marks.clear();
+        self.visual_start = None;
+    }
+
+    /// Runs the action on every selected todo, returning false for actions that only work on
+    /// the todo under the cursor
+    fn run_bulk_action(&mut self, action: Action, ids: &[String]) -> bool {
+        match action {
+            Action::ToggleDone => {
+                // Done, unless every one of them is done already
+                let done = ids.iter().any(|id| {
+                    self.todo_app
+                        .todo_by_id(id)
+                        .is_some_and(|todo| !todo.done())
+                });
+                self.todo_app.set_done_by_ids(ids, done);
+            }
+            Action::Delete => self.todo_app.remove_by_ids(ids),
+            Action::Cut => {
+                let start = self.todo_app.removed_todos.len();
+                self.todo_app.remove_by_ids(ids);
+                let todos: Vec<Todo> = self.todo_app.removed_todos.drain(start..).collect();
+                self.todo_buffer.yank(todos);
+            }
+            Action::Yank => {
+                let todos: Vec<Todo> = ids
+                    .iter()
+                    .filter_map(|id| self.todo_app.todo_by_id(id).cloned())
+                    .collect();
+                self.todo_buffer.yank(todos);
+            }
+            Action::MoveMarked => self.todo_app.move_ids_here(ids),
+            // The marks stay until the schedule is given
+            Action::SetSchedule => {
+                self.set_text_mode(
+                    Self::on_bulk_schedule,
+                    "Change schedule day or rule of the marked todos",
+                    "7, 2w, weekdays, mon,thu, month(1,15), month(fri,-1)",
+                );
+                return true;
+            }
+            _ => return false,
+        }
+        self.clear_selection();
+        true
+    }
+
+    #[inline]
+    fn on_bulk_schedule(&mut self, str: String) {
+        let selected = self.selected_ids();
+        if self.todo_app.schedule_by_ids(&selected, &str) {
+            self.clear_selection();
+        }
+    }
+
+    /// Id of the selected todo if it has sub todos to expand or collapse
+    fn selected_parent_id(&self) -> Option<String> {
+        self.todo_app
+            .todo()
+            .filter(|todo| todo.dependency.as_ref().is_some_and(|dep| dep.is_list()))
+            .map(|todo| todo.id().to_string())
+    }
+
+    /// Collapses the whole outline, moving the cursor to the todo of the root list it was under
+    fn collapse_outline(&mut self) {
+        self.outline.collapse_all(&self.todo_app.todo_list);
+        if let Some(&root_index) = self.todo_app.tree_path.first() {
+            let rows = self
+                .outline
+                .rows(&self.todo_app.todo_list, self.todo_app.get_restriction());
+            if let Some(row) = rows.iter().find(|row| row.path() == [root_index]) {
+                self.select_row(row);
+            }
+        }
+    }
+
+    #[inline]
+    fn select_row(&mut self, row: &Row) {
+        self.todo_app.tree_path = row.tree_path.clone();
+        self.todo_app.index = row.index;
+    }
+
+    /// The rows of the outline and the one of the selected todo, moving the cursor to a row if
+    /// the outline doesn't show the selected todo
+    fn outline_rows(&mut self) -> (Vec<Row>, Option<usize>) {
+        // The parents of the selected todo are expanded, for it to be seen
+        for depth in 1..=self.todo_app.tree_path.len() {
+            if let Some(todo) = self.todo_app.todo_at_path(&self.todo_app.tree_path[..depth]) {
+                self.outline.expand(todo.id());
+            }
+        }
+        let rows = self
+            .outline
+            .rows(&self.todo_app.todo_list, self.todo_app.get_restriction());
+        if !self.todo_app.is_todos_empty() {
+            self.todo_app.index = self.todo_app.index.min(self.todo_app.bottom());
+        }
+        let tree_path = &self.todo_app.tree_path;
+        let index = self.todo_app.index;
+        let position = rows
+            .iter()
+            .position(|row| row.tree_path == *tree_path && row.index == index)
+            // Nothing is left in the list of the cursor, like when its last todo is done
+            .or_else(|| rows.iter().position(|row| row.path() == *tree_path))
+            .or((!rows.is_empty()).then_some(0));
+        if let Some(position) = position {
+            self.select_row(&rows[position]);
+        }
+        (rows, position)
+    }
+
+    /// Moves the cursor between the rows of the outline, returning false for actions that
+    /// aren't moves
+    fn move_in_outline(&mut self, action: Action) -> bool {
+        let (rows, position) = self.outline_rows();
+        let Some(position) = position else {
+            return false;
+        };
+        let row = &rows[position];
+        let target = match action {
+            Action::MoveDown => (position + 1) % rows.len(),
+            Action::MoveUp => position.checked_sub(1).unwrap_or(rows.len() - 1),
+            Action::GoTop | Action::GoRoot => 0,
+            Action::GoBottom => rows.len() - 1,
+            Action::GoIn | Action::EnterDependency => match row.fold {
+                Fold::Collapsed => {
+                    if let Some(id) = self.selected_parent_id() {
+                        self.outline.expand(&id);
+                    }
+                    position
+                }
+                Fold::Expanded => position + 1,
+                Fold::Leaf => position,
+            },
+            Action::GoOut => match row.fold {
+                Fold::Expanded => {
+                    if let Some(id) = self.selected_parent_id() {
+                        self.outline.collapse(&id);
+                    }
+                    position
+                }
+                _ => rows
+                    .iter()
+                    .position(|parent| parent.path() == row.tree_path)
+                    .unwrap_or(position),
+            },
+            _ => return false,
+        };
+        self.select_row(&rows[target]);
+        true
+    }
+
     #[inline]
     fn is_dependency_enabled(&self, todo: Option<&Todo>) -> bool {
-        todo.map_or(false, |todo| {
-            self.show_right && todo.dependency.is_some() && self.todo_app.is_tree()
+        todo.is_some_and(|todo| {
+            self.show_right
+                && todo.dependency.is_some()
+                && self.todo_app.is_tree()
+                // Sub todos are already in the outline
+                && !(self.outline.enabled && todo.dependency.as_ref().is_some_and(|dep| dep.is_list()))
         })
     }
 
@@ -729,11 +1172,14 @@ impl<'a> TuiApp<'a> {
         frame: &mut Frame,
         todo: Option<&Todo>,
         dependency_layout: Rect,
+        selected: &[String],
     ) {
         if let Some(todo) = todo {
             if let Some(note) = todo.dependency.as_ref().and_then(|dep| dep.note()) {
-                match self.args.no_glow.not().then(|| {
-                    let mut glow = Command::new("glow");
+                let mut viewer = self.args.note_viewer.split_whitespace();
+                match viewer.next().filter(|_| !self.args.no_glow).map(|program| {
+                    let mut glow = Command::new(program);
+                    glow.args(viewer);
                     glow.stdin(Stdio::piped());
                     glow.stdout(Stdio::piped());
                     glow.spawn()
@@ -763,16 +1209,19 @@ impl<'a> TuiApp<'a> {
                 }
             }
             if let Some(todo_list) = todo.dependency.as_ref().and_then(|dep| dep.todo_list()) {
+                let lines = self.todo_lines(
+                    todo_list,
+                    0,
+                    dependency_layout.height as usize - 2,
+                    selected,
+                );
                 Self::render_todos_widget(
                     self.highlight_string(),
+                    &self.theme,
                     frame,
                     None,
                     dependency_layout,
-                    self.todo_app.display_a_slice(
-                        todo_list,
-                        0,
-                        dependency_layout.height as usize - 2,
-                    ),
+                    lines,
                     String::from("Todo dependencies"),
                 )
             }
@@ -785,40 +1234,108 @@ impl<'a> TuiApp<'a> {
         frame: &mut Frame,
         list_state: &mut ListState,
         todo_layout: Rect,
+        selected: &[String],
     ) {
-        let title = self.title();
-        let display = if self.args.minimal_render {
+        let title = self.title() + &marked_str(selected.len());
+        let current_list = self.todo_app.current_list();
+        let lines = if self.args.minimal_render {
             let first = self.todo_app.index();
-            let last = self
-                .todo_app
-                .current_list()
+            let last = current_list
                 .len(self.todo_app.get_restriction())
                 .min(todo_layout.height as usize + first - 2);
-            self.todo_app
-                .display_a_slice(self.todo_app.current_list(), first, last)
+            self.todo_lines(current_list, first, last, selected)
         } else {
-            self.todo_app.display_current_list()
+            self.todo_lines(current_list, 0, usize::MAX, selected)
         };
         Self::render_todos_widget(
             self.highlight_string(),
+            &self.theme,
             frame,
             Some(list_state),
             todo_layout,
-            display,
+            lines,
             title,
         )
     }
 
+    #[inline(always)]
+    fn render_outline_widget(
+        &self,
+        frame: &mut Frame,
+        list_state: &mut ListState,
+        todo_layout: Rect,
+        rows: Vec<Row>,
+        selected: &[String],
+    ) {
+        let changed_str = if self.todo_app.is_changed() { "*" } else { "" };
+        let read_only_str = if self.todo_app.is_read_only() {
+            " (read only)"
+        } else {
+            ""
+        };
+        let title = format!(
+            "Outline ({}){changed_str}{read_only_str}{}",
+            rows.len(),
+            marked_str(selected.len())
+        );
+        let lines = rows
+            .iter()
+            .filter_map(|row| {
+                let todo = self.todo_app.todo_at_path(&row.path())?;
+                let mut line = self.todo_line(todo, selected);
+                let guide = format!("{}{}", row.guide, row.fold_marker());
+                line.spans.insert(0, Span::raw(guide));
+                Some(line)
+            })
+            .collect();
+        Self::render_todos_widget(
+            self.highlight_string(),
+            &self.theme,
+            frame,
+            Some(list_state),
+            todo_layout,
+            lines,
+            title,
+        )
+    }
+
+    /// The styled lines of the todos of the list the restriction lets through, like
+    /// `TodoList::display_slice`
+    fn todo_lines(
+        &self,
+        todo_list: &TodoList,
+        min: usize,
+        max: usize,
+        selected: &[String],
+    ) -> Vec<Line<'static>> {
+        todo_list
+            .restricted_slice(self.todo_app.get_restriction(), min, max)
+            .map(|todo| self.todo_line(todo, selected))
+            .collect()
+    }
+
+    #[inline]
+    fn todo_line(&self, todo: &Todo, selected: &[String]) -> Line<'static> {
+        let display = todo.display_with_args(&self.todo_app.args.display_args);
+        let line = self.theme.todo_line(todo, display);
+        if selected.iter().any(|id| id == todo.id()) {
+            self.theme.marked(line)
+        } else {
+            line
+        }
+    }
+
     #[inline(always)]
     fn render_todos_widget(
         highlight_symbol: &str,
+        theme: &Theme,
         frame: &mut Frame,
         list_state: Option<&mut ListState>,
         todo_layout: Rect,
-        display_list: Vec<String>,
+        lines: Vec<Line<'static>>,
         title: String,
     ) {
-        match create_todo_widget(display_list, title, highlight_symbol) {
+        match create_todo_widget(lines, title, highlight_symbol, theme) {
             TodoWidget::Paragraph(widget) => frame.render_widget(widget, todo_layout),
             TodoWidget::List(widget) => {
                 if let Some(list_state) = list_state {
@@ -834,10 +1351,10 @@ impl<'a> TuiApp<'a> {
     fn render_help_widget(&self, frame: &mut Frame) {
         let size = frame.size();
         let floating_window = Rect::new(
-            size.width / 4,
-            size.height / 4,
-            size.width / 2,
-            size.height / 2,
+            size.width / 8,
+            size.height / 8,
+            size.width * 3 / 4,
+            size.height * 3 / 4,
         );
 
         self.help_page.render(frame, floating_window);
@@ -845,8 +1362,12 @@ impl<'a> TuiApp<'a> {
 
     #[inline]
     pub fn ui(&mut self, frame: &mut Frame, list_state: &mut ListState) {
+        let outline_rows = self.outline.enabled.then(|| self.outline_rows());
+        let selected = self.selected_ids();
         let todo = self.todo_app.todo();
-        if !self.args.minimal_render {
+        if let Some((_, position)) = outline_rows {
+            list_state.select(position);
+        } else if !self.args.minimal_render {
             list_state.select(Some(self.todo_app.index()));
         }
 
@@ -884,18 +1405,32 @@ impl<'a> TuiApp<'a> {
             ])
             .split(todo_app_layout[0]);
         if dependency_enabled {
-            self.render_dependency_widget(frame, todo, todo_app_layout[1]);
+            self.render_dependency_widget(frame, todo, todo_app_layout[1], &selected);
         }
         if is_editing {
             frame.render_widget(self.textarea.widget(), todo_and_textarea_layout[0]);
         }
-        self.render_current_todos_widget(frame, list_state, todo_and_textarea_layout[1]);
+        if let Some((rows, _)) = outline_rows {
+            let layout = todo_and_textarea_layout[1];
+            self.render_outline_widget(frame, list_state, layout, rows, &selected);
+        } else {
+            let layout = todo_and_textarea_layout[1];
+            self.render_current_todos_widget(frame, list_state, layout, &selected);
+        }
         if self.show_help {
             self.render_help_widget(frame);
         }
     }
 }
 
+/// What the title says about the marked todos
+fn marked_str(count: usize) -> String {
+    match count {
+        0 => String::new(),
+        count => format!(" [{count} marked]"),
+    }
+}
+
 pub fn default_block<'a, T>(title: T) -> Block<'a>
 where
     T: Into<Line<'a>>,
@@ -911,18 +1446,19 @@ pub enum TodoWidget<'a> {
     Paragraph(ratatui::widgets::Paragraph<'a>),
 }
 
-pub fn create_todo_widget(
-    display_list: Vec<String>,
+pub fn create_todo_widget<'a>(
+    lines: Vec<Line<'static>>,
     title: String,
-    highlight_symbol: &str,
-) -> TodoWidget<'_> {
-    if display_list.is_empty() {
+    highlight_symbol: &'a str,
+    theme: &Theme,
+) -> TodoWidget<'a> {
+    if lines.is_empty() {
         TodoWidget::Paragraph(Paragraph::new("No todo.").block(default_block(title)))
     } else {
         TodoWidget::List(
-            List::new(display_list)
+            List::new(lines)
                 .block(default_block(title))
-                .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+                .highlight_style(theme.highlight())
                 .highlight_symbol(highlight_symbol)
                 .repeat_highlight_symbol(true),
         )
@@ -947,12 +1483,16 @@ pub fn startup() -> io::Result<()> {
 }
 
 #[inline]
-pub fn run(app: &mut App, args: TuiArgs) -> io::Result<()> {
+pub fn run(app: &mut App, args: TuiArgs, keymap: Keymap, theme: Theme) -> io::Result<()> {
     startup()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
 
+    // Another TUI is open on the same todos, this one only shows them
+    if app.lock(None).is_err() {
+        app.set_read_only(true);
+    }
     let mut list_state = ListState::default().with_selected(Some(0));
-    let mut app = TuiApp::new(app, args);
+    let mut app = TuiApp::new(app, args, keymap, theme);
 
     loop {
         terminal.draw(|frame| app.ui(frame, &mut list_state))?;
@@ -1,18 +1,17 @@
 use ratatui::{
-    layout::Rect,
+    layout::{Constraint, Direction, Layout, Rect},
     widgets::{Block, BorderType, Borders, Paragraph, Clear},
     Frame,
 };
-use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct HelpPage {
-    entries: HashMap<String, String>,
+    entries: Vec<(String, String)>,
 }
 
 impl HelpPage {
     pub fn add_entry(&mut self, key: &str, value: &str) {
-        self.entries.insert(key.to_string(), value.to_string());
+        self.entries.push((key.to_string(), value.to_string()));
     }
 
     pub fn render(&self, frame: &mut Frame, area: Rect) {
@@ -20,14 +19,23 @@ impl HelpPage {
             .title("Help")
             .borders(Borders::ALL)
             .border_type(BorderType::Rounded);
+        let inner = block.inner(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
 
-        let mut content = String::new();
-        for (key, value) in &self.entries {
-            content.push_str(&format!("{}: {}\n", key, value));
+        // As many columns as the entries need to fit in the height
+        let height = usize::from(inner.height.max(1));
+        let columns = self.entries.len().div_ceil(height).max(1);
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Ratio(1, columns as u32); columns])
+            .split(inner);
+        for (entries, column) in self.entries.chunks(height).zip(layout.iter()) {
+            let mut content = String::new();
+            for (key, value) in entries {
+                content.push_str(&format!("{}: {}\n", key, value));
+            }
+            frame.render_widget(Paragraph::new(content), *column);
         }
-
-        let paragraph = Paragraph::new(content).block(block);
-        frame.render_widget(Clear, area);
-        frame.render_widget(paragraph, area);
     }
 }
@@ -0,0 +1,556 @@
+//! Named actions of the TUI and the keys they're bound to. Keys are written like in vim: `j` and
+//! `G` are keys, `gg` and `dd` sequences of them, and `<C-r>`, `<A-x>`, `<Down>`, `<Space>`
+//! or `<lt>` (for `<`) named ones.
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+use std::io;
+
+use c3::fileio::invalid_data;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Suspend,
+    OpenFile,
+    Cut,
+    ToggleDaily,
+    ToggleWeekly,
+    SetSchedule,
+    SetReminder,
+    SetDue,
+    ToggleSchedule,
+    ToggleShowDone,
+    RestrictPriority,
+    RestrictSchedule,
+    RestrictTag,
+    Filter,
+    Yank,
+    Paste,
+    IncreaseDay,
+    DecreaseDay,
+    AppendFile,
+    OutputFile,
+    MoveDown,
+    MoveUp,
+    GoIn,
+    EnterDependency,
+    GoOut,
+    GoTop,
+    GoBottom,
+    GoRoot,
+    Write,
+    MoveTodoDown,
+    MoveTodoUp,
+    ToggleRight,
+    ToggleOutline,
+    ToggleFold,
+    ExpandAll,
+    CollapseAll,
+    ToggleModule,
+    EditNote,
+    AddDependency,
+    Delete,
+    Read,
+    RemoveDependency,
+    ToggleDone,
+    ToggleMark,
+    Visual,
+    MoveMarked,
+    SearchNext,
+    Prepend,
+    Append,
+    Search,
+    TreeSearch,
+    FzfSearch,
+    Edit,
+    EditStart,
+    BatchEdit,
+    Undo,
+    Redo,
+    Help,
+    Quit,
+    PotatoSkip,
+    PotatoIncreaseTimer,
+    PotatoDecreaseTimer,
+    PotatoTogglePause,
+    PotatoQuit,
+    PotatoRestart,
+    PotatoIncreasePomodoro,
+    PotatoDecreasePomodoro,
+    PotatoNext,
+    PotatoPrev,
+}
+
+/// Every action with its name, default keys and what it does
+#[rustfmt::skip]
+const ACTIONS: [(Action, &str, &[&str], &str); 69] = [
+    (Action::Suspend, "suspend", &["<C-z>"], "Suspend c3 to the background"),
+    (Action::OpenFile, "open_file", &["<C-o>"], "Open a todo file with nnn"),
+    (Action::Cut, "cut", &["x"], "Cut todo to the clipboard"),
+    (Action::ToggleDaily, "toggle_daily", &["d"], "Toggle daily"),
+    (Action::ToggleWeekly, "toggle_weekly", &["W"], "Toggle weekly"),
+    (Action::SetSchedule, "set_schedule", &["S"], "Set a custom schedule"),
+    (Action::SetReminder, "set_reminder", &["m"], "Set todo as a reminder"),
+    (Action::SetDue, "set_due", &["$"], "Set due date"),
+    (Action::ToggleSchedule, "toggle_schedule", &["M"], "Turn the schedule on or off"),
+    (Action::ToggleShowDone, "toggle_show_done", &["!"], "Toggle showing done todos"),
+    (Action::RestrictPriority, "restrict_priority", &["@"], "Limit todos by priority"),
+    (Action::RestrictSchedule, "restrict_schedule", &["%"], "Limit todos by schedule day"),
+    (Action::RestrictTag, "restrict_tag", &["#"], "Limit todos by tag"),
+    (Action::Filter, "filter", &["|"], "Filter todos with a query"),
+    (Action::Yank, "yank", &["y"], "Yank todo to the clipboard"),
+    (Action::Paste, "paste", &["p"], "Paste todo from the clipboard"),
+    (Action::IncreaseDay, "increase_day", &["i"], "Increase day by 1"),
+    (Action::DecreaseDay, "decrease_day", &["I"], "Decrease day by 1"),
+    (Action::AppendFile, "append_file", &["o"], "Append a todo file with nnn"),
+    (Action::OutputFile, "output_file", &["O"], "Output todos to a file with nnn"),
+    (Action::MoveDown, "move_down", &["j", "<Down>"], "Move down"),
+    (Action::MoveUp, "move_up", &["k", "<Up>"], "Move up"),
+    (Action::GoIn, "go_in", &["l", "<Right>"], "Go in the dependency, adding one"),
+    (Action::EnterDependency, "enter_dependency", &["<Enter>"], "Go in the dependency"),
+    (Action::GoOut, "go_out", &["h", "<Left>"], "Go back to the parent"),
+    (Action::GoTop, "go_top", &["g", "<Home>"], "Go to the top"),
+    (Action::GoBottom, "go_bottom", &["G", "<End>"], "Go to the bottom"),
+    (Action::GoRoot, "go_root", &["~"], "Go to the root list"),
+    (Action::Write, "write", &["w"], "Write changes"),
+    (Action::MoveTodoDown, "move_todo_down", &["J"], "Move todo down"),
+    (Action::MoveTodoUp, "move_todo_up", &["K"], "Move todo up"),
+    (Action::ToggleRight, "toggle_right", &["]"], "Toggle the dependency pane"),
+    (Action::ToggleOutline, "toggle_outline", &["<Tab>"], "Toggle the outline of the whole tree"),
+    (Action::ToggleFold, "toggle_fold", &["za"], "Expand or collapse todo in the outline"),
+    (Action::ExpandAll, "expand_all", &["zR"], "Expand every todo in the outline"),
+    (Action::CollapseAll, "collapse_all", &["zM"], "Collapse every todo in the outline"),
+    (Action::ToggleModule, "toggle_module", &["P"], "Toggle the potato module"),
+    (Action::EditNote, "edit_note", &[">"], "Add or edit todo note"),
+    (Action::AddDependency, "add_dependency", &["t"], "Add todo dependency"),
+    (Action::Delete, "delete", &["D"], "Delete todo"),
+    (Action::Read, "read", &["R"], "Read from file (discard changes)"),
+    (Action::RemoveDependency, "remove_dependency", &["T"], "Delete todo dependency/note"),
+    (Action::ToggleDone, "toggle_done", &["<Space>"], "Toggle todo done"),
+    (Action::ToggleMark, "toggle_mark", &["v"], "Mark or unmark todo"),
+    (Action::Visual, "visual", &["V"], "Start or end marking a range of todos"),
+    (Action::MoveMarked, "move_marked", &["X"], "Move the marked todos to the current list"),
+    (Action::SearchNext, "search_next", &["n"], "Tree search next"),
+    (Action::Prepend, "prepend", &["a"], "Add todo to the bottom"),
+    (Action::Append, "append", &["A"], "Add todo to the top"),
+    (Action::Search, "search", &["/"], "Search the current list"),
+    (Action::TreeSearch, "tree_search", &["'"], "Search the whole tree"),
+    (Action::FzfSearch, "fzf_search", &["F"], "FZF search"),
+    (Action::Edit, "edit", &["e"], "Edit todo"),
+    (Action::EditStart, "edit_start", &["E"], "Edit todo (start)"),
+    (Action::BatchEdit, "batch_edit", &["r"], "Batch edit messages"),
+    (Action::Undo, "undo", &["u"], "Undo"),
+    (Action::Redo, "redo", &["<C-r>"], "Redo"),
+    (Action::Help, "help", &["?"], "Toggle this help"),
+    (Action::Quit, "quit", &["q"], "Quit the application"),
+    (Action::PotatoSkip, "potato_skip", &["s"], "Skip potato module"),
+    (Action::PotatoIncreaseTimer, "potato_increase_timer", &["H"], "Increase potato timer"),
+    (Action::PotatoDecreaseTimer, "potato_decrease_timer", &["L"], "Decrease potato timer"),
+    (Action::PotatoTogglePause, "potato_toggle_pause", &["c"], "Toggle potato pause"),
+    (Action::PotatoQuit, "potato_quit", &["C"], "Quit potato module"),
+    (Action::PotatoRestart, "potato_restart", &["f"], "Restart potato module"),
+    (Action::PotatoIncreasePomodoro, "potato_increase_pomodoro", &["+", "="], "Increase pomodoro"),
+    (Action::PotatoDecreasePomodoro, "potato_decrease_pomodoro", &["-"], "Decrease pomodoro"),
+    (Action::PotatoNext, "potato_next", &["."], "Next potato module"),
+    (Action::PotatoPrev, "potato_prev", &[","], "Previous potato module"),
+];
+
+/// Names of keys written in angle brackets
+const KEY_NAMES: [(KeyCode, &str); 15] = [
+    (KeyCode::Char(' '), "Space"),
+    (KeyCode::Char('<'), "lt"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Esc, "Esc"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "BS"),
+    (KeyCode::Delete, "Del"),
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Home, "Home"),
+    (KeyCode::End, "End"),
+    (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"),
+];
+
+impl Action {
+    #[inline]
+    fn entry(self) -> &'static (Action, &'static str, &'static [&'static str], &'static str) {
+        ACTIONS
+            .iter()
+            .find(|(action, ..)| *action == self)
+            .expect("every action is in ACTIONS")
+    }
+
+    #[inline]
+    pub fn name(self) -> &'static str {
+        self.entry().1
+    }
+
+    #[inline]
+    pub fn description(self) -> &'static str {
+        self.entry().3
+    }
+
+    /// Actions of the potato module, which only take their keys while it's enabled
+    #[inline]
+    pub fn is_module(self) -> bool {
+        self.name().starts_with("potato_")
+    }
+}
+
+/// A key with the modifiers that matter for bindings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for KeyPress {
+    fn from(event: KeyEvent) -> Self {
+        // Shift is already in the character, like `G`
+        let modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        Self {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = KEY_NAMES
+            .iter()
+            .find(|(code, _)| *code == self.code)
+            .map(|(_, name)| name.to_string());
+        let name = match (name, self.code) {
+            (Some(name), _) => name,
+            (None, KeyCode::Char(c)) if self.modifiers.is_empty() => return write!(f, "{c}"),
+            (None, KeyCode::Char(c)) => c.to_string(),
+            (None, KeyCode::F(number)) => format!("F{number}"),
+            (None, code) => format!("{code:?}"),
+        };
+        let mut prefix = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+        write!(f, "<{prefix}{name}>")
+    }
+}
+
+/// The key named inside angle brackets, like `C-r` or `Down`
+fn parse_named_key(name: &str) -> Option<KeyPress> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    loop {
+        if let Some(after) = rest.strip_prefix("C-") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("A-") {
+            modifiers |= KeyModifiers::ALT;
+            rest = after;
+        } else {
+            break;
+        }
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(_, key_name)| key_name.eq_ignore_ascii_case(rest))
+            .map(|(code, _)| *code)
+            .or_else(|| {
+                let number = rest.strip_prefix(['F', 'f'])?.parse().ok()?;
+                Some(KeyCode::F(number))
+            })?,
+    };
+    Some(KeyPress { code, modifiers })
+}
+
+/// The keys of a binding like `gg` or `<C-r>`
+pub fn parse_keys(binding: &str) -> Option<Vec<KeyPress>> {
+    let mut keys = vec![];
+    let mut rest = binding;
+    while let Some(c) = rest.chars().next() {
+        let named = rest
+            .strip_prefix('<')
+            .and_then(|after| after.split_once('>'))
+            .and_then(|(name, after)| Some((parse_named_key(name)?, after)));
+        if let Some((key, after)) = named {
+            keys.push(key);
+            rest = after;
+        } else {
+            keys.push(KeyPress {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE,
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    (!keys.is_empty()).then_some(keys)
+}
+
+#[inline(always)]
+fn write_keys(keys: &[KeyPress]) -> String {
+    keys.iter().map(KeyPress::to_string).collect()
+}
+
+/// What the keys pressed so far do
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys are the start of a longer binding
+    Pending,
+    None,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyPress>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .flat_map(|(action, _, keys, _)| {
+                keys.iter()
+                    .filter_map(|binding| parse_keys(binding))
+                    .map(|keys| (keys, *action))
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+/// Whether one of the bindings is the start of the other, so both can't be told apart
+#[inline(always)]
+fn overlaps(keys: &[KeyPress], other: &[KeyPress]) -> bool {
+    keys.starts_with(other) || other.starts_with(keys)
+}
+
+/// Whether the two actions can't share keys. Potato module actions take their keys from the
+/// others while it's enabled
+#[inline(always)]
+fn conflicts(action: Action, other: Action) -> bool {
+    action.is_module() == other.is_module()
+}
+
+impl Keymap {
+    /// The default keymap with the bindings of the actions given replaced. Default bindings
+    /// that overlap the given ones are dropped
+    pub fn new(keys: &[(String, Vec<String>)]) -> io::Result<Self> {
+        let mut given: Vec<(Vec<KeyPress>, Action)> = vec![];
+        for (name, bindings) in keys {
+            let action = ACTIONS
+                .iter()
+                .find(|(_, action_name, ..)| action_name == name)
+                .map(|(action, ..)| *action)
+                .ok_or_else(|| invalid_data(format!("keys.{name} isn't an action")))?;
+            for binding in bindings {
+                let keys = parse_keys(binding)
+                    .ok_or_else(|| invalid_data(format!("keys.{name} has an empty key")))?;
+                let conflict = given.iter().find(|(other_keys, other)| {
+                    overlaps(&keys, other_keys) && conflicts(action, *other)
+                });
+                if let Some((other_keys, other)) = conflict {
+                    return Err(invalid_data(format!(
+                        "keys.{name} \"{binding}\" can't be told apart from \"{}\" of {}",
+                        write_keys(other_keys),
+                        other.name()
+                    )));
+                }
+                given.push((keys, action));
+            }
+        }
+        let mut bindings = Self::default().bindings;
+        bindings.retain(|(keys, action)| {
+            !given.iter().any(|(given_keys, given_action)| {
+                given_action == action
+                    || (overlaps(keys, given_keys) && conflicts(*action, *given_action))
+            })
+        });
+        bindings.extend(given);
+        // Keep the order of ACTIONS, for the help and the config
+        bindings.sort_by_key(|(_, action)| ACTIONS.iter().position(|(other, ..)| other == action));
+        Ok(Self { bindings })
+    }
+
+    /// What the keys do, with the potato module enabled or not
+    pub fn lookup(&self, keys: &[KeyPress], module_enabled: bool) -> Lookup {
+        if module_enabled {
+            let lookup = self.lookup_among(keys, true);
+            if lookup != Lookup::None {
+                return lookup;
+            }
+        }
+        self.lookup_among(keys, false)
+    }
+
+    /// What the keys do among the module actions or the others, which have no overlapping
+    /// bindings
+    fn lookup_among(&self, keys: &[KeyPress], module: bool) -> Lookup {
+        let mut lookup = Lookup::None;
+        for (binding, action) in &self.bindings {
+            if action.is_module() != module {
+                continue;
+            }
+            if binding == keys {
+                return Lookup::Action(*action);
+            }
+            if binding.starts_with(keys) {
+                lookup = Lookup::Pending;
+            }
+        }
+        lookup
+    }
+
+    /// Every action with its keys, in the order of `ACTIONS`
+    pub fn actions(&self) -> Vec<(Action, Vec<String>)> {
+        ACTIONS
+            .iter()
+            .map(|(action, ..)| {
+                let keys = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, other)| other == action)
+                    .map(|(keys, _)| write_keys(keys))
+                    .collect();
+                (*action, keys)
+            })
+            .collect()
+    }
+
+    /// The `[keys]` section of the config that makes this keymap
+    pub fn config(&self) -> String {
+        let mut lines = vec![String::from("[keys]")];
+        for (action, keys) in self.actions() {
+            let keys: Vec<String> = keys
+                .into_iter()
+                .map(|key| toml::Value::String(key).to_string())
+                .collect();
+            lines.push(format!("{} = [{}]", action.name(), keys.join(", ")));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(binding: &str) -> Vec<KeyPress> {
+        parse_keys(binding).unwrap()
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(keys("gg").len(), 2);
+        assert_eq!(
+            keys("<C-r>"),
+            vec![KeyPress {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::CONTROL
+            }]
+        );
+        assert_eq!(keys("<Space>")[0].code, KeyCode::Char(' '));
+        assert_eq!(keys("<lt>")[0].code, KeyCode::Char('<'));
+        assert_eq!(keys("<F5>")[0].code, KeyCode::F(5));
+        // Not a named key, so every character is a key
+        assert_eq!(keys("<x").len(), 2);
+        assert_eq!(keys("<nothing>").len(), 9);
+        assert_eq!(parse_keys(""), None);
+        for binding in [
+            "gg", "<C-r>", "<A-C-x>", "<Space>", "<lt>", "<Down>", "G", "<F5>",
+        ] {
+            let written = write_keys(&keys(binding));
+            assert_eq!(keys(&written), keys(binding));
+        }
+        assert_eq!(write_keys(&keys("<A-C-x>")), "<C-A-x>");
+    }
+
+    #[test]
+    fn test_lookup() -> io::Result<()> {
+        let keymap = Keymap::default();
+        assert_eq!(
+            keymap.lookup(&keys("j"), false),
+            Lookup::Action(Action::MoveDown)
+        );
+        assert_eq!(
+            keymap.lookup(&keys("<Down>"), false),
+            Lookup::Action(Action::MoveDown)
+        );
+        assert_eq!(keymap.lookup(&keys("s"), false), Lookup::None);
+        assert_eq!(
+            keymap.lookup(&keys("s"), true),
+            Lookup::Action(Action::PotatoSkip)
+        );
+
+        let keymap = Keymap::new(&[
+            (String::from("go_top"), vec![String::from("gg")]),
+            (String::from("delete"), vec![String::from("dd")]),
+            (String::from("potato_skip"), vec![String::from("x")]),
+        ])?;
+        assert_eq!(keymap.lookup(&keys("g"), false), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(&keys("gg"), false),
+            Lookup::Action(Action::GoTop)
+        );
+        // The default `d` of toggle_daily overlaps `dd`, so it's dropped
+        assert_eq!(keymap.lookup(&keys("d"), false), Lookup::Pending);
+        assert_eq!(keymap.lookup(&keys("D"), false), Lookup::None);
+        assert_eq!(
+            keymap.lookup(&keys("dd"), false),
+            Lookup::Action(Action::Delete)
+        );
+        assert_eq!(keymap.lookup(&keys("<Home>"), false), Lookup::None);
+        // The potato module takes keys while it's enabled
+        assert_eq!(
+            keymap.lookup(&keys("x"), false),
+            Lookup::Action(Action::Cut)
+        );
+        assert_eq!(
+            keymap.lookup(&keys("x"), true),
+            Lookup::Action(Action::PotatoSkip)
+        );
+        assert_eq!(keymap.lookup(&keys("s"), true), Lookup::None);
+
+        let actions = keymap.actions();
+        assert_eq!(actions[0], (Action::Suspend, vec![String::from("<C-z>")]));
+        assert!(actions.contains(&(Action::ToggleDaily, vec![])));
+        assert!(keymap.config().contains("\ngo_top = [\"gg\"]\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_keymap_errors() {
+        let error = |name: &str, bindings: &[&str]| {
+            let bindings = bindings.iter().map(|binding| binding.to_string()).collect();
+            Keymap::new(&[(name.to_string(), bindings)])
+                .err()
+                .map(|error| error.to_string())
+        };
+        assert_eq!(error("go_top", &["gg", "<Home>"]), None);
+        assert_eq!(
+            error("toggle_fun", &["f"]),
+            Some(String::from("keys.toggle_fun isn't an action"))
+        );
+        assert_eq!(
+            error("quit", &[""]),
+            Some(String::from("keys.quit has an empty key"))
+        );
+        let overlapping = Keymap::new(&[
+            (String::from("go_top"), vec![String::from("gg")]),
+            (String::from("go_bottom"), vec![String::from("g")]),
+        ]);
+        assert_eq!(
+            overlapping.err().map(|error| error.to_string()),
+            Some(String::from(
+                "keys.go_bottom \"g\" can't be told apart from \"gg\" of go_top"
+            ))
+        );
+    }
+}
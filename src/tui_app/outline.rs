@@ -0,0 +1,220 @@
+//! The outline of the TUI, the whole tree shown inline with tree guides like the ones of the CLI.
+//! Todos with sub todos can be collapsed, and the cursor of the app moves between the rows of
+//! the outline so every operation works on whatever todo is selected.
+use c3::todo_app::{Restriction, Todo, TodoList};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fold {
+    /// No sub todos the restriction lets through
+    Leaf,
+    Expanded,
+    Collapsed,
+}
+
+/// A todo shown in the outline
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Row {
+    /// Tree path of the list the todo is in, like `App::tree_path`
+    pub tree_path: Vec<usize>,
+    /// Index of the todo in its list with the restriction, like `App::index`
+    pub index: usize,
+    /// Index of the todo in its list, counting the todos the restriction hides
+    true_index: usize,
+    /// The tree guides before the todo, like `│   └── `
+    pub guide: String,
+    pub fold: Fold,
+}
+
+impl Row {
+    /// Tree path of the todo itself
+    pub fn path(&self) -> Vec<usize> {
+        let mut path = self.tree_path.clone();
+        path.push(self.true_index);
+        path
+    }
+
+    #[inline]
+    pub fn fold_marker(&self) -> &'static str {
+        match self.fold {
+            Fold::Leaf => "  ",
+            Fold::Expanded => "▾ ",
+            Fold::Collapsed => "▸ ",
+        }
+    }
+}
+
+/// The guides of a todo, with whether each of its parents is the last of its list
+fn guide(last_stack: &[bool], is_last: bool) -> String {
+    // Todos of the root list don't have guides
+    let Some((_, parents)) = last_stack.split_first() else {
+        return String::new();
+    };
+    let mut guide = String::new();
+    for &last in parents {
+        guide.push_str(if last { "    " } else { "│   " });
+    }
+    guide.push_str(if is_last { "└── " } else { "├── " });
+    guide
+}
+
+#[inline(always)]
+fn sub_list(todo: &Todo) -> Option<&TodoList> {
+    todo.dependency.as_ref().and_then(|dep| dep.todo_list())
+}
+
+#[derive(Default)]
+pub struct Outline {
+    pub enabled: bool,
+    /// Ids of the collapsed todos, so that they stay collapsed when todos move around
+    collapsed: HashSet<String>,
+}
+
+impl Outline {
+    /// Rows of the todos the restriction lets through, without the ones under collapsed todos
+    pub fn rows(&self, todo_list: &TodoList, restriction: &Restriction) -> Vec<Row> {
+        let mut rows = vec![];
+        self.push_rows(todo_list, restriction, &mut vec![], &mut vec![], &mut rows);
+        rows
+    }
+
+    fn push_rows(
+        &self,
+        todo_list: &TodoList,
+        restriction: &Restriction,
+        tree_path: &mut Vec<usize>,
+        last_stack: &mut Vec<bool>,
+        rows: &mut Vec<Row>,
+    ) {
+        let todos: Vec<(usize, &Todo)> = todo_list
+            .todos
+            .iter()
+            .enumerate()
+            .filter(|(_, todo)| restriction(todo))
+            .collect();
+        let count = todos.len();
+        for (index, (true_index, todo)) in todos.into_iter().enumerate() {
+            let is_last = index + 1 == count;
+            let sub_list = sub_list(todo).filter(|sub_list| !sub_list.is_empty(restriction));
+            let fold = match sub_list {
+                None => Fold::Leaf,
+                Some(_) if self.collapsed.contains(todo.id()) => Fold::Collapsed,
+                Some(_) => Fold::Expanded,
+            };
+            rows.push(Row {
+                tree_path: tree_path.clone(),
+                index,
+                true_index,
+                guide: guide(last_stack, is_last),
+                fold,
+            });
+            if let (Some(sub_list), Fold::Expanded) = (sub_list, fold) {
+                tree_path.push(true_index);
+                last_stack.push(is_last);
+                self.push_rows(sub_list, restriction, tree_path, last_stack, rows);
+                tree_path.pop();
+                last_stack.pop();
+            }
+        }
+    }
+
+    #[inline]
+    pub fn expand(&mut self, id: &str) {
+        self.collapsed.remove(id);
+    }
+
+    #[inline]
+    pub fn collapse(&mut self, id: &str) {
+        self.collapsed.insert(id.to_string());
+    }
+
+    #[inline]
+    pub fn toggle(&mut self, id: &str) {
+        if !self.collapsed.remove(id) {
+            self.collapse(id);
+        }
+    }
+
+    #[inline]
+    pub fn expand_all(&mut self) {
+        self.collapsed.clear();
+    }
+
+    /// Collapses every todo of the tree with sub todos
+    pub fn collapse_all(&mut self, todo_list: &TodoList) {
+        for todo in &todo_list.todos {
+            if let Some(sub_list) = sub_list(todo) {
+                self.collapse(todo.id());
+                self.collapse_all(sub_list);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use c3::todo_app::{test_helpers::*, App};
+    use std::{fs::remove_dir_all, io};
+
+    fn texts(app: &App, outline: &Outline) -> Vec<String> {
+        outline
+            .rows(&app.todo_list, app.get_restriction())
+            .iter()
+            .map(|row| {
+                let todo = app.todo_at_path(&row.path()).unwrap();
+                format!("{}{}{}", row.guide, row.fold_marker(), todo.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rows() -> io::Result<()> {
+        let dir = dir("test-outline")?;
+        let mut app = write_test_todos(&dir)?;
+        remove_dir_all(dir)?;
+        app.append(String::from("Last one"));
+        app.select_path(&[0]);
+        app.add_dependency_traverse_down();
+        app.append(String::from("Under the first"));
+        app.go_root();
+        app.update_show_done_restriction();
+
+        let mut outline = Outline::default();
+        let expanded = texts(&app, &outline);
+        assert_eq!(
+            expanded,
+            vec![
+                "▾ Hello",
+                "└──   Under the first",
+                "  Goodbye",
+                "▾ Hello there",
+                "└── ▾ Is there anybody outthere?",
+                "    └── ▾ Just nod if you can here me",
+                "        └──   Is there anyone home",
+                "  Last one",
+            ]
+        );
+        let rows = outline.rows(&app.todo_list, app.get_restriction());
+        assert_eq!(rows[6].tree_path, vec![2, 0, 0]);
+        assert_eq!(rows[6].index, 0);
+        assert_eq!(rows[7].path(), vec![3]);
+
+        // Collapsing a todo hides everything under it
+        let id = app.todo_at_path(&rows[4].path()).unwrap().id().to_string();
+        outline.toggle(&id);
+        assert_eq!(
+            texts(&app, &outline)[3..],
+            ["▾ Hello there", "└── ▸ Is there anybody outthere?", "  Last one"]
+        );
+
+        outline.collapse_all(&app.todo_list);
+        assert_eq!(
+            texts(&app, &outline),
+            vec!["▸ Hello", "  Goodbye", "▸ Hello there", "  Last one"]
+        );
+        outline.expand_all();
+        assert_eq!(texts(&app, &outline), expanded);
+        Ok(())
+    }
+}
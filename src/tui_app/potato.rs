@@ -36,7 +36,7 @@ impl Potato {
     }
 
     #[inline]
-    pub fn get_widget(&self) -> Paragraph {
+    pub fn get_widget(&self) -> Paragraph<'_> {
         let args = vec!["+%m\n%t\n%p".to_string(), self.resolve_arg("1")];
 
         let time_str = match self.output(args) {
@@ -0,0 +1,456 @@
+//! Styles of the todos in the TUI. A theme starts from one of the built in ones, picked with
+//! `--theme`, and the `[theme]` section of the config changes its styles with style strings like
+//! `"red bold"`, `"#8ec07c on black"` or `"dim crossed-out"`.
+use c3::fileio::invalid_data;
+use c3::{date, todo_app::Todo};
+use clap::ValueEnum;
+use ratatui::prelude::*;
+use std::env;
+use std::io;
+use std::str::FromStr;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThemeName {
+    /// Colours for dark terminals
+    #[default]
+    Default,
+    /// Colours for light terminals
+    Light,
+    /// No colours, only bold, dim, underlined and such
+    Monochrome,
+}
+
+const MODIFIERS: [(Modifier, &str); 7] = [
+    (Modifier::BOLD, "bold"),
+    (Modifier::DIM, "dim"),
+    (Modifier::ITALIC, "italic"),
+    (Modifier::UNDERLINED, "underlined"),
+    (Modifier::REVERSED, "reversed"),
+    (Modifier::CROSSED_OUT, "crossed-out"),
+    (Modifier::SLOW_BLINK, "blink"),
+];
+
+/// The style of a style string, like `red bold` or `white on blue`
+fn parse_style(input: &str) -> Option<Style> {
+    let mut style = Style::new();
+    let mut words = input.split_whitespace();
+    while let Some(word) = words.next() {
+        let word = word.to_lowercase();
+        if word == "none" {
+            continue;
+        }
+        if word == "on" {
+            style = style.bg(Color::from_str(words.next()?).ok()?);
+        } else if let Some((modifier, _)) = MODIFIERS.iter().find(|(_, name)| *name == word) {
+            style = style.add_modifier(*modifier);
+        } else {
+            style = style.fg(Color::from_str(&word).ok()?);
+        }
+    }
+    Some(style)
+}
+
+fn write_style(style: Style) -> String {
+    let mut words = vec![];
+    if let Some(fg) = style.fg {
+        words.push(fg.to_string().to_lowercase());
+    }
+    if let Some(bg) = style.bg {
+        words.push(format!("on {}", bg.to_string().to_lowercase()));
+    }
+    for (modifier, name) in MODIFIERS {
+        if style.add_modifier.contains(modifier) {
+            words.push(name.to_string());
+        }
+    }
+    if words.is_empty() {
+        return String::from("none");
+    }
+    words.join(" ")
+}
+
+#[inline(always)]
+fn without_colors(style: Style) -> Style {
+    Style {
+        fg: None,
+        bg: None,
+        ..style
+    }
+}
+
+/// Whether `NO_COLOR` asks for no colours, see <https://no-color.org>
+pub fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    /// Styles of todos by their priority, 0 being the ones without any
+    priorities: [Style; 10],
+    done: Style,
+    /// Todos past their due date, and reminders past their date
+    overdue: Style,
+    /// The `>` of todos with a note
+    note: Style,
+    /// The `-` of todos with sub todos
+    sub_list: Style,
+    project: Style,
+    context: Style,
+    highlight: Style,
+    /// Todos marked or in the range being selected
+    marked: Style,
+}
+
+impl From<ThemeName> for Theme {
+    fn from(name: ThemeName) -> Self {
+        let plain = Style::new();
+        match name {
+            ThemeName::Default => Self {
+                priorities: [
+                    plain,
+                    plain.fg(Color::LightRed).add_modifier(Modifier::BOLD),
+                    plain.fg(Color::LightYellow),
+                    plain.fg(Color::LightGreen),
+                    plain.fg(Color::LightBlue),
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                ],
+                done: plain
+                    .fg(Color::DarkGray)
+                    .add_modifier(Modifier::CROSSED_OUT),
+                overdue: plain.fg(Color::Red).add_modifier(Modifier::BOLD),
+                note: plain.fg(Color::Yellow),
+                sub_list: plain.fg(Color::Blue).add_modifier(Modifier::BOLD),
+                project: plain.fg(Color::Cyan),
+                context: plain.fg(Color::Magenta),
+                highlight: plain.add_modifier(Modifier::REVERSED),
+                marked: plain
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::UNDERLINED),
+            },
+            ThemeName::Light => Self {
+                priorities: [
+                    plain,
+                    plain.fg(Color::Red).add_modifier(Modifier::BOLD),
+                    plain.fg(Color::Magenta),
+                    plain.fg(Color::Blue),
+                    plain.fg(Color::Green),
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                ],
+                done: plain.fg(Color::Gray).add_modifier(Modifier::CROSSED_OUT),
+                overdue: plain.fg(Color::Red).add_modifier(Modifier::BOLD),
+                note: plain.fg(Color::Magenta),
+                sub_list: plain.fg(Color::Blue).add_modifier(Modifier::BOLD),
+                project: plain.fg(Color::Blue),
+                context: plain.fg(Color::Magenta),
+                highlight: plain.add_modifier(Modifier::REVERSED),
+                marked: plain.bg(Color::Gray).add_modifier(Modifier::UNDERLINED),
+            },
+            ThemeName::Monochrome => Self {
+                priorities: [
+                    plain,
+                    plain.add_modifier(Modifier::BOLD),
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                    plain,
+                ],
+                done: plain.add_modifier(Modifier::DIM | Modifier::CROSSED_OUT),
+                overdue: plain.add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                note: plain.add_modifier(Modifier::ITALIC),
+                sub_list: plain.add_modifier(Modifier::BOLD),
+                project: plain.add_modifier(Modifier::UNDERLINED),
+                context: plain.add_modifier(Modifier::ITALIC),
+                highlight: plain.add_modifier(Modifier::REVERSED),
+                marked: plain.add_modifier(Modifier::UNDERLINED),
+            },
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::from(ThemeName::default())
+    }
+}
+
+impl Theme {
+    /// The built in theme with the styles given, by their names in the config, changed. Without
+    /// colours when `no_color` is set, like for `NO_COLOR`
+    pub fn new(name: ThemeName, styles: &[(String, String)], no_color: bool) -> io::Result<Self> {
+        let mut theme = Self::from(name);
+        for (name, value) in styles {
+            let style = parse_style(value)
+                .ok_or_else(|| invalid_data(format!("theme.{name} \"{value}\" isn't a style")))?;
+            *theme.style_mut(name).ok_or_else(|| {
+                invalid_data(format!("theme.{name} isn't a style of the theme"))
+            })? = style;
+        }
+        if no_color {
+            theme.priorities = theme.priorities.map(without_colors);
+            for name in Self::names() {
+                if let Some(style) = theme.style_mut(&name) {
+                    *style = without_colors(*style);
+                }
+            }
+        }
+        Ok(theme)
+    }
+
+    /// Names of the styles in the config
+    fn names() -> Vec<String> {
+        let mut names: Vec<String> = (0..10)
+            .map(|priority| format!("priority-{priority}"))
+            .collect();
+        for name in [
+            "done",
+            "overdue",
+            "note",
+            "sub-list",
+            "project",
+            "context",
+            "highlight",
+            "marked",
+        ] {
+            names.push(name.to_string());
+        }
+        names
+    }
+
+    fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        if let Some(priority) = name.strip_prefix("priority-") {
+            let priority: usize = priority.parse().ok()?;
+            return self.priorities.get_mut(priority);
+        }
+        match name {
+            "done" => Some(&mut self.done),
+            "overdue" => Some(&mut self.overdue),
+            "note" => Some(&mut self.note),
+            "sub-list" => Some(&mut self.sub_list),
+            "project" => Some(&mut self.project),
+            "context" => Some(&mut self.context),
+            "highlight" => Some(&mut self.highlight),
+            "marked" => Some(&mut self.marked),
+            _ => None,
+        }
+    }
+
+    /// The `[theme]` section of the config that makes this theme
+    pub fn config(&self) -> String {
+        let mut theme = self.clone();
+        let mut lines = vec![String::from("[theme]")];
+        for name in Self::names() {
+            if let Some(style) = theme.style_mut(&name) {
+                lines.push(format!("{name} = \"{}\"", write_style(*style)));
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+
+    #[inline]
+    pub fn highlight(&self) -> Style {
+        self.highlight
+    }
+
+    /// The line of a todo, styled as marked
+    #[inline]
+    pub fn marked(&self, mut line: Line<'static>) -> Line<'static> {
+        line.patch_style(self.marked);
+        line
+    }
+
+    /// The style of the whole line of a todo
+    fn todo_style(&self, todo: &Todo) -> Style {
+        let priority = usize::from(todo.priority()).min(9);
+        let mut style = self.priorities[priority];
+        if todo.done() {
+            return style.patch(self.done);
+        }
+        let reminder_passed = todo.schedule.as_ref().is_some_and(|schedule| {
+            schedule.is_reminder() && schedule.date().is_some_and(|date| date < date::current())
+        });
+        if todo.is_overdue() || reminder_passed {
+            style = style.patch(self.overdue);
+        }
+        style
+    }
+
+    /// The displayed todo in spans, with the `>` or `-` of its dependency and its `+project` and
+    /// `@context` tags in their own styles
+    pub fn todo_line(&self, todo: &Todo, display: String) -> Line<'static> {
+        let style = self.todo_style(todo);
+        let mut spans: Vec<Span> = vec![];
+        let mut rest = display.as_str();
+        let marker = todo.dependency.as_ref().map(|dep| {
+            let style = if dep.note().is_some() {
+                self.note
+            } else {
+                self.sub_list
+            };
+            (format!("{}{} ", todo.priority(), dep.display()), style)
+        });
+        if let Some((marker, marker_style)) = marker {
+            if let Some(position) = rest.find(&marker) {
+                // The priority and the marker, without the space after it
+                let end = position + marker.len() - 1;
+                let priority_end = end - 1;
+                spans.push(Span::styled(rest[..priority_end].to_string(), style));
+                spans.push(Span::styled(
+                    rest[priority_end..end].to_string(),
+                    style.patch(marker_style),
+                ));
+                rest = &rest[end..];
+            }
+        }
+        let mut plain = String::new();
+        for chunk in rest.split_inclusive(' ') {
+            let word = chunk.trim_end();
+            if Todo::is_tag(word) {
+                if !plain.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut plain), style));
+                }
+                let tag_style = if word.starts_with('+') {
+                    self.project
+                } else {
+                    self.context
+                };
+                spans.push(Span::styled(word.to_string(), style.patch(tag_style)));
+                plain.push_str(&chunk[word.len()..]);
+            } else {
+                plain.push_str(chunk);
+            }
+        }
+        if !plain.is_empty() {
+            spans.push(Span::styled(plain, style));
+        }
+        Line::from(spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_styles() {
+        let style = parse_style("#8ec07c on dark-gray bold crossed-out");
+        assert_eq!(
+            style,
+            Some(
+                Style::new()
+                    .fg(Color::Rgb(0x8e, 0xc0, 0x7c))
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD | Modifier::CROSSED_OUT)
+            )
+        );
+        assert_eq!(parse_style("none"), Some(Style::new()));
+        assert_eq!(parse_style("bold on"), None);
+        assert_eq!(parse_style("reddish"), None);
+        for input in ["red bold", "#8ec07c on darkgray dim", "none", "reversed"] {
+            let style = parse_style(input).unwrap();
+            assert_eq!(parse_style(&write_style(style)), Some(style));
+        }
+    }
+
+    #[test]
+    fn test_theme() -> io::Result<()> {
+        let styles = [
+            (String::from("done"), String::from("dim")),
+            (String::from("priority-1"), String::from("red on white")),
+        ];
+        let theme = Theme::new(ThemeName::Default, &styles, false)?;
+        assert_eq!(theme.done, Style::new().add_modifier(Modifier::DIM));
+        assert_eq!(
+            theme.priorities[1],
+            Style::new().fg(Color::Red).bg(Color::White)
+        );
+        assert!(theme.config().contains("\npriority-1 = \"red on white\"\n"));
+        assert_eq!(
+            Theme::new(ThemeName::Default, &theme_config(&theme), false)?,
+            theme
+        );
+
+        let theme = Theme::new(ThemeName::Default, &styles, true)?;
+        assert_eq!(theme.priorities[1], Style::new());
+        assert_eq!(theme.project, Style::new());
+        assert_eq!(
+            theme.highlight,
+            Style::new().add_modifier(Modifier::REVERSED)
+        );
+
+        let error = |name: &str, value: &str| {
+            Theme::new(
+                ThemeName::Default,
+                &[(name.to_string(), value.to_string())],
+                false,
+            )
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(
+            error("done", "gray-ish"),
+            "theme.done \"gray-ish\" isn't a style"
+        );
+        assert_eq!(
+            error("priority-10", "red"),
+            "theme.priority-10 isn't a style of the theme"
+        );
+        Ok(())
+    }
+
+    /// The styles of the `[theme]` section the theme writes
+    fn theme_config(theme: &Theme) -> Vec<(String, String)> {
+        theme
+            .config()
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let (name, value) = line.split_once(" = ")?;
+                Some((name.to_string(), value.trim_matches('"').to_string()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_todo_line() {
+        let theme = Theme::default();
+        let mut todo: Todo = "[1] Call mom +family @phone".parse().unwrap();
+        let line = theme.todo_line(&todo, todo.to_string());
+        let texts: Vec<&str> = line
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(texts, vec!["1. Call mom ", "+family", " ", "@phone"]);
+        assert_eq!(line.spans[0].style, theme.priorities[1]);
+        assert_eq!(
+            line.spans[1].style,
+            theme.priorities[1].patch(theme.project)
+        );
+
+        todo.set_note(String::from("Some note")).unwrap();
+        let line = theme.todo_line(&todo, format!("[ ] {todo}"));
+        let texts: Vec<&str> = line
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(texts[..3], ["[ ] 1", ">", " Call mom "]);
+        assert_eq!(line.spans[1].style, theme.priorities[1].patch(theme.note));
+
+        todo.toggle_done();
+        let line = theme.todo_line(&todo, todo.to_string());
+        assert_eq!(line.spans[0].style, theme.priorities[1].patch(theme.done));
+    }
+}
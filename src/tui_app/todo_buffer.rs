@@ -1,24 +1,25 @@
 use c3::todo_app::Todo;
 
+/// Yanked or cut todos, with their sub todos
 #[derive(Default)]
 pub struct TodoBuffer {
-    buffer: Option<Todo>,
+    buffer: Vec<Todo>,
 }
 
 impl TodoBuffer {
     #[inline]
-    pub fn yank<T>(&mut self, todo: T) 
-    where 
-    T: Into<Option<Todo>>,
-    { 
-        if let Some(todo) = todo.into() {
-            self.buffer = Some(todo);
+    pub fn yank<T>(&mut self, todos: T)
+    where
+    T: IntoIterator<Item = Todo>,
+    {
+        let todos: Vec<Todo> = todos.into_iter().collect();
+        if !todos.is_empty() {
+            self.buffer = todos;
         }
     }
 
     #[inline]
-    pub fn get(&self) -> Option<Todo> {
+    pub fn get(&self) -> Vec<Todo> {
         self.buffer.clone()
     }
 }
-